@@ -5,8 +5,8 @@
 
 use std::result::Result::Ok;
 
-use anyhow::Result;
-use clap::{Args, Parser, Subcommand};
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use log::LevelFilter;
 use serde::{Deserialize, Serialize};
 
@@ -23,30 +23,160 @@ struct Cli {
     #[arg(short = 'v', long, action = clap::ArgAction::Count, global = true)]
     verbose: u8,
 
+    /// Include the event metadata (event type, source, certificate, event data) in the output
+    #[arg(long, global = true)]
+    metadata: bool,
+
+    /// Defaults to computing the PCRs of the running host
     #[command(subcommand)]
-    command: Command,
+    command: Option<Command>,
 }
 
 #[derive(Args, Debug)]
-#[group(required = true, multiple = false)]
+#[group(id = "secureboot_variables", multiple = false)]
 struct SecureBootVarStores {
     #[arg(long, help = "Path to the directory storing EFIVar files")]
     efivars: Option<String>,
+    #[arg(
+        long = "ovmf-vars",
+        help = "Path to an OVMF_VARS.fd variable store, for virtual machines"
+    )]
+    ovmf_vars: Option<String>,
+    #[arg(
+        long = "vars-json",
+        help = "Path to a virt-firmware JSON variable store, as written by virt-fw-vars --output-json"
+    )]
+    vars_json: Option<String>,
+    #[arg(
+        long = "aws-uefi-data",
+        help = "Path to the base64 encoded AWS EC2 uefi-data blob of an AMI"
+    )]
+    aws_uefi_data: Option<String>,
+}
+
+impl SecureBootVarStores {
+    /// Loads the Secure Boot variables of the store, with the signed
+    /// variable updates applied
+    fn loader(&self, updates: &AuthUpdates) -> Result<uefi::secureboot::VarStoreLoader> {
+        use uefi::auth::UpdatedVarsLoader;
+        use uefi::secureboot::VarStoreLoader;
+        let updates = updates.load()?;
+        Ok(
+            match (&self.ovmf_vars, &self.vars_json, &self.aws_uefi_data) {
+                (Some(path), _, _) => VarStoreLoader::from_loader(UpdatedVarsLoader::new(
+                    uefi::ovmf::load(path)?,
+                    updates,
+                )),
+                (_, Some(path), _) => VarStoreLoader::from_loader(UpdatedVarsLoader::new(
+                    uefi::json::load(path)?,
+                    updates,
+                )),
+                (_, _, Some(path)) => VarStoreLoader::from_loader(UpdatedVarsLoader::new(
+                    uefi::aws::load(path)?,
+                    updates,
+                )),
+                _ => VarStoreLoader::from_loader(UpdatedVarsLoader::new(
+                    uefi::efivars::EFIVarsLoader::new(
+                        self.efivars.as_deref().context("--efivars is required")?,
+                        uefi::efivars::SECURE_BOOT_ATTR_HEADER_LENGTH,
+                    ),
+                    updates,
+                )),
+            },
+        )
+    }
+
+    /// Computes PCR 7, with the signed variable updates applied to the
+    /// variables of the store
+    fn compute_pcr7(
+        &self,
+        rfs: &rootfs::RootFSTree,
+        secureboot_enabled: bool,
+        updates: &AuthUpdates,
+    ) -> Result<Pcr> {
+        Ok(compute_pcr7_with(
+            rfs.fs(),
+            self.loader(updates)?,
+            rfs.esp(),
+            secureboot_enabled,
+        ))
+    }
+
+    /// Computes PCR 14 from a MokList*RT directory, or from the JSON
+    /// variable store when none is given
+    fn compute_pcr14(&self, mok_variables: Option<&str>) -> Result<Pcr> {
+        match (mok_variables, &self.vars_json) {
+            (Some(path), _) => compute_pcr14(path),
+            (None, Some(path)) => {
+                let fs = uefi::json::load(path)?.mok_variables();
+                compute_pcr14_in(&fs, "/")
+            }
+            (None, None) => anyhow::bail!("--mok-variables is required"),
+        }
+    }
+}
+
+/// Parses a `VAR=PATH` or `VAR+=PATH` update
+fn parse_auth_update(value: &str) -> Result<(String, uefi::auth::UpdateMode, String), String> {
+    let (var, path) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected VAR=PATH or VAR+=PATH, got {value}"))?;
+    Ok(match var.strip_suffix('+') {
+        Some(var) => (var.into(), uefi::auth::UpdateMode::Append, path.into()),
+        None => (var.into(), uefi::auth::UpdateMode::Replace, path.into()),
+    })
+}
+
+/// Parses a PCR bank whose values are computed, that is only sha256
+fn parse_computed_bank(value: &str) -> Result<tcg::HashAlgorithm, String> {
+    match value.parse()? {
+        tcg::HashAlgorithm::Sha256 => Ok(tcg::HashAlgorithm::Sha256),
+        other => Err(format!(
+            "PCR values are only computed for the sha256 bank, not {other}"
+        )),
+    }
+}
+
+#[derive(Args, Debug)]
+struct AuthUpdates {
+    #[arg(
+        long = "auth-update",
+        value_name = "VAR=PATH",
+        value_parser = parse_auth_update,
+        help = "Signed .auth update of PK, KEK, db or dbx to apply before computing PCR 7. \
+                VAR=PATH replaces the variable, VAR+=PATH appends to it. Applied in order"
+    )]
+    auth_update: Vec<(String, uefi::auth::UpdateMode, String)>,
+}
+
+impl AuthUpdates {
+    fn load(&self) -> Result<Vec<uefi::auth::AuthUpdate>> {
+        self.auth_update
+            .iter()
+            .map(|(var, mode, path)| {
+                uefi::auth::AuthUpdate::new(var, *mode, &read_input(path)?)
+                    .with_context(|| format!("Failed to load {path}"))
+            })
+            .collect()
+    }
 }
 
 #[derive(Subcommand, Debug)]
 enum Command {
     /// Compute all possible PCR values from the binaries available in the current environment
+    #[command(mut_group("secureboot_variables", |group| group.required(true)))]
     All {
         #[arg(
             long,
             short,
             default_value = "/",
-            help = "Path to the target container image root filesystem"
+            help = "Path to the target container image root filesystem or tarball, OCI archive, OCI image layout, containers-storage: image, live ISO or raw or qcow2 disk image"
         )]
         rootfs: String,
         #[command(flatten)]
         secureboot_variables: SecureBootVarStores,
+        #[command(flatten)]
+        auth_updates: AuthUpdates,
         #[arg(
             long,
             default_value_t = false,
@@ -61,10 +191,10 @@ enum Command {
         no_secureboot: bool,
         #[arg(
             long = "mok-variables",
-            required = true,
+            required_unless_present = "vars_json",
             help = "Path to directory storing MokListRT, MokListTrustedRT and MokListXRT"
         )]
-        mok_variables: String,
+        mok_variables: Option<String>,
     },
     /// Compute PCR 4
     Pcr4 {
@@ -72,7 +202,7 @@ enum Command {
             long,
             short,
             default_value = "/",
-            help = "Path to the target container image root filesystem"
+            help = "Path to the target container image root filesystem or tarball, OCI archive, OCI image layout, containers-storage: image, live ISO or raw or qcow2 disk image"
         )]
         rootfs: String,
         #[arg(
@@ -89,16 +219,19 @@ enum Command {
         no_secureboot: bool,
     },
     /// Compute PCR 7
+    #[command(mut_group("secureboot_variables", |group| group.required(true)))]
     Pcr7 {
         #[arg(
             long,
             short,
             default_value = "/",
-            help = "Path to the target container image root filesystem"
+            help = "Path to the target container image root filesystem or tarball, OCI archive, OCI image layout, containers-storage: image, live ISO or raw or qcow2 disk image"
         )]
         rootfs: String,
         #[command(flatten)]
         secureboot_variables: SecureBootVarStores,
+        #[command(flatten)]
+        auth_updates: AuthUpdates,
         #[arg(
             long = "secureboot-disabled",
             default_value_t = false,
@@ -106,6 +239,12 @@ enum Command {
         )]
         no_secureboot: bool,
     },
+    /// Compute PCR 9 of a live ISO, from the GRUB configuration and the
+    /// kernel and initrds it boots
+    Pcr9 {
+        #[arg(long, short, help = "Path to the live ISO")]
+        rootfs: String,
+    },
     /// Compute PCR 11
     Pcr11 {
         /// Path to a UKI
@@ -120,11 +259,506 @@ enum Command {
         )]
         mok_variables: String,
     },
+    /// Identify which component of an image produced event digests
+    Whatis {
+        #[arg(
+            long,
+            short,
+            default_value = "/",
+            help = "Path to the target container image root filesystem or tarball, OCI archive, OCI image layout, containers-storage: image, live ISO or raw or qcow2 disk image"
+        )]
+        rootfs: String,
+        #[command(flatten)]
+        secureboot_variables: Option<SecureBootVarStores>,
+        #[command(flatten)]
+        auth_updates: AuthUpdates,
+        #[arg(
+            long = "mok-variables",
+            help = "Path to directory storing MokListRT, MokListTrustedRT and MokListXRT"
+        )]
+        mok_variables: Option<String>,
+        #[arg(
+            long,
+            required_unless_present = "event_log",
+            help = "Hex encoded SHA-256 event digest to identify"
+        )]
+        digest: Vec<String>,
+        #[arg(
+            long = "event-log",
+            conflicts_with = "digest",
+            help = "Path to a TCG Canonical Event Log whose events should be identified"
+        )]
+        event_log: Option<String>,
+        #[arg(long, value_enum, default_value_t = CelEncoding::Json)]
+        encoding: CelEncoding,
+    },
+    /// Compute the TPM2 PolicyPCR digest of a PCR selection
+    Policy {
+        /// Path to a compute-pcrs output file, or "-" to read it from stdin
+        input: String,
+        #[arg(
+            long,
+            required = true,
+            value_delimiter = ',',
+            help = "Comma separated list of PCRs to select"
+        )]
+        pcrs: Vec<u64>,
+        #[arg(long, default_value = "sha256", help = "PCR bank of the selection")]
+        bank: tcg::HashAlgorithm,
+        #[arg(
+            long = "hash-alg",
+            default_value = "sha256",
+            help = "Hash algorithm of the policy session"
+        )]
+        hash_alg: tcg::HashAlgorithm,
+        #[arg(long, help = "Hex encoded policy digest to chain the PolicyPCR onto")]
+        previous: Option<String>,
+    },
+    /// Compute the expected TPMS_QUOTE_INFO of a quote over a PCR selection
+    Quote {
+        /// Path to a compute-pcrs output file, or "-" to read it from stdin
+        input: String,
+        #[arg(
+            long,
+            required = true,
+            value_delimiter = ',',
+            help = "Comma separated list of PCRs to select"
+        )]
+        pcrs: Vec<u64>,
+        #[arg(long, default_value = "sha256", help = "PCR bank of the selection")]
+        bank: tcg::HashAlgorithm,
+        #[arg(
+            long = "hash-alg",
+            help = "Hash algorithm of the quote signing scheme. Defaults to the bank algorithm"
+        )]
+        hash_alg: Option<tcg::HashAlgorithm>,
+    },
+    /// Print the configuration of a Clevis tpm2 pin bound to a PCR selection
+    Clevis {
+        /// Path to a compute-pcrs output file, or "-" to read it from stdin
+        input: String,
+        #[arg(
+            long,
+            required = true,
+            value_delimiter = ',',
+            help = "Comma separated list of PCRs to bind to"
+        )]
+        pcrs: Vec<u64>,
+        #[arg(long, default_value = "sha256", help = "PCR bank to bind to")]
+        bank: tcg::HashAlgorithm,
+    },
+    /// Print Trustee RVPS reference values for the computed PCRs
+    Rvps {
+        /// Paths to compute-pcrs output files, or "-" to read one from stdin.
+        /// Every value found for a PCR is an acceptable reference value.
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        #[arg(
+            long,
+            default_value = "tpm",
+            help = "Trustee verifier the reference values are named for (tpm or aztpm)"
+        )]
+        verifier: rvps::Verifier,
+        #[arg(
+            long = "validity-days",
+            default_value_t = 365,
+            help = "Number of days until the reference values expire"
+        )]
+        validity_days: i64,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Wrap the reference values in an RVPS sample registration message"
+        )]
+        message: bool,
+    },
+    /// Write an IETF CoRIM holding the reference values of the computed PCRs
+    Corim {
+        /// Path to a compute-pcrs output file, or "-" to read it from stdin
+        input: String,
+        #[arg(
+            long,
+            short,
+            default_value = "/",
+            help = "Path to the target container image root filesystem or tarball, OCI archive, OCI image layout, containers-storage: image, live ISO or raw or qcow2 disk image"
+        )]
+        rootfs: String,
+        #[arg(
+            long,
+            default_value = "x86_64-uefi",
+            help = "Platform the image boots on, part of the environment class"
+        )]
+        platform: String,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Also include the digest of every event as a reference value"
+        )]
+        events: bool,
+        #[arg(
+            long = "private-key",
+            help = "Path to a PEM encoded private key to sign the CoRIM with"
+        )]
+        private_key: Option<String>,
+        #[arg(
+            long = "signer-name",
+            default_value = "compute-pcrs",
+            help = "Signer name recorded in the signed CoRIM metadata"
+        )]
+        signer_name: String,
+        #[arg(long, short, help = "Write the CoRIM to a file instead of stdout")]
+        output: Option<String>,
+    },
+    /// Sign the computed PCRs as an in-toto statement in a DSSE envelope
+    Attest {
+        /// Paths to compute-pcrs output files, or "-" to read one from stdin
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        #[arg(
+            long,
+            help = "Container image the PCRs were computed from, as name@sha256:<digest>. \
+                    Defaults to using the input files as subjects"
+        )]
+        image: Option<String>,
+        #[arg(long = "private-key", help = "Path to the PEM encoded private key")]
+        private_key: String,
+        #[arg(long, short, help = "Write the envelope to a file instead of stdout")]
+        output: Option<String>,
+    },
+    /// Verify a DSSE envelope written by attest and print its statement
+    Verify {
+        /// Path to the envelope, or "-" to read it from stdin
+        envelope: String,
+        #[arg(long = "public-key", help = "Path to the PEM encoded public key")]
+        public_key: String,
+    },
+    /// Compare two compute-pcrs outputs or two root filesystems event by event,
+    /// attributing PCR changes to boot components
+    Diff {
+        /// Old compute-pcrs output file or target container image root filesystem
+        old: String,
+        /// New compute-pcrs output file or target container image root filesystem
+        new: String,
+        #[command(flatten)]
+        secureboot_variables: Option<SecureBootVarStores>,
+        #[command(flatten)]
+        auth_updates: AuthUpdates,
+        #[arg(
+            long = "mok-variables",
+            help = "Path to directory storing MokListRT, MokListTrustedRT and MokListXRT, \
+                    to compare root filesystems"
+        )]
+        mok_variables: Option<String>,
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Indicates that the linux image is an UKI image (e.g. is not vmlinuz))"
+        )]
+        uki: bool,
+        #[arg(
+            long = "secureboot-disabled",
+            default_value_t = false,
+            help = "Compute PCRs as if secure boot was disabled in the system"
+        )]
+        no_secureboot: bool,
+    },
+    /// Print the GUID partition table of a raw or qcow2 disk image, with the
+    /// EV_EFI_GPT_EVENT firmwares measure into PCR 5 when booting from it
+    Gpt {
+        /// Path to the disk image
+        disk: String,
+    },
+    /// Compute the PCRs of the running host, from its firmware variables,
+    /// booted kernel and mounted ESP
+    Host {
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Compare the computed PCRs to the values of the TPM, failing on mismatches"
+        )]
+        check: bool,
+    },
+    /// Print the Keylime measured boot reference state of an image
+    #[command(mut_group("secureboot_variables", |group| group.required(true)))]
+    Keylime {
+        #[arg(
+            long,
+            short,
+            default_value = "/",
+            help = "Path to the target container image root filesystem or tarball, OCI archive, OCI image layout, containers-storage: image, live ISO or raw or qcow2 disk image"
+        )]
+        rootfs: String,
+        #[arg(
+            long,
+            required = true,
+            help = "Hex encoded SHA-256 digest of the EV_S_CRTM_VERSION event of PCR 0, from the event log of the target platform"
+        )]
+        scrtm: String,
+        #[arg(
+            long = "platform-firmware",
+            help = "Hex encoded SHA-256 digest of an EV_EFI_PLATFORM_FIRMWARE_BLOB event of PCR 0, from the event log of the target platform. Can be repeated"
+        )]
+        platform_firmware: Vec<String>,
+        #[command(flatten)]
+        secureboot_variables: SecureBootVarStores,
+        #[command(flatten)]
+        auth_updates: AuthUpdates,
+        #[arg(
+            long = "mok-variables",
+            required = true,
+            help = "Path to directory storing MokListRT, MokListTrustedRT and MokListXRT"
+        )]
+        mok_variables: String,
+        #[arg(
+            long = "secureboot-disabled",
+            default_value_t = false,
+            help = "Generate the reference state as if secure boot was disabled in the system"
+        )]
+        no_secureboot: bool,
+        #[arg(
+            long = "kernel-cmdline",
+            default_value = ".*",
+            help = "Regular expression the kernel command line must match"
+        )]
+        kernel_cmdline: String,
+    },
+    /// Write a systemd-pcrlock .pcrlock file for each measured boot component
+    Pcrlock {
+        /// Path to a compute-pcrs output file, or "-" to read it from stdin
+        input: String,
+        #[arg(
+            long = "output-dir",
+            short,
+            default_value = ".",
+            help = "Directory to write the .pcrlock files to"
+        )]
+        output_dir: String,
+    },
+    /// Write a tpm2_pcrextend script reproducing the computed PCRs on a fresh
+    /// (software) TPM, and the pcr.dat file tpm2_pcrread -o produces for them
+    Swtpm {
+        /// Path to a compute-pcrs output file, or "-" to read it from stdin
+        input: String,
+        #[arg(
+            long = "output-dir",
+            short,
+            default_value = ".",
+            help = "Directory to write the script and pcr.dat to"
+        )]
+        output_dir: String,
+    },
+    /// Sign and verify systemd .pcrsig PCR 11 policies
+    Pcrsig {
+        #[command(subcommand)]
+        command: PcrsigCommand,
+    },
+    /// Read and write TCG Canonical Event Logs
+    Cel {
+        #[command(subcommand)]
+        command: CelCommand,
+    },
+    /// Build EFI variable directories, as read by --efivars
+    Efivars {
+        #[command(subcommand)]
+        command: EfivarsCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum EfivarsCommand {
+    /// Assemble the PK, KEK, db and dbx variables from certificates and
+    /// SHA-256 hashes, in the Name-GUID layout of efivarfs
+    Create {
+        /// Directory to write the variables to
+        output_dir: String,
+        #[arg(long, help = "Platform key certificate, PEM or DER encoded")]
+        pk: Option<String>,
+        #[arg(long, help = "Key exchange key certificates, PEM or DER encoded")]
+        kek: Vec<String>,
+        #[arg(
+            long,
+            help = "Allowed signature database certificates, PEM or DER encoded"
+        )]
+        db: Vec<String>,
+        #[arg(long = "db-hash", help = "Hex encoded SHA-256 hashes allowed by db")]
+        db_hash: Vec<String>,
+        #[arg(
+            long,
+            help = "Forbidden signature database certificates, PEM or DER encoded"
+        )]
+        dbx: Vec<String>,
+        #[arg(
+            long = "dbx-hash",
+            help = "Hex encoded SHA-256 hashes forbidden by dbx"
+        )]
+        dbx_hash: Vec<String>,
+        #[arg(
+            long,
+            default_value_t = uuid::Uuid::nil(),
+            help = "Owner GUID of the signatures"
+        )]
+        owner: uuid::Uuid,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum CelEncoding {
+    Json,
+    Cbor,
+}
+
+#[derive(Subcommand, Debug)]
+enum CelCommand {
+    /// Export the events of computed PCRs as a predicted event log
+    Export {
+        /// Path to a compute-pcrs output file, or "-" to read it from stdin
+        input: String,
+        #[arg(long, value_enum, default_value_t = CelEncoding::Json)]
+        encoding: CelEncoding,
+        #[arg(long, short, help = "Write the event log to a file instead of stdout")]
+        output: Option<String>,
+    },
+    /// Replay an event log and print the resulting PCR values
+    Replay {
+        /// Path to the event log, or "-" to read it from stdin
+        log: String,
+        #[arg(long, value_enum, default_value_t = CelEncoding::Json)]
+        encoding: CelEncoding,
+        #[arg(
+            long,
+            value_enum,
+            help = "Write the event log with the given encoding to stdout instead of replaying it"
+        )]
+        reencode: Option<CelEncoding>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum PcrsigCommand {
+    /// Sign the PCR 11 policy of every boot phase, like systemd-measure sign
+    Sign {
+        /// Path to a UKI
+        uki: String,
+        #[arg(long = "private-key", help = "Path to the PEM encoded private key")]
+        private_key: String,
+        #[arg(
+            long,
+            default_values_t = pcrsig::DEFAULT_PHASES.map(String::from),
+            help = "Boot phase to sign the policy for, can be repeated"
+        )]
+        phase: Vec<String>,
+        #[arg(
+            long,
+            default_value = "sha256",
+            value_parser = parse_computed_bank,
+            help = "PCR bank to sign the policy for. Only sha256 is supported"
+        )]
+        bank: Vec<tcg::HashAlgorithm>,
+    },
+    /// Verify the .pcrsig section embedded in a UKI against recomputed values
+    Verify {
+        /// Path to a UKI
+        uki: String,
+        #[arg(
+            long = "public-key",
+            help = "Path to the PEM encoded public key. Defaults to the .pcrpkey section of the UKI"
+        )]
+        public_key: Option<String>,
+        #[arg(
+            long,
+            default_values_t = pcrsig::DEFAULT_PHASES.map(String::from),
+            help = "Boot phase to verify the policy of, can be repeated"
+        )]
+        phase: Vec<String>,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
 struct Output {
     pcrs: Vec<Pcr>,
+    /// Digest of the manifest of the image the PCRs were computed from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    manifest_digest: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Identification<'a> {
+    digest: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pcr: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event_type: Option<String>,
+    matches: Vec<&'a whatis::Candidate>,
+}
+
+fn read_input(path: &str) -> Result<Vec<u8>> {
+    if path == "-" {
+        let mut data = vec![];
+        std::io::Read::read_to_end(&mut std::io::stdin(), &mut data)?;
+        return Ok(data);
+    }
+    std::fs::read(path).with_context(|| format!("Failed to read {path}"))
+}
+
+fn write_output(path: Option<&str>, data: &[u8]) -> Result<()> {
+    match path {
+        Some(path) => std::fs::write(path, data).with_context(|| format!("Failed to write {path}")),
+        None => Ok(std::io::Write::write_all(&mut std::io::stdout(), data)?),
+    }
+}
+
+/// Loads the PCRs from the output of any of the compute commands, which is
+/// either a full output or a single PCR.
+fn parse_pcrs(data: &[u8]) -> Result<Vec<Pcr>> {
+    if let Ok(output) = serde_json::from_slice::<Output>(data) {
+        return Ok(output.pcrs);
+    }
+    let pcr = serde_json::from_slice::<Pcr>(data).context("Unknown compute-pcrs output format")?;
+    Ok(vec![pcr])
+}
+
+fn read_pcrs(path: &str) -> Result<Vec<Pcr>> {
+    parse_pcrs(&read_input(path)?)
+}
+
+/// Returns the signature database entries of certificate files and hex
+/// encoded SHA-256 hashes
+fn signatures(
+    certs: &[String],
+    hashes: &[String],
+    owner: uuid::Uuid,
+) -> Result<Vec<certs::SignatureData>> {
+    let mut signatures = vec![];
+    for path in certs {
+        let ders = certs::certs_to_der(&read_input(path)?)
+            .with_context(|| format!("Failed to parse certificates of {path}"))?;
+        signatures.extend(
+            ders.iter()
+                .map(|der| certs::SignatureData::x509(owner, der)),
+        );
+    }
+    for hash in hashes {
+        let hash: [u8; 32] = hex::decode(hash)
+            .ok()
+            .and_then(|h| h.try_into().ok())
+            .with_context(|| format!("Invalid SHA-256 hash {hash}"))?;
+        signatures.push(certs::SignatureData::sha256(owner, &hash));
+    }
+    Ok(signatures)
+}
+
+fn encode_cel(records: &[cel::Record], encoding: CelEncoding) -> Result<Vec<u8>> {
+    match encoding {
+        CelEncoding::Json => Ok(format!("{}\n", cel::to_json(records)?).into_bytes()),
+        CelEncoding::Cbor => cel::to_cbor(records),
+    }
+}
+
+fn decode_cel(data: &[u8], encoding: CelEncoding) -> Result<Vec<cel::Record>> {
+    match encoding {
+        CelEncoding::Json => cel::from_json(std::str::from_utf8(data)?),
+        CelEncoding::Cbor => cel::from_cbor(data),
+    }
 }
 
 fn main() -> Result<()> {
@@ -142,28 +776,46 @@ fn main() -> Result<()> {
         .format_timestamp(None)
         .init();
 
-    match &cli.command {
+    // Event metadata is only printed on demand, to keep the default output
+    // format stable
+    let strip = |pcr: Pcr| {
+        if cli.metadata {
+            pcr
+        } else {
+            pcr.without_metadata()
+        }
+    };
+
+    let command = cli.command.unwrap_or(Command::Host { check: false });
+    match &command {
         Command::All {
             rootfs,
             secureboot_variables,
+            auth_updates,
             uki,
             no_secureboot,
             mok_variables,
         } => {
-            let rfs = rootfs::RootFSTree::new(rootfs).unwrap();
-            let pcrs = vec![
-                compute_pcr4(rfs.vmlinuz(), rfs.esp(), *uki, !no_secureboot),
-                compute_pcr7(
-                    secureboot_variables.efivars.as_deref(),
-                    rfs.esp(),
-                    !no_secureboot,
-                ),
+            let rfs = rootfs::RootFSTree::open(rootfs)?;
+            let mut pcrs: Vec<Pcr> = vec![
+                compute_pcr4_in(rfs.fs(), rfs.vmlinuz(), rfs.esp(), *uki, !no_secureboot),
+                secureboot_variables.compute_pcr7(&rfs, !no_secureboot, auth_updates)?,
                 /* compute_pcr11(), */
-                compute_pcr14(mok_variables),
+                secureboot_variables.compute_pcr14(mok_variables.as_deref())?,
             ];
+            // GRUB only boots the kernel directly from its ESP configuration
+            // on live ISOs
+            if rfs.is_live_iso() {
+                pcrs.insert(2, compute_pcr9_in(rfs.fs(), rfs.esp(), rfs.root()));
+            }
+            let pcrs: Vec<Pcr> = pcrs.into_iter().map(strip).collect();
             println!(
                 "{}",
-                serde_json::to_string_pretty(&Output { pcrs }).unwrap()
+                serde_json::to_string_pretty(&Output {
+                    pcrs,
+                    manifest_digest: rfs.manifest_digest().map(String::from),
+                })
+                .unwrap()
             );
             Ok(())
         }
@@ -172,34 +824,455 @@ fn main() -> Result<()> {
             uki,
             no_secureboot,
         } => {
-            let rfs = rootfs::RootFSTree::new(rootfs).unwrap();
-            let pcr = compute_pcr4(rfs.vmlinuz(), rfs.esp(), *uki, !no_secureboot);
+            let rfs = rootfs::RootFSTree::open(rootfs)?;
+            let pcr = strip(compute_pcr4_in(
+                rfs.fs(),
+                rfs.vmlinuz(),
+                rfs.esp(),
+                *uki,
+                !no_secureboot,
+            ));
             println!("{}", serde_json::to_string_pretty(&pcr).unwrap());
             Ok(())
         }
         Command::Pcr7 {
             rootfs,
             secureboot_variables,
+            auth_updates,
             no_secureboot,
         } => {
-            let rfs = rootfs::RootFSTree::new(rootfs).unwrap();
-            let pcr = compute_pcr7(
-                secureboot_variables.efivars.as_deref(),
-                rfs.esp(),
-                !no_secureboot,
-            );
+            let rfs = rootfs::RootFSTree::open(rootfs)?;
+            let pcr =
+                strip(secureboot_variables.compute_pcr7(&rfs, !no_secureboot, auth_updates)?);
+            println!("{}", serde_json::to_string_pretty(&pcr).unwrap());
+            Ok(())
+        }
+        Command::Pcr9 { rootfs } => {
+            let rfs = rootfs::RootFSTree::open(rootfs)?;
+            if !rfs.is_live_iso() {
+                anyhow::bail!("PCR 9 is only computed for live ISOs, {rootfs} is not one");
+            }
+            let pcr = strip(compute_pcr9_in(rfs.fs(), rfs.esp(), rfs.root()));
             println!("{}", serde_json::to_string_pretty(&pcr).unwrap());
             Ok(())
         }
         Command::Pcr11 { uki } => {
-            let pcr = compute_pcr11(uki);
+            let pcr = strip(compute_pcr11(uki));
             println!("{}", serde_json::to_string_pretty(&pcr).unwrap());
             Ok(())
         }
         Command::Pcr14 { mok_variables } => {
-            let pcr = compute_pcr14(mok_variables);
+            let pcr = strip(compute_pcr14(mok_variables)?);
             println!("{}", serde_json::to_string_pretty(&pcr).unwrap());
             Ok(())
         }
+        Command::Whatis {
+            rootfs,
+            secureboot_variables,
+            auth_updates,
+            mok_variables,
+            digest,
+            event_log,
+            encoding,
+        } => {
+            let rfs = rootfs::RootFSTree::open(rootfs)?;
+            let mok_variables = mok_variables
+                .as_deref()
+                .map(|path| (vfs::HostFs::host() as &dyn vfs::Filesystem, path));
+            let secureboot_vars = secureboot_variables
+                .as_ref()
+                .map(|stores| stores.loader(auth_updates))
+                .transpose()?;
+            let candidates = whatis::collect_candidates(&rfs, secureboot_vars, mok_variables);
+            let identifications: Vec<Identification> = match event_log {
+                Some(log) => decode_cel(&read_input(log)?, *encoding)?
+                    .iter()
+                    .flat_map(|record| {
+                        record.digests.iter().map(|d| Identification {
+                            digest: d.digest.clone(),
+                            pcr: Some(record.pcr),
+                            event_type: tcg::event_type_name(record.content.event_type)
+                                .map(String::from),
+                            matches: whatis::identify(&candidates, &d.digest),
+                        })
+                    })
+                    .collect(),
+                None => digest
+                    .iter()
+                    .map(|d| Identification {
+                        digest: d.to_lowercase(),
+                        pcr: None,
+                        event_type: None,
+                        matches: whatis::identify(&candidates, d),
+                    })
+                    .collect(),
+            };
+            println!("{}", serde_json::to_string_pretty(&identifications)?);
+            Ok(())
+        }
+        Command::Policy {
+            input,
+            pcrs,
+            bank,
+            hash_alg,
+            previous,
+        } => {
+            let selection = tpm2::PcrSelection::new(*bank, pcrs)?;
+            let previous = previous.as_deref().map(hex::decode).transpose()?;
+            let digest = tpm2::policy_pcr(
+                &selection,
+                &read_pcrs(input)?,
+                *hash_alg,
+                previous.as_deref(),
+            )?;
+            println!("{}", hex::encode(digest));
+            Ok(())
+        }
+        Command::Quote {
+            input,
+            pcrs,
+            bank,
+            hash_alg,
+        } => {
+            let selection = tpm2::PcrSelection::new(*bank, pcrs)?;
+            let quote_info =
+                tpm2::quote_info(&selection, &read_pcrs(input)?, hash_alg.unwrap_or(*bank))?;
+            println!("{}", serde_json::to_string_pretty(&quote_info)?);
+            Ok(())
+        }
+        Command::Clevis { input, pcrs, bank } => {
+            let selection = tpm2::PcrSelection::new(*bank, pcrs)?;
+            let config = clevis::tpm2_pin_config(&selection, &read_pcrs(input)?)?;
+            println!("{}", serde_json::to_string(&config)?);
+            Ok(())
+        }
+        Command::Rvps {
+            inputs,
+            verifier,
+            validity_days,
+            message,
+        } => {
+            let outputs = inputs
+                .iter()
+                .map(|input| read_pcrs(input))
+                .collect::<Result<Vec<_>>>()?;
+            let expiration = chrono::Utc::now() + chrono::Duration::days(*validity_days);
+            let reference_values = rvps::reference_values(&outputs, *verifier, expiration);
+            let json = if *message {
+                serde_json::to_string_pretty(&rvps::sample_message(&reference_values)?)?
+            } else {
+                serde_json::to_string_pretty(&reference_values)?
+            };
+            println!("{json}");
+            Ok(())
+        }
+        Command::Corim {
+            input,
+            rootfs,
+            platform,
+            events,
+            private_key,
+            signer_name,
+            output,
+        } => {
+            let class =
+                corim::EnvironmentClass::from_rootfs(&rootfs::RootFSTree::open(rootfs)?, platform)?;
+            let comid = corim::comid(&class, &read_pcrs(input)?, *events)?;
+            let mut data = corim::corim(&comid)?;
+            if let Some(private_key) = private_key {
+                let key = openssl::pkey::PKey::private_key_from_pem(&read_input(private_key)?)?;
+                data = corim::sign(&data, &key, signer_name)?;
+            }
+            write_output(output.as_deref(), &data)
+        }
+        Command::Attest {
+            inputs,
+            image,
+            private_key,
+            output,
+        } => {
+            let mut pcrs = vec![];
+            let mut subjects = vec![];
+            for input in inputs {
+                let data = read_input(input)?;
+                subjects.push(intoto::Subject::from_data(input, &data));
+                pcrs.extend(parse_pcrs(&data)?);
+            }
+            if let Some(image) = image {
+                subjects = vec![intoto::Subject::from_image(image)?];
+            }
+            let statement = intoto::Statement::new(
+                subjects,
+                serde_json::to_value(Output {
+                    pcrs,
+                    manifest_digest: None,
+                })?,
+            );
+            let key = openssl::pkey::PKey::private_key_from_pem(&read_input(private_key)?)?;
+            let envelope = intoto::sign(&statement, &key)?;
+            let data = format!("{}\n", serde_json::to_string(&envelope)?);
+            write_output(output.as_deref(), data.as_bytes())
+        }
+        Command::Verify {
+            envelope,
+            public_key,
+        } => {
+            let envelope: dsse::Envelope = serde_json::from_slice(&read_input(envelope)?)
+                .context("Failed to parse DSSE envelope")?;
+            let key = openssl::pkey::PKey::public_key_from_pem(&read_input(public_key)?)?;
+            let statement = intoto::verify(&envelope, &key)?;
+            println!("{}", serde_json::to_string_pretty(&statement)?);
+            Ok(())
+        }
+        Command::Diff {
+            old,
+            new,
+            secureboot_variables,
+            auth_updates,
+            mok_variables,
+            uki,
+            no_secureboot,
+        } => {
+            // Root filesystems are compared with the part metadata, for a
+            // finer attribution of the changes
+            let load = |path: &str| -> Result<Vec<Pcr>> {
+                if !std::path::Path::new(path).is_dir() && !rootfs::RootFSTree::is_image(path) {
+                    return read_pcrs(path);
+                }
+                let rfs = rootfs::RootFSTree::open(path)?;
+                let stores = secureboot_variables.as_ref().context(
+                    "--efivars, --ovmf-vars, --vars-json or --aws-uefi-data is required \
+                     to compare root filesystems",
+                )?;
+                Ok(vec![
+                    compute_pcr4_in(rfs.fs(), rfs.vmlinuz(), rfs.esp(), *uki, !no_secureboot),
+                    stores.compute_pcr7(&rfs, !no_secureboot, auth_updates)?,
+                    stores
+                        .compute_pcr14(mok_variables.as_deref())
+                        .context("Failed to compare root filesystems")?,
+                ])
+            };
+            let diff = diff::diff(&load(old)?, &load(new)?);
+            println!("{}", serde_json::to_string_pretty(&diff)?);
+            Ok(())
+        }
+        Command::Gpt { disk } => {
+            #[derive(Serialize)]
+            struct GptOutput<'a> {
+                #[serde(flatten)]
+                gpt: &'a disk::gpt::Gpt,
+                event: Part,
+            }
+            let gpt = disk::read_gpt(disk)?;
+            let output = GptOutput {
+                gpt: &gpt,
+                event: gpt.event(),
+            };
+            println!("{}", serde_json::to_string_pretty(&output)?);
+            Ok(())
+        }
+        Command::Host { check } => {
+            let host = host::Host::open()?;
+            let pcrs = host.compute_pcrs()?;
+            if *check {
+                let checks = host::check(&pcrs);
+                println!("{}", serde_json::to_string_pretty(&checks)?);
+                if !checks.iter().all(|c| c.matches) {
+                    anyhow::bail!("Computed PCRs do not match the TPM");
+                }
+                return Ok(());
+            }
+            let pcrs: Vec<Pcr> = pcrs.into_iter().map(strip).collect();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&Output {
+                    pcrs,
+                    manifest_digest: None,
+                })?
+            );
+            Ok(())
+        }
+        Command::Keylime {
+            rootfs,
+            scrtm,
+            platform_firmware,
+            secureboot_variables,
+            auth_updates,
+            mok_variables,
+            no_secureboot,
+            kernel_cmdline,
+        } => {
+            let scrtm_and_bios = keylime::ScrtmAndBios::new(scrtm, platform_firmware)?;
+            let rfs = rootfs::RootFSTree::open(rootfs)?;
+            let refstate = keylime::mb_refstate(
+                &rfs,
+                scrtm_and_bios,
+                secureboot_variables.loader(auth_updates)?,
+                mok_variables,
+                !no_secureboot,
+                kernel_cmdline,
+            )?;
+            println!("{}", serde_json::to_string_pretty(&refstate)?);
+            Ok(())
+        }
+        Command::Pcrlock { input, output_dir } => {
+            std::fs::create_dir_all(output_dir)?;
+            for file in pcrlock::pcrlock_files(&read_pcrs(input)?) {
+                let path = std::path::Path::new(output_dir).join(file.file_name());
+                let data = format!("{}\n", serde_json::to_string_pretty(&file)?);
+                std::fs::write(&path, data)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                log::info!("Wrote {}", path.display());
+            }
+            Ok(())
+        }
+        Command::Swtpm { input, output_dir } => {
+            use std::os::unix::fs::PermissionsExt;
+
+            let pcrs = read_pcrs(input)?;
+            let dir = std::path::Path::new(output_dir);
+            std::fs::create_dir_all(dir)?;
+
+            let script = dir.join(tpm2_tools::SCRIPT_FILE_NAME);
+            std::fs::write(&script, tpm2_tools::pcrextend_script(&pcrs)?)
+                .with_context(|| format!("Failed to write {}", script.display()))?;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755))?;
+            log::info!("Wrote {}", script.display());
+
+            let pcr_dat = dir.join(tpm2_tools::PCR_DAT_FILE_NAME);
+            std::fs::write(&pcr_dat, tpm2_tools::pcr_dat(&pcrs)?)
+                .with_context(|| format!("Failed to write {}", pcr_dat.display()))?;
+            log::info!("Wrote {}", pcr_dat.display());
+            Ok(())
+        }
+        Command::Pcrsig { command } => match command {
+            PcrsigCommand::Sign {
+                uki,
+                private_key,
+                phase,
+                bank,
+            } => {
+                let key = openssl::pkey::PKey::private_key_from_pem(&read_input(private_key)?)?;
+                let phases: Vec<&str> = phase.iter().map(String::as_str).collect();
+                let signatures = pcrsig::sign(&compute_pcr11(uki), &phases, bank, &key)?;
+                println!("{}", serde_json::to_string(&signatures)?);
+                Ok(())
+            }
+            PcrsigCommand::Verify {
+                uki,
+                public_key,
+                phase,
+            } => {
+                let pe = pefile::PeFile::load_from_file(uki, false)
+                    .with_context(|| format!("Failed to load {uki}"))?;
+                let section = |name: &str| -> Result<Vec<u8>> {
+                    let mut data = pe
+                        .section(name)
+                        .with_context(|| format!("No {name} section in {uki}"))?;
+                    // Section contents are padded with zeros
+                    while data.last() == Some(&0) {
+                        data.pop();
+                    }
+                    Ok(data)
+                };
+                let key_pem = match public_key {
+                    Some(path) => read_input(path)?,
+                    None => section(pcrsig::PCRPKEY_SECTION)?,
+                };
+                let key = openssl::pkey::PKey::public_key_from_pem(&key_pem)?;
+                let signatures: pcrsig::PcrSig =
+                    serde_json::from_slice(&section(pcrsig::PCRSIG_SECTION)?)?;
+                let phases: Vec<&str> = phase.iter().map(String::as_str).collect();
+                pcrsig::verify(&signatures, &compute_pcr11(uki), &phases, &key)?;
+                println!("OK");
+                Ok(())
+            }
+        },
+        Command::Cel { command } => match command {
+            CelCommand::Export {
+                input,
+                encoding,
+                output,
+            } => {
+                let records = cel::from_pcrs(&read_pcrs(input)?);
+                write_output(output.as_deref(), &encode_cel(&records, *encoding)?)
+            }
+            CelCommand::Replay {
+                log,
+                encoding,
+                reencode,
+            } => {
+                let records = decode_cel(&read_input(log)?, *encoding)?;
+                if let Some(reencode) = reencode {
+                    return write_output(None, &encode_cel(&records, *reencode)?);
+                }
+                let pcrs = cel::replay(&records)?.into_iter().map(strip).collect();
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&Output {
+                        pcrs,
+                        manifest_digest: None,
+                    })
+                    .unwrap()
+                );
+                Ok(())
+            }
+        },
+        Command::Efivars { command } => match command {
+            EfivarsCommand::Create {
+                output_dir,
+                pk,
+                kek,
+                db,
+                db_hash,
+                dbx,
+                dbx_hash,
+                owner,
+            } => {
+                let pk = Vec::from_iter(pk.clone());
+                let dbs = [
+                    signatures(&pk, &[], *owner)?,
+                    signatures(kek, &[], *owner)?,
+                    signatures(db, db_hash, *owner)?,
+                    signatures(dbx, dbx_hash, *owner)?,
+                ];
+                std::fs::create_dir_all(output_dir)?;
+                let targets = uefi::efivars::get_secure_boot_targets();
+                for ((name, guid), signatures) in targets.iter().zip(dbs) {
+                    // Unset variables are read as empty
+                    if signatures.is_empty() {
+                        continue;
+                    }
+                    let var =
+                        uefi::UEFIVariableData::new(*guid, name, certs::build_db(&signatures));
+                    uefi::efivars::write_efivar(
+                        std::path::Path::new(output_dir),
+                        &var,
+                        uefi::efivars::SECURE_BOOT_VAR_ATTRIBUTES,
+                    )
+                    .with_context(|| format!("Failed to write {name} to {output_dir}"))?;
+                }
+                Ok(())
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_secureboot_variable_store() {
+        let parse =
+            |args: &[&str]| Cli::try_parse_from(["compute-pcrs", "pcr7"].iter().chain(args));
+        assert!(parse(&["--efivars", "a"]).is_ok());
+        assert!(parse(&[]).is_err());
+        for other in ["--ovmf-vars", "--vars-json", "--aws-uefi-data"] {
+            assert!(parse(&["--efivars", "a", other, "b"]).is_err());
+        }
+        assert!(parse(&["--vars-json", "a", "--aws-uefi-data", "b"]).is_err());
+        // Only needed to compare root filesystems
+        assert!(Cli::try_parse_from(["compute-pcrs", "diff", "old.json", "new.json"]).is_ok());
     }
 }
@@ -0,0 +1,340 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! TCG Canonical Event Log (CEL) support.
+//!
+//! Records use the PCClient standard content type (`pcclient_std`). Both the
+//! CEL-JSON and the CEL-CBOR encodings are supported, for writing predicted
+//! logs out of computed [`Pcr`]s and for reading logs back to replay them.
+
+use crate::tcg;
+use crate::{Part, Pcr};
+use anyhow::{Context, Result, anyhow, bail};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ciborium::value::Value;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use std::collections::BTreeMap;
+
+const CONTENT_TYPE_PCCLIENT_STD: &str = "pcclient_std";
+const HASH_ALG_SHA256: &str = "sha256";
+
+// CEL-CBOR record and content keys
+const CBOR_RECNUM: u64 = 0;
+const CBOR_PCR: u64 = 1;
+const CBOR_DIGESTS: u64 = 3;
+const CBOR_PCCLIENT_STD: u64 = 5;
+const CBOR_EVENT_TYPE: u64 = 0;
+const CBOR_EVENT_DATA: u64 = 1;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Digest {
+    #[serde(rename = "hashAlg")]
+    pub hash_alg: String,
+    pub digest: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PcClientStdContent {
+    pub event_type: u32,
+    #[serde(with = "base64_data")]
+    pub event_data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub recnum: u64,
+    pub pcr: u64,
+    pub digests: Vec<Digest>,
+    pub content_type: String,
+    pub content: PcClientStdContent,
+}
+
+mod base64_data {
+    use super::BASE64;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        BASE64.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Record {
+    fn sha256(&self) -> Result<Vec<u8>> {
+        let digest = self
+            .digests
+            .iter()
+            .find(|d| d.hash_alg == HASH_ALG_SHA256)
+            .ok_or_else(|| anyhow!("record {} has no sha256 digest", self.recnum))?;
+        Ok(hex::decode(&digest.digest)?)
+    }
+}
+
+/// Returns the event type a part is measured with. Parts without metadata
+/// are resolved from their name; UKI sections measured into PCR 11 by
+/// systemd-stub are named after the section and are logged as EV_IPL events.
+fn part_event_type(part: &Part) -> u32 {
+    part.event_type
+        .or_else(|| tcg::event_type_from_name(&part.name))
+        .unwrap_or(tcg::EV_IPL)
+}
+
+/// Returns the event data recorded in the part metadata. Parts without it
+/// are exported with the event data fixed by the specification, if any, or
+/// with empty event data.
+fn part_event_data(part: &Part) -> Vec<u8> {
+    if let Some(data) = part.event_data.as_ref().and_then(|d| hex::decode(d).ok()) {
+        return data;
+    }
+    match part_event_type(part) {
+        tcg::EV_EFI_ACTION => b"Calling EFI Application from Boot Option".to_vec(),
+        tcg::EV_SEPARATOR => vec![0; 4],
+        _ => vec![],
+    }
+}
+
+/// Builds a predicted event log out of computed PCRs. Events are logged PCR
+/// by PCR, in the order in which they extend each PCR.
+pub fn from_pcrs(pcrs: &[Pcr]) -> Vec<Record> {
+    pcrs.iter()
+        .flat_map(|pcr| pcr.parts.iter().map(move |part| (pcr.id, part)))
+        .enumerate()
+        .map(|(recnum, (pcr, part))| Record {
+            recnum: recnum as u64,
+            pcr,
+            digests: vec![Digest {
+                hash_alg: HASH_ALG_SHA256.into(),
+                digest: part.hash.clone(),
+            }],
+            content_type: CONTENT_TYPE_PCCLIENT_STD.into(),
+            content: PcClientStdContent {
+                event_type: part_event_type(part),
+                event_data: part_event_data(part),
+            },
+        })
+        .collect()
+}
+
+/// Replays an event log and returns the resulting SHA-256 PCR values, sorted
+/// by PCR index. EV_NO_ACTION events are informational and never extended.
+pub fn replay(records: &[Record]) -> Result<Vec<Pcr>> {
+    let mut pcrs: BTreeMap<u64, (Vec<u8>, Vec<Part>)> = BTreeMap::new();
+    for record in records {
+        if record.content.event_type == tcg::EV_NO_ACTION {
+            continue;
+        }
+        let hash = record.sha256()?;
+        let (value, parts) = pcrs
+            .entry(record.pcr)
+            .or_insert_with(|| (vec![0; 32], vec![]));
+        let mut hasher = Sha256::new();
+        hasher.update(&value);
+        hasher.update(&hash);
+        *value = hasher.finalize().to_vec();
+        let event_type = record.content.event_type;
+        parts.push(Part {
+            name: tcg::event_type_name(event_type)
+                .map(String::from)
+                .unwrap_or_else(|| format!("{event_type:#010x}")),
+            hash: hex::encode(hash),
+            event_type: Some(event_type),
+            event_data: Some(hex::encode(&record.content.event_data)),
+            ..Default::default()
+        });
+    }
+
+    Ok(pcrs
+        .into_iter()
+        .map(|(id, (value, parts))| Pcr {
+            id,
+            value: hex::encode(value),
+            parts,
+        })
+        .collect())
+}
+
+pub fn to_json(records: &[Record]) -> Result<String> {
+    Ok(serde_json::to_string_pretty(records)?)
+}
+
+pub fn from_json(data: &str) -> Result<Vec<Record>> {
+    serde_json::from_str(data).context("Failed to parse CEL-JSON log")
+}
+
+fn record_to_cbor(record: &Record) -> Result<Value> {
+    let digests = record
+        .digests
+        .iter()
+        .map(|d| {
+            let alg_id = match d.hash_alg.as_str() {
+                HASH_ALG_SHA256 => tcg::TPM_ALG_SHA256,
+                other => bail!("unsupported hash algorithm {other}"),
+            };
+            Ok((
+                Value::from(alg_id as u64),
+                Value::Bytes(hex::decode(&d.digest)?),
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Value::Map(vec![
+        (Value::from(CBOR_RECNUM), Value::from(record.recnum)),
+        (Value::from(CBOR_PCR), Value::from(record.pcr)),
+        (Value::from(CBOR_DIGESTS), Value::Map(digests)),
+        (
+            Value::from(CBOR_PCCLIENT_STD),
+            Value::Map(vec![
+                (
+                    Value::from(CBOR_EVENT_TYPE),
+                    Value::from(record.content.event_type as u64),
+                ),
+                (
+                    Value::from(CBOR_EVENT_DATA),
+                    Value::Bytes(record.content.event_data.clone()),
+                ),
+            ]),
+        ),
+    ]))
+}
+
+/// Encodes an event log as a CBOR array of CEL-CBOR records
+pub fn to_cbor(records: &[Record]) -> Result<Vec<u8>> {
+    let log = Value::Array(records.iter().map(record_to_cbor).collect::<Result<_>>()?);
+    let mut buf = vec![];
+    ciborium::into_writer(&log, &mut buf)?;
+    Ok(buf)
+}
+
+fn cbor_map_get(map: &[(Value, Value)], key: u64) -> Option<&Value> {
+    map.iter()
+        .find(|(k, _)| k.as_integer().and_then(|i| u64::try_from(i).ok()) == Some(key))
+        .map(|(_, v)| v)
+}
+
+fn cbor_uint(value: Option<&Value>, field: &str) -> Result<u64> {
+    value
+        .and_then(Value::as_integer)
+        .and_then(|i| u64::try_from(i).ok())
+        .ok_or_else(|| anyhow!("invalid or missing CEL-CBOR field {field}"))
+}
+
+fn record_from_cbor(value: &Value) -> Result<Record> {
+    let map = value
+        .as_map()
+        .ok_or_else(|| anyhow!("CEL-CBOR record is not a map"))?;
+
+    let digests = cbor_map_get(map, CBOR_DIGESTS)
+        .and_then(Value::as_map)
+        .ok_or_else(|| anyhow!("invalid or missing CEL-CBOR field digests"))?
+        .iter()
+        .map(|(alg, digest)| {
+            let alg_id = cbor_uint(Some(alg), "hashAlg")?;
+            if alg_id != tcg::TPM_ALG_SHA256 as u64 {
+                bail!("unsupported hash algorithm {alg_id:#06x}");
+            }
+            let digest = digest
+                .as_bytes()
+                .ok_or_else(|| anyhow!("invalid CEL-CBOR digest"))?;
+            Ok(Digest {
+                hash_alg: HASH_ALG_SHA256.into(),
+                digest: hex::encode(digest),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let content = cbor_map_get(map, CBOR_PCCLIENT_STD)
+        .and_then(Value::as_map)
+        .ok_or_else(|| anyhow!("only the pcclient_std CEL content type is supported"))?;
+    let event_data = cbor_map_get(content, CBOR_EVENT_DATA)
+        .and_then(Value::as_bytes)
+        .ok_or_else(|| anyhow!("invalid or missing CEL-CBOR field event_data"))?;
+
+    Ok(Record {
+        recnum: cbor_uint(cbor_map_get(map, CBOR_RECNUM), "recnum")?,
+        pcr: cbor_uint(cbor_map_get(map, CBOR_PCR), "pcr")?,
+        digests,
+        content_type: CONTENT_TYPE_PCCLIENT_STD.into(),
+        content: PcClientStdContent {
+            event_type: cbor_uint(cbor_map_get(content, CBOR_EVENT_TYPE), "event_type")?
+                .try_into()?,
+            event_data: event_data.clone(),
+        },
+    })
+}
+
+pub fn from_cbor(data: &[u8]) -> Result<Vec<Record>> {
+    let log: Value = ciborium::from_reader(data).context("Failed to parse CEL-CBOR log")?;
+    log.as_array()
+        .ok_or_else(|| anyhow!("CEL-CBOR log is not an array"))?
+        .iter()
+        .map(record_from_cbor)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcr4() -> Pcr {
+        Pcr {
+            id: 4,
+            value: "1108cb3de5a9380f5d1f699a30e49a03f68cf021d27b891a35f29a59db13ed57".into(),
+            parts: vec![
+                Part {
+                    name: "EV_EFI_ACTION".into(),
+                    hash: "3d6772b4f84ed47595d72a2c4c5ffd15f5bb72c7507fe26f2aaee2c69d5633ba".into(),
+                    ..Default::default()
+                },
+                Part {
+                    name: "EV_SEPARATOR".into(),
+                    hash: "df3f619804a92fdb4057192dc43dd748ea778adc52bc498ce80524c014b81119".into(),
+                    ..Default::default()
+                },
+                Part {
+                    name: "EV_EFI_BOOT_SERVICES_APPLICATION".into(),
+                    hash: "27b12463e599b3147635f272e3722960e443def60fef088eba600697017529f2".into(),
+                    ..Default::default()
+                },
+                Part {
+                    name: "EV_EFI_BOOT_SERVICES_APPLICATION".into(),
+                    hash: "71f09da2978dfd9b92150b6b96329e728a21c938e23f483fed8bd22789692ee6".into(),
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn cbor_roundtrip_replays_to_same_value() {
+        let records = from_pcrs(&[pcr4()]);
+        let decoded = from_cbor(&to_cbor(&records).unwrap()).unwrap();
+        assert_eq!(records, decoded);
+        assert_eq!(replay(&decoded).unwrap()[0].value, pcr4().value);
+    }
+
+    #[test]
+    fn replay_skips_no_action_events() {
+        let mut records = from_pcrs(&[pcr4()]);
+        let mut no_action = records[0].clone();
+        no_action.content.event_type = tcg::EV_NO_ACTION;
+        no_action.digests[0].digest = hex::encode([0u8; 32]);
+        records.insert(0, no_action);
+        let pcrs = replay(&records).unwrap();
+        assert_eq!(pcrs[0].value, pcr4().value);
+        assert_eq!(pcrs[0].parts.len(), 4);
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let records = from_pcrs(&[pcr4()]);
+        assert_eq!(records, from_json(&to_json(&records).unwrap()).unwrap());
+    }
+}
@@ -3,13 +3,24 @@
 //
 // SPDX-License-Identifier: MIT
 
-use crate::uefi::{EFI_CERT_TYPE_X509_GUID, guid_to_le_bytes};
+use crate::uefi::{
+    EFI_CERT_SHA256_GUID, EFI_CERT_TYPE_X509_GUID, guid_from_le_bytes, guid_to_le_bytes,
+};
 use std::fmt;
+use uuid::Uuid;
+
+/// EFI_SIGNATURE_LIST header size: type GUID, list size, header size and
+/// signature size
+const SIGNATURE_LIST_HEADER_SIZE: usize = 28;
+/// Size of the owner GUID leading every EFI_SIGNATURE_DATA
+const SIGNATURE_OWNER_SIZE: usize = 16;
 
 #[derive(Debug)]
 pub struct X509Cert {
     pub issuer: String,
     pub subject: String,
+    /// Hex encoded serial number
+    pub serial: String,
     pub raw: Vec<u8>,
 }
 
@@ -19,6 +30,7 @@ impl X509Cert {
         Ok(X509Cert {
             issuer: cert_issuer(&cert),
             subject: cert_subject(&cert),
+            serial: cert_serial(&cert),
             raw: data.to_vec(),
         })
     }
@@ -93,6 +105,14 @@ fn cert_issuer(cert: &openssl::x509::X509) -> String {
         .join(", ")
 }
 
+/// Returns the hex encoded serial number of a openssl X509 certificate
+fn cert_serial(cert: &openssl::x509::X509) -> String {
+    cert.serial_number()
+        .to_bn()
+        .and_then(|bn| bn.to_hex_str().map(|s| s.to_lowercase()))
+        .unwrap_or_default()
+}
+
 /// Finds the certificates that UEFI db contains given its raw representation
 /// Returns X509 structures and their raw representation
 fn get_db_certs_raw(
@@ -145,6 +165,103 @@ fn get_db_certs_raw(
     Ok(certs)
 }
 
+/// An EFI_SIGNATURE_DATA entry of a signature database
+#[derive(Clone, Debug, PartialEq)]
+pub struct SignatureData {
+    /// Type of the EFI_SIGNATURE_LIST holding the entry
+    pub signature_type: Uuid,
+    pub owner: Uuid,
+    pub data: Vec<u8>,
+}
+
+impl SignatureData {
+    /// An X.509 certificate entry, from its DER encoding
+    pub fn x509(owner: Uuid, der: &[u8]) -> SignatureData {
+        SignatureData {
+            signature_type: EFI_CERT_TYPE_X509_GUID,
+            owner,
+            data: der.to_vec(),
+        }
+    }
+
+    /// A SHA-256 hash entry
+    pub fn sha256(owner: Uuid, hash: &[u8; 32]) -> SignatureData {
+        SignatureData {
+            signature_type: EFI_CERT_SHA256_GUID,
+            owner,
+            data: hash.to_vec(),
+        }
+    }
+}
+
+/// Returns the DER encoding of the certificates of a PEM file, or of a DER
+/// encoded certificate
+pub fn certs_to_der(data: &[u8]) -> Result<Vec<Vec<u8>>, openssl::error::ErrorStack> {
+    if data.starts_with(b"-----BEGIN") {
+        return openssl::x509::X509::stack_from_pem(data)?
+            .iter()
+            .map(|cert| cert.to_der())
+            .collect();
+    }
+    Ok(vec![openssl::x509::X509::from_der(data)?.to_der()?])
+}
+
+/// Builds the raw representation of a signature database, the inverse of
+/// [`get_db_signatures`]. Consecutive entries of the same type and size are
+/// grouped into a single EFI_SIGNATURE_LIST.
+pub fn build_db(signatures: &[SignatureData]) -> Vec<u8> {
+    let mut db = vec![];
+    for list in signatures
+        .chunk_by(|a, b| a.signature_type == b.signature_type && a.data.len() == b.data.len())
+    {
+        let item_size = SIGNATURE_OWNER_SIZE + list[0].data.len();
+        let list_size = SIGNATURE_LIST_HEADER_SIZE + list.len() * item_size;
+        db.extend(guid_to_le_bytes(&list[0].signature_type));
+        db.extend((list_size as u32).to_le_bytes());
+        db.extend(0u32.to_le_bytes());
+        db.extend((item_size as u32).to_le_bytes());
+        for signature in list {
+            db.extend(guid_to_le_bytes(&signature.owner));
+            db.extend(&signature.data);
+        }
+    }
+    db
+}
+
+/// Returns every entry of a signature database given its raw representation,
+/// whatever its signature type
+pub fn get_db_signatures(data: &[u8]) -> Result<Vec<SignatureData>, CertDbParsingError> {
+    let mut signatures = vec![];
+    let mut offset = 0;
+
+    while offset + SIGNATURE_LIST_HEADER_SIZE <= data.len() {
+        let header = &data[offset..offset + SIGNATURE_LIST_HEADER_SIZE];
+        let signature_type = guid_from_le_bytes(header[0..16].try_into().unwrap());
+        let list_size = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+        let head_size = u32::from_le_bytes(header[20..24].try_into().unwrap()) as usize;
+        let item_size = u32::from_le_bytes(header[24..28].try_into().unwrap()) as usize;
+
+        if list_size < SIGNATURE_LIST_HEADER_SIZE + head_size || offset + list_size > data.len() {
+            return Err(CertDbParsingError::new("Invalid list size"));
+        }
+        if item_size <= SIGNATURE_OWNER_SIZE {
+            return Err(CertDbParsingError::new("Invalid signature size"));
+        }
+
+        let items = &data[offset + SIGNATURE_LIST_HEADER_SIZE + head_size..offset + list_size];
+        for item in items.chunks_exact(item_size) {
+            signatures.push(SignatureData {
+                signature_type,
+                owner: guid_from_le_bytes(item[..SIGNATURE_OWNER_SIZE].try_into().unwrap()),
+                data: item[SIGNATURE_OWNER_SIZE..].to_vec(),
+            });
+        }
+        offset += list_size;
+    }
+
+    Ok(signatures)
+}
+
 // Given the raw representation of a certificate db, it returns a vector
 // containing X509Cert representations of its contents
 pub fn get_db_certs(data: &[u8]) -> Result<Vec<X509Cert>, CertDbParsingError> {
@@ -153,7 +270,32 @@ pub fn get_db_certs(data: &[u8]) -> Result<Vec<X509Cert>, CertDbParsingError> {
         .map(|(c, r)| X509Cert {
             subject: cert_subject(c),
             issuer: cert_issuer(c),
+            serial: cert_serial(c),
             raw: r.clone(),
         })
         .collect())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::uuid;
+
+    #[test]
+    fn build_db_round_trip() {
+        let owner = uuid!("77fa9abd-0359-4d32-bd60-28f4e78f784b");
+        let signatures = vec![
+            SignatureData::sha256(owner, &[1; 32]),
+            SignatureData::sha256(Uuid::nil(), &[2; 32]),
+            SignatureData::x509(owner, b"certificate"),
+            SignatureData::x509(owner, b"other certificate"),
+        ];
+        let db = build_db(&signatures);
+        // The two hashes share a list, certificates of different sizes don't
+        assert_eq!(
+            db.len(),
+            3 * SIGNATURE_LIST_HEADER_SIZE + 4 * SIGNATURE_OWNER_SIZE + 2 * 32 + 11 + 17
+        );
+        assert_eq!(get_db_signatures(&db).unwrap(), signatures);
+    }
+}
@@ -0,0 +1,72 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Clevis tpm2 pin configuration.
+
+use crate::Pcr;
+use crate::tpm2::PcrSelection;
+use anyhow::Result;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tpm2PinConfig {
+    pub pcr_bank: String,
+    /// Comma separated list of PCR indexes
+    pub pcr_ids: String,
+    /// Base64url encoded expected PCR values
+    pub pcr_digest: String,
+}
+
+/// Builds the configuration of a Clevis tpm2 pin binding to the selected
+/// PCRs.
+///
+/// Clevis decodes `pcr_digest` and hands it to `tpm2_createpolicy` as the
+/// PCR values file, which holds the raw values of the selected PCRs
+/// concatenated in selection order. tpm2-tools then computes the PCR digest
+/// of the policy out of it.
+pub fn tpm2_pin_config(selection: &PcrSelection, pcrs: &[Pcr]) -> Result<Tpm2PinConfig> {
+    Ok(Tpm2PinConfig {
+        pcr_bank: selection.bank.to_string(),
+        pcr_ids: selection
+            .pcrs
+            .iter()
+            .map(u64::to_string)
+            .collect::<Vec<_>>()
+            .join(","),
+        pcr_digest: BASE64URL.encode(selection.values(pcrs)?.concat()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcg::HashAlgorithm;
+
+    #[test]
+    fn pin_config() {
+        let pcrs: Vec<Pcr> = [4u8, 7, 14]
+            .iter()
+            .map(|id| Pcr {
+                id: *id as u64,
+                value: hex::encode([*id; 32]),
+                parts: vec![],
+            })
+            .collect();
+        let selection = PcrSelection::new(HashAlgorithm::Sha256, &[7, 4]).unwrap();
+        let config = tpm2_pin_config(&selection, &pcrs).unwrap();
+        assert_eq!(
+            serde_json::to_value(&config).unwrap(),
+            serde_json::json!({
+                "pcr_bank": "sha256",
+                "pcr_ids": "4,7",
+                "pcr_digest": "BAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBw"
+            })
+        );
+        let selection = PcrSelection::new(HashAlgorithm::Sha256, &[11]).unwrap();
+        assert!(tpm2_pin_config(&selection, &pcrs).is_err());
+    }
+}
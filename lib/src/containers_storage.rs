@@ -0,0 +1,322 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! containers-storage input.
+//!
+//! Images pulled by podman or buildah are looked up in the overlay driver
+//! store, without mounting them. The paths compute-pcrs reads are read in
+//! memory from the layer `diff` directories, so the store is only ever read.
+
+use crate::oci::{Extractor, OciImage, may_contain_needed};
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+pub const CONTAINERS_STORAGE_PREFIX: &str = "containers-storage:";
+
+const ROOT_GRAPHROOT: &str = "/var/lib/containers/storage";
+const ROOTLESS_GRAPHROOT: &str = "containers/storage";
+const OVERLAY_DRIVER: &str = "overlay";
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+/// Extended attributes marking opaque directories, for rootful and rootless
+/// stores
+const OPAQUE_XATTRS: [&str; 2] = ["trusted.overlay.opaque", "user.overlay.opaque"];
+
+#[derive(Debug, Deserialize)]
+struct Image {
+    id: String,
+    #[serde(default)]
+    names: Vec<String>,
+    #[serde(default)]
+    digest: Option<String>,
+    layer: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Layer {
+    id: String,
+    #[serde(default)]
+    parent: Option<String>,
+}
+
+/// Default store of the current user, as used by podman without a
+/// storage.conf override
+fn default_graphroot() -> Result<PathBuf> {
+    if fs::metadata("/proc/self")?.uid() == 0 {
+        return Ok(ROOT_GRAPHROOT.into());
+    }
+    let data_home = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            PathBuf::from(std::env::var_os("HOME").context("HOME is not set")?).join(".local/share")
+        }
+    };
+    Ok(data_home.join(ROOTLESS_GRAPHROOT))
+}
+
+/// Splits a reference into the store graph root and the image name or ID.
+/// The store can be given as `[overlay@/graph/root+/run/root]image`.
+fn parse_reference(reference: &str) -> Result<(PathBuf, &str)> {
+    let Some(spec) = reference.strip_prefix('[') else {
+        return Ok((default_graphroot()?, reference));
+    };
+    let (store, image) = spec
+        .split_once(']')
+        .ok_or_else(|| anyhow!("Invalid store specification in {reference}"))?;
+    let graphroot = match store.split_once('@') {
+        Some((driver, _)) if driver != OVERLAY_DRIVER => {
+            bail!("Unsupported storage driver {driver}")
+        }
+        Some((_, roots)) => roots,
+        None => store,
+    };
+    let graphroot = graphroot.split(['+', ':']).next().unwrap_or_default();
+    Ok((graphroot.into(), image))
+}
+
+fn matches(image: &Image, name: &str) -> bool {
+    let name = name.strip_prefix("sha256:").unwrap_or(name);
+    let is_id_prefix = name.len() >= 3 && name.chars().all(|c| c.is_ascii_hexdigit());
+    let tagged = if name.contains(':') || name.contains('@') {
+        name.to_string()
+    } else {
+        format!("{name}:latest")
+    };
+    (is_id_prefix && image.id.starts_with(name))
+        || image
+            .names
+            .iter()
+            .any(|n| *n == tagged || n.ends_with(&format!("/{tagged}")))
+}
+
+/// Finds the image a name, or a unique ID prefix, refers to
+fn find_image<'a>(images: &'a [Image], name: &str) -> Result<&'a Image> {
+    let found: Vec<&Image> = images.iter().filter(|i| matches(i, name)).collect();
+    match found[..] {
+        [image] => Ok(image),
+        [] => bail!("Image {name} not found"),
+        _ => bail!(
+            "Ambiguous image reference {name}, matching images {}",
+            found
+                .iter()
+                .map(|i| i.id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&data).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn is_opaque(path: &Path) -> bool {
+    OPAQUE_XATTRS
+        .iter()
+        .any(|attr| matches!(xattr::get(path, attr), Ok(Some(v)) if v == b"y"))
+}
+
+/// Reads the needed paths of the `dir` directory of a layer
+fn link_layer_dir(extractor: &mut Extractor, diff: &Path, dir: &Path, layer: usize) -> Result<()> {
+    let source = diff.join(dir);
+    let entries = match fs::read_dir(&source) {
+        Ok(entries) => entries,
+        // A subdirectory may vanish while the layer is being read, but the
+        // layer root itself must be readable
+        Err(e) if e.kind() == io::ErrorKind::NotFound && dir != Path::new("") => {
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", source.display()));
+        }
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        let source = entry.path();
+        let file_type = entry.file_type()?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+
+        // Whiteouts are either overlay character devices or AUFS style
+        // files, depending on how the layer has been applied
+        if file_name == OPAQUE_WHITEOUT {
+            extractor.whiteout(dir, layer, true);
+            continue;
+        }
+        if let Some(name) = file_name.strip_prefix(WHITEOUT_PREFIX) {
+            extractor.whiteout(&dir.join(name), layer, false);
+            continue;
+        }
+        if file_type.is_char_device() && entry.metadata()?.rdev() == 0 {
+            extractor.whiteout(&path, layer, false);
+            continue;
+        }
+
+        if file_type.is_dir() {
+            if !may_contain_needed(&path) {
+                continue;
+            }
+            if is_opaque(&source) {
+                extractor.whiteout(&path, layer, true);
+            }
+            if extractor.claim(&path, layer) {
+                extractor.dir(&path);
+            }
+            link_layer_dir(extractor, diff, &path, layer)?;
+        } else if extractor.claim(&path, layer) {
+            if file_type.is_symlink() {
+                extractor.symlink(&path, &fs::read_link(&source)?)?;
+            } else {
+                let data = fs::read(&source)
+                    .with_context(|| format!("Failed to read {}", source.display()))?;
+                extractor.file(&path, data);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Opens an image of a containers-storage store, given its name or ID
+pub fn open(reference: &str) -> Result<OciImage> {
+    let (graphroot, name) = parse_reference(reference)?;
+    let images: Vec<Image> = read_json(&graphroot.join("overlay-images/images.json"))?;
+    let layers: Vec<Layer> = read_json(&graphroot.join("overlay-layers/layers.json"))?;
+
+    let image = find_image(&images, name).with_context(|| format!("In {}", graphroot.display()))?;
+
+    // Layers from the top one
+    let mut chain = vec![];
+    let mut layer_id = Some(image.layer.clone());
+    while let Some(id) = layer_id {
+        let layer = layers
+            .iter()
+            .find(|l| l.id == id)
+            .ok_or_else(|| anyhow!("Layer {id} not found"))?;
+        chain.push(layer);
+        layer_id = layer.parent.clone();
+    }
+
+    let mut extractor = Extractor::new();
+    for (position, layer) in chain.iter().enumerate() {
+        let diff = graphroot.join(OVERLAY_DRIVER).join(&layer.id).join("diff");
+        link_layer_dir(
+            &mut extractor,
+            &diff,
+            Path::new(""),
+            chain.len() - 1 - position,
+        )?;
+    }
+
+    // Images built locally have no manifest digest, in which case the image
+    // ID, the digest of its configuration, is recorded instead
+    let digest = match &image.digest {
+        Some(digest) => digest.clone(),
+        None => format!("sha256:{}", image.id),
+    };
+    Ok(OciImage::new(&digest, extractor.into_fs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::Filesystem;
+    use tempfile::TempDir;
+
+    fn image(id: &str, names: &[&str]) -> Image {
+        Image {
+            id: id.into(),
+            names: names.iter().map(|n| n.to_string()).collect(),
+            digest: None,
+            layer: String::new(),
+        }
+    }
+
+    #[test]
+    fn store_reference() {
+        let (graphroot, name) =
+            parse_reference("[overlay@/srv/storage+/run/containers/storage]fedora:42").unwrap();
+        assert_eq!(graphroot, Path::new("/srv/storage"));
+        assert_eq!(name, "fedora:42");
+        let (graphroot, name) = parse_reference("[/srv/storage]abc123").unwrap();
+        assert_eq!(graphroot, Path::new("/srv/storage"));
+        assert_eq!(name, "abc123");
+        assert!(parse_reference("[vfs@/srv/storage]fedora").is_err());
+        assert!(parse_reference("[/srv/storage").is_err());
+    }
+
+    #[test]
+    fn image_lookup() {
+        let images = [
+            image("abc123", &["quay.io/fedora/fedora-bootc:42"]),
+            image("abc456", &["localhost/fcos:latest"]),
+        ];
+        assert!(matches(&images[0], "fedora-bootc:42"));
+        assert!(matches(&images[0], "quay.io/fedora/fedora-bootc:42"));
+        assert!(!matches(&images[0], "fedora-bootc"));
+        assert!(matches(&images[1], "fcos"));
+        assert!(matches(&images[1], "sha256:abc4"));
+
+        assert_eq!(find_image(&images, "abc1").unwrap().id, "abc123");
+        assert_eq!(find_image(&images, "fcos").unwrap().id, "abc456");
+        assert!(find_image(&images, "abd").is_err());
+        let err = find_image(&images, "abc").unwrap_err();
+        assert!(err.to_string().starts_with("Ambiguous image reference abc"));
+    }
+
+    #[test]
+    fn layer_whiteouts() {
+        let store = TempDir::new().unwrap();
+        let write = |layer: &str, path: &str, data: &[u8]| {
+            let path = store.path().join(layer).join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+        };
+        write("lower", "usr/lib/modules/6.14.0/vmlinuz", b"old kernel");
+        write(
+            "lower",
+            "usr/lib/bootupd/updates/EFI/fedora/shimx64.efi",
+            b"shim",
+        );
+        write(
+            "lower",
+            "usr/lib/bootupd/updates/EFI/fedora/grubx64.efi",
+            b"grub",
+        );
+        write("upper", "usr/lib/modules/.wh..wh..opq", b"");
+        write("upper", "usr/lib/modules/6.15.0/vmlinuz", b"new kernel");
+        write(
+            "upper",
+            "usr/lib/bootupd/updates/EFI/fedora/.wh.grubx64.efi",
+            b"",
+        );
+
+        let mut extractor = Extractor::new();
+        // Layers are linked from the top one, as done by open
+        for (layer, number) in [("upper", 1), ("lower", 0)] {
+            let diff = store.path().join(layer);
+            link_layer_dir(&mut extractor, &diff, Path::new(""), number).unwrap();
+        }
+        let root = extractor.into_fs();
+        let modules = Path::new("usr/lib/modules");
+        assert_eq!(
+            root.read(&modules.join("6.15.0/vmlinuz")).unwrap(),
+            b"new kernel"
+        );
+        assert!(!root.is_file(&modules.join("6.14.0/vmlinuz")));
+        let efi = Path::new("usr/lib/bootupd/updates/EFI/fedora");
+        assert_eq!(root.read(&efi.join("shimx64.efi")).unwrap(), b"shim");
+        assert!(!root.is_file(&efi.join("grubx64.efi")));
+
+        // Unreadable layers are not silently skipped
+        let missing = store.path().join("missing");
+        assert!(link_layer_dir(&mut Extractor::new(), &missing, Path::new(""), 0).is_err());
+    }
+}
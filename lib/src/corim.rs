@@ -0,0 +1,335 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! IETF CoRIM (Concise Reference Integrity Manifest) output.
+//!
+//! The CoRIM holds a single CoMID with one reference value triple. Its
+//! environment is the class of the image, and its measurements are the
+//! computed PCR values, keyed by PCR index, optionally followed by the digest
+//! of every event, keyed by `pcr<index>/<event number>`. The CoRIM can be
+//! signed as a COSE_Sign1 message.
+
+use crate::Pcr;
+use crate::rootfs::RootFSTree;
+use anyhow::{Context, Result, bail};
+use ciborium::value::Value;
+use openssl::ec::EcKey;
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{MessageDigest, hash};
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::rsa::Padding;
+use openssl::sign::{RsaPssSaltlen, Signer};
+use sha2::{Digest as _, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use uuid::{Builder, Uuid};
+
+// CBOR tags
+const TAG_UUID: u64 = 37;
+const TAG_COSE_SIGN1: u64 = 18;
+const TAG_UNSIGNED_CORIM: u64 = 501;
+const TAG_COMID: u64 = 506;
+
+// corim-map keys
+const CORIM_ID: i64 = 0;
+const CORIM_TAGS: i64 = 1;
+// concise-mid-tag keys
+const COMID_TAG_IDENTITY: i64 = 1;
+const COMID_TRIPLES: i64 = 4;
+const TAG_IDENTITY_ID: i64 = 0;
+const TRIPLES_REFERENCE: i64 = 0;
+// environment-map and class-map keys
+const ENVIRONMENT_CLASS: i64 = 0;
+const CLASS_VENDOR: i64 = 1;
+const CLASS_MODEL: i64 = 2;
+// measurement-map and measurement-values-map keys
+const MEASUREMENT_KEY: i64 = 0;
+const MEASUREMENT_VALUES: i64 = 1;
+const VALUES_DIGESTS: i64 = 2;
+const VALUES_NAME: i64 = 11;
+// corim-meta-map and corim-signer-map keys
+const META_SIGNER: i64 = 0;
+const SIGNER_NAME: i64 = 0;
+
+/// Named Information hash algorithm identifier of SHA-256
+const NI_SHA256: i64 = 1;
+
+// COSE header parameters
+const COSE_HEADER_ALG: i64 = 1;
+const COSE_HEADER_CONTENT_TYPE: i64 = 3;
+const COSE_HEADER_CORIM_META: i64 = 8;
+const CORIM_CONTENT_TYPE: &str = "application/rim+cbor";
+
+const OS_RELEASE_PATHS: [&str; 2] = ["etc/os-release", "usr/lib/os-release"];
+
+/// The class of the environment the reference values apply to
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvironmentClass {
+    pub vendor: String,
+    pub model: String,
+}
+
+fn os_release(rootfs: &RootFSTree) -> Result<HashMap<String, String>> {
+    let fs = rootfs.fs();
+    let path = OS_RELEASE_PATHS
+        .iter()
+        .map(|p| Path::new(rootfs.root()).join(p))
+        .find(|p| fs.is_file(p))
+        .with_context(|| format!("No os-release file found in {}", rootfs.root()))?;
+    let data = fs
+        .read(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(String::from_utf8_lossy(&data)
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().into(), value.trim().trim_matches('"').into()))
+        .collect())
+}
+
+impl EnvironmentClass {
+    /// Derives the class from the os-release file of the image and the name
+    /// of the platform it boots on. The vendor is the OS name, and the model
+    /// the OS identifier, version and platform.
+    pub fn from_rootfs(rootfs: &RootFSTree, platform: &str) -> Result<EnvironmentClass> {
+        let os_release = os_release(rootfs)?;
+        let field = |key: &str| os_release.get(key).cloned().unwrap_or_default();
+        let id = field("ID");
+        let vendor = os_release.get("NAME").cloned().unwrap_or(id.clone());
+        Ok(EnvironmentClass {
+            vendor,
+            model: format!("{id}-{}/{platform}", field("VERSION_ID")),
+        })
+    }
+}
+
+fn encode(value: &Value) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    ciborium::into_writer(value, &mut buf)?;
+    Ok(buf)
+}
+
+fn int_map(entries: Vec<(i64, Value)>) -> Value {
+    Value::Map(entries.into_iter().map(|(k, v)| (k.into(), v)).collect())
+}
+
+/// Derives a stable UUID from some content, so that generating the CoRIM of
+/// the same values twice gives the same identifiers
+fn tagged_uuid(content: &[u8]) -> Value {
+    let digest = Sha256::digest(content);
+    let uuid: Uuid = Builder::from_custom_bytes(digest[..16].try_into().unwrap()).into_uuid();
+    Value::Tag(TAG_UUID, Box::new(Value::Bytes(uuid.as_bytes().to_vec())))
+}
+
+fn measurement(key: Value, name: Option<&str>, digest: &str) -> Result<Value> {
+    let digests = Value::Array(vec![Value::Array(vec![
+        NI_SHA256.into(),
+        Value::Bytes(hex::decode(digest)?),
+    ])]);
+    let mut values = vec![(VALUES_DIGESTS, digests)];
+    if let Some(name) = name {
+        values.push((VALUES_NAME, name.into()));
+    }
+    Ok(int_map(vec![
+        (MEASUREMENT_KEY, key),
+        (MEASUREMENT_VALUES, int_map(values)),
+    ]))
+}
+
+/// Encodes a CoMID holding the reference values of the computed PCRs, and of
+/// their events if `events` is set
+pub fn comid(class: &EnvironmentClass, pcrs: &[Pcr], events: bool) -> Result<Vec<u8>> {
+    let mut measurements = pcrs
+        .iter()
+        .map(|pcr| measurement(pcr.id.into(), None, &pcr.value))
+        .collect::<Result<Vec<_>>>()?;
+    if events {
+        for pcr in pcrs {
+            for (index, part) in pcr.parts.iter().enumerate() {
+                let key = format!("pcr{}/{index}", pcr.id);
+                measurements.push(measurement(key.into(), Some(&part.name), &part.hash)?);
+            }
+        }
+    }
+
+    let environment = int_map(vec![(
+        ENVIRONMENT_CLASS,
+        int_map(vec![
+            (CLASS_VENDOR, class.vendor.as_str().into()),
+            (CLASS_MODEL, class.model.as_str().into()),
+        ]),
+    )]);
+    let triples = int_map(vec![(
+        TRIPLES_REFERENCE,
+        Value::Array(vec![Value::Array(vec![
+            environment,
+            Value::Array(measurements),
+        ])]),
+    )]);
+
+    let tag_id = tagged_uuid(&encode(&triples)?);
+    encode(&int_map(vec![
+        (COMID_TAG_IDENTITY, int_map(vec![(TAG_IDENTITY_ID, tag_id)])),
+        (COMID_TRIPLES, triples),
+    ]))
+}
+
+/// Encodes an unsigned CoRIM wrapping a CoMID
+pub fn corim(comid: &[u8]) -> Result<Vec<u8>> {
+    let corim = int_map(vec![
+        (CORIM_ID, tagged_uuid(comid)),
+        (
+            CORIM_TAGS,
+            Value::Array(vec![Value::Tag(
+                TAG_COMID,
+                Box::new(Value::Bytes(comid.to_vec())),
+            )]),
+        ),
+    ]);
+    encode(&Value::Tag(TAG_UNSIGNED_CORIM, Box::new(corim)))
+}
+
+/// COSE signature algorithms supported for signing a CoRIM
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CoseAlgorithm {
+    Es256,
+    Es384,
+    Es512,
+    EdDsa,
+    Ps256,
+}
+
+impl CoseAlgorithm {
+    fn for_key(key: &PKey<Private>) -> Result<CoseAlgorithm> {
+        Ok(match key.id() {
+            Id::EC => match key.ec_key()?.group().curve_name() {
+                Some(Nid::X9_62_PRIME256V1) => CoseAlgorithm::Es256,
+                Some(Nid::SECP384R1) => CoseAlgorithm::Es384,
+                Some(Nid::SECP521R1) => CoseAlgorithm::Es512,
+                _ => bail!("Unsupported elliptic curve"),
+            },
+            Id::ED25519 => CoseAlgorithm::EdDsa,
+            Id::RSA => CoseAlgorithm::Ps256,
+            _ => bail!("Unsupported signing key type"),
+        })
+    }
+
+    fn id(&self) -> i64 {
+        match self {
+            CoseAlgorithm::Es256 => -7,
+            CoseAlgorithm::Es384 => -35,
+            CoseAlgorithm::Es512 => -36,
+            CoseAlgorithm::EdDsa => -8,
+            CoseAlgorithm::Ps256 => -37,
+        }
+    }
+
+    /// Signs with ECDSA, encoding the signature as the concatenation of r
+    /// and s as COSE expects, instead of the DER encoding OpenSSL produces
+    fn ecdsa_sign(ec: &EcKey<Private>, md: MessageDigest, data: &[u8]) -> Result<Vec<u8>> {
+        let size = ec.group().degree().div_ceil(8) as i32;
+        let signature = EcdsaSig::sign(&hash(md, data)?, ec)?;
+        let mut buf = signature.r().to_vec_padded(size)?;
+        buf.extend(signature.s().to_vec_padded(size)?);
+        Ok(buf)
+    }
+
+    fn sign(&self, key: &PKey<Private>, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            CoseAlgorithm::Es256 => Self::ecdsa_sign(&key.ec_key()?, MessageDigest::sha256(), data),
+            CoseAlgorithm::Es384 => Self::ecdsa_sign(&key.ec_key()?, MessageDigest::sha384(), data),
+            CoseAlgorithm::Es512 => Self::ecdsa_sign(&key.ec_key()?, MessageDigest::sha512(), data),
+            CoseAlgorithm::EdDsa => Ok(Signer::new_without_digest(key)?.sign_oneshot_to_vec(data)?),
+            CoseAlgorithm::Ps256 => {
+                let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+                signer.set_rsa_padding(Padding::PKCS1_PSS)?;
+                signer.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+                signer.update(data)?;
+                Ok(signer.sign_to_vec()?)
+            }
+        }
+    }
+}
+
+/// Signs an unsigned CoRIM as a COSE_Sign1 message, with the signer name
+/// recorded in the CoRIM metadata
+pub fn sign(corim: &[u8], key: &PKey<Private>, signer_name: &str) -> Result<Vec<u8>> {
+    let alg = CoseAlgorithm::for_key(key)?;
+    let meta = int_map(vec![(
+        META_SIGNER,
+        int_map(vec![(SIGNER_NAME, signer_name.into())]),
+    )]);
+    let protected = encode(&int_map(vec![
+        (COSE_HEADER_ALG, alg.id().into()),
+        (COSE_HEADER_CONTENT_TYPE, CORIM_CONTENT_TYPE.into()),
+        (COSE_HEADER_CORIM_META, Value::Bytes(encode(&meta)?)),
+    ]))?;
+
+    let to_be_signed = encode(&Value::Array(vec![
+        "Signature1".into(),
+        Value::Bytes(protected.clone()),
+        Value::Bytes(vec![]),
+        Value::Bytes(corim.to_vec()),
+    ]))?;
+    let signature = alg.sign(key, &to_be_signed)?;
+
+    encode(&Value::Tag(
+        TAG_COSE_SIGN1,
+        Box::new(Value::Array(vec![
+            Value::Bytes(protected),
+            Value::Map(vec![]),
+            Value::Bytes(corim.to_vec()),
+            Value::Bytes(signature),
+        ])),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::bn::BigNum;
+    use openssl::ec::EcGroup;
+
+    #[test]
+    fn es256_signature_verifies() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let ec = EcKey::generate(&group).unwrap();
+        let key = PKey::from_ec_key(ec.clone()).unwrap();
+        let class = EnvironmentClass {
+            vendor: "Fedora Linux".into(),
+            model: "fedora-42/x86_64-uefi".into(),
+        };
+        let pcrs = vec![Pcr {
+            id: 7,
+            value: hex::encode([0u8; 32]),
+            parts: vec![],
+        }];
+        let corim = corim(&comid(&class, &pcrs, false).unwrap()).unwrap();
+
+        let signed: Value =
+            ciborium::from_reader(&sign(&corim, &key, "test").unwrap()[..]).unwrap();
+        let Value::Tag(TAG_COSE_SIGN1, message) = signed else {
+            panic!("Not a COSE_Sign1 message");
+        };
+        let fields = message.as_array().unwrap();
+        assert_eq!(fields[2].as_bytes().unwrap(), &corim);
+
+        let to_be_signed = encode(&Value::Array(vec![
+            "Signature1".into(),
+            fields[0].clone(),
+            Value::Bytes(vec![]),
+            fields[2].clone(),
+        ]))
+        .unwrap();
+        let raw = fields[3].as_bytes().unwrap();
+        let signature = EcdsaSig::from_private_components(
+            BigNum::from_slice(&raw[..32]).unwrap(),
+            BigNum::from_slice(&raw[32..]).unwrap(),
+        )
+        .unwrap();
+        let digest = hash(MessageDigest::sha256(), &to_be_signed).unwrap();
+        assert!(signature.verify(&digest, &ec).unwrap());
+    }
+}
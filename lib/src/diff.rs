@@ -0,0 +1,229 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Event by event comparison of two sets of computed PCRs.
+//!
+//! Events of a PCR are matched by event type and by position among the
+//! events of the same type, following the `parts` ordering. Changed events
+//! are attributed to the boot component they measure, using the part
+//! metadata when available and the well known event order otherwise.
+
+use crate::{Part, Pcr, mok, tcg};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Boot applications measured into PCR 4, in measurement order
+const PCR4_APPLICATIONS: [&str; 3] = ["shim", "GRUB", "kernel"];
+/// Secure Boot variables measured into PCR 7, in measurement order
+const PCR7_VARIABLES: [&str; 5] = ["SecureBoot", "PK", "KEK", "db", "dbx"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EventChange {
+    pub pcr: u64,
+    pub kind: ChangeKind,
+    pub component: String,
+    pub event_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PcrChange {
+    pub id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Diff {
+    /// PCRs whose value changed
+    pub pcrs: Vec<PcrChange>,
+    /// Changed components and the PCRs they affect
+    pub components: BTreeMap<String, Vec<u64>>,
+    pub events: Vec<EventChange>,
+}
+
+fn event_type(part: &Part) -> Option<u32> {
+    part.event_type
+        .or_else(|| tcg::event_type_from_name(&part.name))
+}
+
+fn application_name(path: &str) -> Option<&'static str> {
+    let file_name = Path::new(path)
+        .file_name()?
+        .to_string_lossy()
+        .to_lowercase();
+    if file_name.starts_with("shim") {
+        Some("shim")
+    } else if file_name.starts_with("grub") {
+        Some("GRUB")
+    } else if file_name.starts_with("vmlinuz") {
+        Some("kernel")
+    } else {
+        None
+    }
+}
+
+/// Returns the name of the component measured by a part. `occurrence` is
+/// the position of the part among the parts of the same event type.
+fn component(pcr: u64, part: &Part, occurrence: usize) -> String {
+    let event_type = event_type(part);
+
+    if let Some(section) = &part.section {
+        return format!("UKI {section} section");
+    }
+    if let Some(variable) = &part.variable {
+        let authority = event_type == Some(tcg::EV_EFI_VARIABLE_AUTHORITY);
+        return match (&part.certificate, &part.path) {
+            (Some(cert), _) => format!("{} certificate {}", variable.name, cert.subject),
+            (None, Some(path)) if authority && variable.name != "SbatLevel" => {
+                format!("{} authority of {path}", variable.name)
+            }
+            _ => variable.name.clone(),
+        };
+    }
+    if let Some(name) = part.path.as_deref().and_then(application_name) {
+        return name.into();
+    }
+
+    let positional = match (pcr, event_type) {
+        (4, Some(tcg::EV_EFI_BOOT_SERVICES_APPLICATION)) => PCR4_APPLICATIONS.get(occurrence),
+        (7, Some(tcg::EV_EFI_VARIABLE_DRIVER_CONFIG)) => PCR7_VARIABLES.get(occurrence),
+        (14, Some(tcg::EV_IPL)) => mok::MOK_EVENTS_PCR14.get(occurrence),
+        _ => None,
+    };
+    match (positional, event_type) {
+        (Some(name), _) => (*name).into(),
+        (None, Some(tcg::EV_EFI_VARIABLE_AUTHORITY)) => {
+            format!("Secure Boot authority #{occurrence}")
+        }
+        _ => part.name.clone(),
+    }
+}
+
+/// Returns the parts of a PCR and their position, indexed by event type, in
+/// measurement order
+fn parts_by_type(pcr: Option<&Pcr>) -> BTreeMap<String, Vec<(usize, &Part)>> {
+    let mut parts: BTreeMap<String, Vec<(usize, &Part)>> = BTreeMap::new();
+    for (position, part) in pcr.iter().flat_map(|p| &p.parts).enumerate() {
+        let name = event_type(part)
+            .and_then(tcg::event_type_name)
+            .unwrap_or(&part.name);
+        parts.entry(name.into()).or_default().push((position, part));
+    }
+    parts
+}
+
+fn diff_events(id: u64, old: Option<&Pcr>, new: Option<&Pcr>) -> Vec<EventChange> {
+    let old_parts = parts_by_type(old);
+    let new_parts = parts_by_type(new);
+    let mut event_types: Vec<&String> = old_parts.keys().chain(new_parts.keys()).collect();
+    event_types.sort();
+    event_types.dedup();
+
+    let mut changes = vec![];
+    for event_type in event_types {
+        let old_parts = old_parts.get(event_type).map(Vec::as_slice).unwrap_or(&[]);
+        let new_parts = new_parts.get(event_type).map(Vec::as_slice).unwrap_or(&[]);
+        for occurrence in 0..old_parts.len().max(new_parts.len()) {
+            let old = old_parts.get(occurrence);
+            let new = new_parts.get(occurrence);
+            let kind = match (old, new) {
+                (Some((_, o)), Some((_, n))) if o.hash == n.hash => continue,
+                (Some(_), Some(_)) => ChangeKind::Modified,
+                (Some(_), None) => ChangeKind::Removed,
+                (None, Some(_)) => ChangeKind::Added,
+                (None, None) => unreachable!(),
+            };
+            // Attribute the change using the new part, which is the one
+            // being looked at, unless it has been removed
+            let (position, part) = new.or(old).unwrap();
+            changes.push((
+                *position,
+                EventChange {
+                    pcr: id,
+                    kind,
+                    component: component(id, part, occurrence),
+                    event_type: event_type.clone(),
+                    old: old.map(|(_, p)| p.hash.clone()),
+                    new: new.map(|(_, p)| p.hash.clone()),
+                },
+            ));
+        }
+    }
+    // Report the changes in measurement order
+    changes.sort_by_key(|(position, _)| *position);
+    changes.into_iter().map(|(_, change)| change).collect()
+}
+
+/// Compares two sets of computed PCRs
+pub fn diff(old: &[Pcr], new: &[Pcr]) -> Diff {
+    let mut ids: Vec<u64> = old.iter().chain(new).map(|p| p.id).collect();
+    ids.sort();
+    ids.dedup();
+
+    let mut diff = Diff::default();
+    for id in ids {
+        let old_pcr = old.iter().find(|p| p.id == id);
+        let new_pcr = new.iter().find(|p| p.id == id);
+        let old_value = old_pcr.map(|p| p.value.clone());
+        let new_value = new_pcr.map(|p| p.value.clone());
+        if old_value == new_value {
+            continue;
+        }
+
+        diff.pcrs.push(PcrChange {
+            id,
+            old: old_value,
+            new: new_value,
+        });
+        for change in diff_events(id, old_pcr, new_pcr) {
+            let pcrs = diff.components.entry(change.component.clone()).or_default();
+            if !pcrs.contains(&id) {
+                pcrs.push(id);
+            }
+            diff.events.push(change);
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcr4(grub: &[u8]) -> Pcr {
+        Pcr::from_parts(
+            4,
+            vec![
+                Part::new(tcg::EV_SEPARATOR, &[0; 32]),
+                Part::new(tcg::EV_EFI_BOOT_SERVICES_APPLICATION, &[1; 32]),
+                Part::new(tcg::EV_EFI_BOOT_SERVICES_APPLICATION, grub),
+            ],
+        )
+    }
+
+    #[test]
+    fn attributes_changed_application() {
+        let diff = diff(&[pcr4(&[2; 32])], &[pcr4(&[3; 32])]);
+        assert_eq!(diff.pcrs.len(), 1);
+        assert_eq!(diff.components["GRUB"], vec![4]);
+        assert_eq!(diff.events.len(), 1);
+        assert_eq!(diff.events[0].kind, ChangeKind::Modified);
+    }
+}
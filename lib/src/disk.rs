@@ -0,0 +1,420 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Raw and qcow2 disk image and live ISO input.
+//!
+//! The GPT of the disk is parsed, the EFI binaries are read from the FAT ESP
+//! and the kernel of the default Boot Loader Specification entry is read
+//! from the ext4 `/boot` partition, into an in memory root filesystem where
+//! they are at the paths the rest of compute-pcrs looks for them.
+//!
+//! Live ISOs boot from the FAT image of their El Torito EFI boot entry, whose
+//! GRUB configuration loads the kernel and initrds from the ISO 9660 file
+//! system. Those files are read at their ISO paths.
+
+mod ext4;
+pub mod gpt;
+mod iso9660;
+mod qcow2;
+
+use crate::grub;
+use crate::vfs::{Filesystem, MemoryFs};
+use anyhow::{Context, Result, anyhow};
+use ext4::Ext4;
+use gpt::{EFI_SYSTEM_PARTITION_GUID, Gpt};
+use iso9660::Iso9660;
+use qcow2::{QCOW2_MAGIC, Qcow2Image};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+const ESP_TARGET: &str = "usr/lib/bootupd/updates";
+const KERNELS_TARGET: &str = "usr/lib/modules";
+/// Boot Loader Specification entries, relative to a boot partition or to a
+/// root partition holding `/boot`
+const BLS_ENTRIES_PATHS: [&str; 2] = ["loader/entries", "boot/loader/entries"];
+const INITRD_FILE_NAME: &str = "initramfs.img";
+
+/// Live kernel and initrd, when the GRUB configuration has no menu entry
+const LIVE_KERNEL_PATH: &str = "images/pxeboot/vmlinuz";
+const LIVE_INITRD_PATH: &str = "images/pxeboot/initrd.img";
+const GRUB_CONFIG_GLOB: &str = "EFI/*/grub.cfg";
+/// Offset of the kernel version string pointer in the x86 boot protocol
+/// header, and of the structure the pointer is relative to
+const KERNEL_VERSION_OFFSET: usize = 0x20e;
+const SETUP_HEADER_BASE: usize = 0x200;
+
+/// Random access to the content of a disk
+pub(crate) trait BlockDevice {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()>;
+}
+
+impl BlockDevice for File {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        Ok(self.read_exact_at(buf, offset)?)
+    }
+}
+
+/// A partition seen as a read only stream, as needed by the FAT driver
+struct PartitionReader<'a> {
+    disk: &'a dyn BlockDevice,
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl Read for PartitionReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = (buf.len() as u64).min(self.len.saturating_sub(self.position)) as usize;
+        self.disk
+            .read_at(self.start + self.position, &mut buf[..length])
+            .map_err(io::Error::other)?;
+        self.position += length as u64;
+        Ok(length)
+    }
+}
+
+impl Write for PartitionReader<'_> {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "Disk images are opened read only",
+        ))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for PartitionReader<'_> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| io::Error::other("Invalid seek"))?;
+        Ok(self.position)
+    }
+}
+
+/// Copies a FAT directory tree
+fn copy_fat_dir<T: fatfs::ReadWriteSeek>(
+    dir: &fatfs::Dir<T>,
+    fs: &mut MemoryFs,
+    target: &Path,
+) -> Result<()> {
+    fs.insert_dir(target);
+    for entry in dir.iter() {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == "." || name == ".." {
+            continue;
+        }
+        if entry.is_dir() {
+            copy_fat_dir(&entry.to_dir(), fs, &target.join(&name))?;
+        } else {
+            let mut data = vec![];
+            entry.to_file().read_to_end(&mut data)?;
+            fs.insert_file(&target.join(&name), data);
+        }
+    }
+    Ok(())
+}
+
+/// Copies the EFI directory of the FAT file system stored at the given range
+fn copy_esp(disk: &dyn BlockDevice, start: u64, len: u64, root: &mut MemoryFs) -> Result<()> {
+    let reader = PartitionReader {
+        disk,
+        start,
+        len,
+        position: 0,
+    };
+    let fs = fatfs::FileSystem::new(reader, fatfs::FsOptions::new())
+        .context("Failed to open the EFI system partition")?;
+    let efi = fs.root_dir().open_dir("EFI")?;
+    copy_fat_dir(&efi, root, &Path::new(ESP_TARGET).join("EFI"))
+}
+
+fn extract_esp(disk: &dyn BlockDevice, gpt: &Gpt, root: &mut MemoryFs) -> Result<()> {
+    let esp = gpt
+        .partitions
+        .iter()
+        .find(|p| p.type_guid() == EFI_SYSTEM_PARTITION_GUID)
+        .ok_or_else(|| anyhow!("No EFI system partition found"))?;
+    let (start, len) = gpt.partition_range(esp);
+    copy_esp(disk, start, len, root)
+}
+
+/// Parses a Boot Loader Specification entry into its keys and values
+fn parse_bls_entry(entry: &str) -> Vec<(&str, &str)> {
+    entry
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| l.split_once(char::is_whitespace))
+        .map(|(key, value)| (key, value.trim()))
+        .collect()
+}
+
+/// Compares two strings as versions: digit runs are compared by value, so
+/// that `ostree-10` comes after `ostree-2`, and anything else by byte value
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    fn runs(mut s: &str) -> Vec<&str> {
+        let mut runs = Vec::new();
+        while let Some(first) = s.chars().next() {
+            let end = s
+                .find(|c: char| c.is_ascii_digit() != first.is_ascii_digit())
+                .unwrap_or(s.len());
+            runs.push(&s[..end]);
+            s = &s[end..];
+        }
+        runs
+    }
+
+    for (a, b) in runs(a).into_iter().zip(runs(b)) {
+        let ordering = if a.starts_with(|c: char| c.is_ascii_digit())
+            && b.starts_with(|c: char| c.is_ascii_digit())
+        {
+            // Compare the significant digits without overflowing
+            let (a, b) = (a.trim_start_matches('0'), b.trim_start_matches('0'));
+            a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+        } else {
+            a.cmp(b)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    runs(a).len().cmp(&runs(b).len())
+}
+
+/// Copies the kernel and initramfs of the last Boot Loader Specification
+/// entry, by version order, which is the default one for ostree based systems
+fn extract_kernel<D: BlockDevice + ?Sized>(fs: &Ext4<D>, root: &mut MemoryFs) -> Result<bool> {
+    for entries_path in BLS_ENTRIES_PATHS {
+        let Ok(dir) = fs.lookup(entries_path) else {
+            continue;
+        };
+        let mut entries: Vec<_> = fs
+            .read_dir(&dir)?
+            .into_iter()
+            .filter(|e| e.name.ends_with(".conf"))
+            .collect();
+        entries.sort_by(|a, b| compare_versions(&a.name, &b.name));
+        let Some(entry) = entries.last() else {
+            continue;
+        };
+
+        let data = fs.read(&fs.inode(entry.inode)?)?;
+        let data = String::from_utf8_lossy(&data);
+        let keys = parse_bls_entry(&data);
+        let value = |name: &str| keys.iter().find(|(key, _)| *key == name).map(|(_, v)| *v);
+        let linux =
+            value("linux").ok_or_else(|| anyhow!("No kernel in boot entry {}", entry.name))?;
+
+        // Kernel paths are relative to the partition holding the entries
+        let prefix = entries_path.trim_end_matches("loader/entries");
+        let kernel = fs.read(&fs.lookup(&format!("{prefix}{linux}"))?)?;
+        let file_name = Path::new(linux).file_name().unwrap_or_default();
+        let file_name = file_name.to_string_lossy();
+        let version = file_name.strip_prefix("vmlinuz-").unwrap_or(&file_name);
+        let target = Path::new(KERNELS_TARGET).join(version);
+        root.insert_file(&target.join("vmlinuz"), kernel);
+        if let Some(initrd) = value("initrd") {
+            let initrd = fs.read(&fs.lookup(&format!("{prefix}{initrd}"))?)?;
+            root.insert_file(&target.join(INITRD_FILE_NAME), initrd);
+        }
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Opens a raw or qcow2 disk image
+fn open_disk(path: &str) -> Result<Box<dyn BlockDevice>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {path}"))?;
+    let mut magic = [0u8; 4];
+    file.read_exact_at(&mut magic, 0)?;
+    Ok(if magic == QCOW2_MAGIC {
+        Box::new(Qcow2Image::open(file)?)
+    } else {
+        Box::new(file)
+    })
+}
+
+/// Reads the partition table of a raw or qcow2 disk image, without looking
+/// at its partitions
+pub fn read_gpt(path: &str) -> Result<Gpt> {
+    Gpt::read(open_disk(path)?.as_ref())
+}
+
+pub struct DiskImage {
+    gpt: Gpt,
+    fs: MemoryFs,
+}
+
+impl DiskImage {
+    /// Returns whether a path is a qcow2 image or a raw disk image with a
+    /// GUID partition table
+    pub fn is_disk_image(source: &str) -> bool {
+        let Ok(file) = File::open(source) else {
+            return false;
+        };
+        if !file.metadata().is_ok_and(|m| m.is_file()) {
+            return false;
+        }
+        let mut magic = [0u8; 4];
+        if file.read_exact_at(&mut magic, 0).is_ok() && magic == QCOW2_MAGIC {
+            return true;
+        }
+        Gpt::read(&file).is_ok()
+    }
+
+    pub fn open(path: &str) -> Result<DiskImage> {
+        let disk = open_disk(path)?;
+        let gpt = Gpt::read(disk.as_ref())?;
+        let mut fs = MemoryFs::new();
+        extract_esp(disk.as_ref(), &gpt, &mut fs)?;
+        for partition in &gpt.partitions {
+            let (start, _) = gpt.partition_range(partition);
+            let Ok(ext4) = Ext4::open(disk.as_ref(), start) else {
+                continue;
+            };
+            if extract_kernel(&ext4, &mut fs)? {
+                break;
+            }
+        }
+        Ok(DiskImage { gpt, fs })
+    }
+
+    /// Partition table of the disk
+    pub fn gpt(&self) -> &Gpt {
+        &self.gpt
+    }
+
+    /// Root filesystem holding the boot files
+    pub fn into_fs(self) -> MemoryFs {
+        self.fs
+    }
+}
+
+/// Returns the version of an x86 kernel image, from its boot protocol header
+fn kernel_version(kernel: &[u8]) -> Option<String> {
+    let pointer = kernel.get(KERNEL_VERSION_OFFSET..KERNEL_VERSION_OFFSET + 2)?;
+    let offset = u16::from_le_bytes([pointer[0], pointer[1]]) as usize + SETUP_HEADER_BASE;
+    let version = kernel.get(offset..)?;
+    let version = &version[..version.iter().position(|b| *b == 0)?];
+    let version = String::from_utf8_lossy(version);
+    version.split_whitespace().next().map(String::from)
+}
+
+pub struct LiveIso {
+    fs: MemoryFs,
+}
+
+impl LiveIso {
+    /// Returns whether a path is an ISO 9660 image
+    pub fn is_live_iso(source: &str) -> bool {
+        File::open(source).is_ok_and(|file| iso9660::is_iso9660(&file))
+    }
+
+    pub fn open(path: &str) -> Result<LiveIso> {
+        let file = File::open(path).with_context(|| format!("Failed to open {path}"))?;
+        let iso = Iso9660::open(&file)?;
+        let mut fs = MemoryFs::new();
+        let (start, len) = iso.efi_boot_image_range()?;
+        copy_esp(&file, start, len, &mut fs)?;
+
+        let config = fs
+            .glob(&format!("/{ESP_TARGET}/{GRUB_CONFIG_GLOB}"))
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No GRUB configuration in the EFI boot image"))?;
+        let config = String::from_utf8_lossy(&fs.read(&config)?).into_owned();
+        let (linux, initrds) = match grub::default_entry(&config) {
+            Some(entry) if !entry.linux.is_empty() => (entry.linux, entry.initrds),
+            _ => (LIVE_KERNEL_PATH.into(), vec![LIVE_INITRD_PATH.into()]),
+        };
+        for file in std::iter::once(&linux).chain(&initrds) {
+            let data = iso.read(&iso.lookup(file)?)?;
+            fs.insert_file(Path::new(file), data);
+        }
+
+        // The kernel and first initrd are also linked where they are looked
+        // for in a root filesystem
+        let kernel = Path::new("/").join(linux.trim_start_matches('/'));
+        let version = kernel_version(&fs.read(&kernel)?).unwrap_or_else(|| "live".into());
+        let modules = Path::new(KERNELS_TARGET).join(version);
+        fs.insert_symlink(&modules.join("vmlinuz"), &kernel);
+        if let Some(initrd) = initrds.first() {
+            let initrd = Path::new("/").join(initrd.trim_start_matches('/'));
+            fs.insert_symlink(&modules.join(INITRD_FILE_NAME), &initrd);
+        }
+        Ok(LiveIso { fs })
+    }
+
+    /// Root filesystem holding the boot files, whose root is also the GRUB
+    /// root device
+    pub fn into_fs(self) -> MemoryFs {
+        self.fs
+    }
+}
+
+#[cfg(test)]
+impl BlockDevice for [u8] {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let data = usize::try_from(offset)
+            .ok()
+            .and_then(|start| self.get(start..start.checked_add(buf.len())?))
+            .ok_or_else(|| anyhow!("Read beyond the end of the disk"))?;
+        buf.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bls_entries() {
+        let entry = "title Fedora CoreOS 42 (ostree:0)\n\
+                     # comment\n\
+                     linux /ostree/fedora-coreos-abc/vmlinuz-6.14.0-63.fc42.x86_64\n\
+                     options  root=UUID=1234 rw\n";
+        assert_eq!(
+            parse_bls_entry(entry),
+            vec![
+                ("title", "Fedora CoreOS 42 (ostree:0)"),
+                (
+                    "linux",
+                    "/ostree/fedora-coreos-abc/vmlinuz-6.14.0-63.fc42.x86_64"
+                ),
+                ("options", "root=UUID=1234 rw"),
+            ]
+        );
+    }
+
+    #[test]
+    fn bls_entries_order() {
+        let mut entries = vec![
+            "ostree-10-fedora-coreos.conf",
+            "ostree-2-fedora-coreos.conf",
+            "ostree-1-fedora-coreos.conf",
+        ];
+        entries.sort_by(|a, b| compare_versions(a, b));
+        assert_eq!(
+            entries,
+            vec![
+                "ostree-1-fedora-coreos.conf",
+                "ostree-2-fedora-coreos.conf",
+                "ostree-10-fedora-coreos.conf",
+            ]
+        );
+    }
+}
@@ -0,0 +1,427 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Minimal read only ext2/3/4 file system support: path lookup, directory
+//! listing and file reading, through extents or block maps. The journal is
+//! not replayed.
+
+use super::BlockDevice;
+use anyhow::{Result, anyhow, bail};
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const EXT4_MAGIC: u16 = 0xef53;
+const ROOT_INODE: u32 = 2;
+
+const INCOMPAT_META_BG: u32 = 0x10;
+const INCOMPAT_64BIT: u32 = 0x80;
+const INCOMPAT_INLINE_DATA: u32 = 0x8000;
+
+const INODE_FLAG_EXTENTS: u32 = 0x8_0000;
+const INODE_FLAG_INLINE_DATA: u32 = 0x1000_0000;
+const EXTENT_MAGIC: u16 = 0xf30a;
+/// Extents longer than this are uninitialized, and read as zeros
+const EXTENT_MAX_INIT_LEN: u16 = 32768;
+
+const S_IFMT: u16 = 0xf000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFLNK: u16 = 0xa000;
+
+/// Size of the i_block field, holding the block map, the extent tree root or
+/// fast symlink targets
+const I_BLOCK_SIZE: usize = 60;
+const DIRECT_BLOCKS: usize = 12;
+const MAX_SYMLINKS: usize = 40;
+const EXTENT_HEADER_SIZE: usize = 12;
+const EXTENT_ENTRY_SIZE: usize = 12;
+/// Maximum depth of an extent tree
+const EXTENT_MAX_DEPTH: u16 = 5;
+/// Largest block size, 64 KiB, as a shift of 1 KiB
+const MAX_LOG_BLOCK_SIZE: u32 = 6;
+/// Files larger than this are not read, as they are read in memory
+const MAX_FILE_SIZE: u64 = 1 << 30;
+
+fn le_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+pub struct Inode {
+    mode: u16,
+    size: u64,
+    flags: u32,
+    block: [u8; I_BLOCK_SIZE],
+}
+
+impl Inode {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+}
+
+pub struct DirEntry {
+    pub inode: u32,
+    pub name: String,
+}
+
+pub struct Ext4<'a, D: BlockDevice + ?Sized> {
+    disk: &'a D,
+    /// Offset of the file system on the disk
+    offset: u64,
+    block_size: u64,
+    inodes_per_group: u32,
+    inode_size: u64,
+    desc_size: u64,
+    first_data_block: u64,
+    is_64bit: bool,
+}
+
+impl<'a, D: BlockDevice + ?Sized> Ext4<'a, D> {
+    pub fn open(disk: &'a D, offset: u64) -> Result<Ext4<'a, D>> {
+        let mut sb = [0u8; 1024];
+        disk.read_at(offset + SUPERBLOCK_OFFSET, &mut sb)?;
+        if le_u16(&sb, 56) != EXT4_MAGIC {
+            bail!("Not an ext2/3/4 file system");
+        }
+        let incompat = le_u32(&sb, 96);
+        if incompat & (INCOMPAT_META_BG | INCOMPAT_INLINE_DATA) != 0 {
+            bail!("Unsupported ext4 features {incompat:#x}");
+        }
+        let is_64bit = incompat & INCOMPAT_64BIT != 0;
+        let rev_level = le_u32(&sb, 76);
+        let log_block_size = le_u32(&sb, 24);
+        if log_block_size > MAX_LOG_BLOCK_SIZE {
+            bail!("Invalid ext4 block size");
+        }
+        let block_size = 1024 << log_block_size;
+        let inodes_per_group = le_u32(&sb, 40);
+        if inodes_per_group == 0 {
+            bail!("Invalid ext4 inodes per group count");
+        }
+        let inode_size = match rev_level {
+            0 => 128,
+            _ => le_u16(&sb, 88) as u64,
+        };
+        if !(128..=block_size).contains(&inode_size) {
+            bail!("Invalid ext4 inode size {inode_size}");
+        }
+        let desc_size = match is_64bit {
+            true => le_u16(&sb, 254) as u64,
+            false => 32,
+        };
+        if !(32..=block_size).contains(&desc_size) {
+            bail!("Invalid ext4 group descriptor size {desc_size}");
+        }
+        Ok(Ext4 {
+            disk,
+            offset,
+            block_size,
+            inodes_per_group,
+            inode_size,
+            desc_size,
+            first_data_block: le_u32(&sb, 20) as u64,
+            is_64bit,
+        })
+    }
+
+    /// Returns the offset on the disk of a position of the file system
+    fn disk_offset(&self, block: u64, offset: u64) -> Result<u64> {
+        block
+            .checked_mul(self.block_size)
+            .and_then(|o| o.checked_add(offset))
+            .and_then(|o| o.checked_add(self.offset))
+            .ok_or_else(|| anyhow!("Invalid ext4 block {block}"))
+    }
+
+    fn read_block(&self, block: u64) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; self.block_size as usize];
+        self.disk.read_at(self.disk_offset(block, 0)?, &mut data)?;
+        Ok(data)
+    }
+
+    pub fn inode(&self, number: u32) -> Result<Inode> {
+        let Some(index) = number.checked_sub(1) else {
+            bail!("Invalid inode number 0");
+        };
+        let group = (index / self.inodes_per_group) as u64;
+        let index = (index % self.inodes_per_group) as u64;
+
+        let desc_offset = self.disk_offset(self.first_data_block + 1, group * self.desc_size)?;
+        let mut desc = vec![0u8; self.desc_size as usize];
+        self.disk.read_at(desc_offset, &mut desc)?;
+        let mut inode_table = le_u32(&desc, 8) as u64;
+        if self.is_64bit && self.desc_size >= 64 {
+            inode_table |= (le_u32(&desc, 0x28) as u64) << 32;
+        }
+
+        let mut raw = [0u8; 160];
+        let length = (self.inode_size as usize).min(raw.len());
+        self.disk.read_at(
+            self.disk_offset(inode_table, index * self.inode_size)?,
+            &mut raw[..length],
+        )?;
+        Ok(Inode {
+            mode: le_u16(&raw, 0),
+            size: le_u32(&raw, 4) as u64 | ((le_u32(&raw, 108) as u64) << 32),
+            flags: le_u32(&raw, 32),
+            block: raw[40..40 + I_BLOCK_SIZE].try_into().unwrap(),
+        })
+    }
+
+    /// Collects the (logical block, physical block, length) extents of an
+    /// extent tree node. Uninitialized extents have no physical block.
+    /// `max_depth` is the depth the node may have at most.
+    fn extents(
+        &self,
+        node: &[u8],
+        max_depth: u16,
+        extents: &mut Vec<(u64, Option<u64>, u64)>,
+    ) -> Result<()> {
+        if node.len() < EXTENT_HEADER_SIZE || le_u16(node, 0) != EXTENT_MAGIC {
+            bail!("Invalid ext4 extent header");
+        }
+        let entries = le_u16(node, 2) as usize;
+        let depth = le_u16(node, 6);
+        if depth > max_depth {
+            bail!("Invalid ext4 extent tree depth {depth}");
+        }
+        let entries = node
+            .get(EXTENT_HEADER_SIZE..EXTENT_HEADER_SIZE + entries * EXTENT_ENTRY_SIZE)
+            .ok_or_else(|| anyhow!("Invalid ext4 extent entry count {entries}"))?;
+        for e in entries.chunks_exact(EXTENT_ENTRY_SIZE) {
+            if depth == 0 {
+                let length = le_u16(e, 4);
+                let start = ((le_u16(e, 6) as u64) << 32) | le_u32(e, 8) as u64;
+                if length > EXTENT_MAX_INIT_LEN {
+                    let length = (length - EXTENT_MAX_INIT_LEN) as u64;
+                    extents.push((le_u32(e, 0) as u64, None, length));
+                } else {
+                    extents.push((le_u32(e, 0) as u64, Some(start), length as u64));
+                }
+            } else {
+                let leaf = ((le_u16(e, 8) as u64) << 32) | le_u32(e, 4) as u64;
+                self.extents(&self.read_block(leaf)?, depth - 1, extents)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects the physical blocks of an indirect block, of the given depth,
+    /// until `count` blocks are known
+    fn indirect_blocks(
+        &self,
+        block: u64,
+        depth: u32,
+        count: u64,
+        blocks: &mut Vec<u64>,
+    ) -> Result<()> {
+        if block == 0 {
+            return Ok(());
+        }
+        let data = self.read_block(block)?;
+        for entry in data.chunks_exact(4) {
+            if blocks.len() as u64 >= count {
+                break;
+            }
+            let child = le_u32(entry, 0) as u64;
+            if depth == 0 {
+                blocks.push(child);
+            } else {
+                self.indirect_blocks(child, depth - 1, count, blocks)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read(&self, inode: &Inode) -> Result<Vec<u8>> {
+        if inode.size > MAX_FILE_SIZE {
+            bail!("File of {} bytes is too large", inode.size);
+        }
+        let mut data = vec![0u8; inode.size as usize];
+        if inode.flags & INODE_FLAG_INLINE_DATA != 0
+            || (inode.is_symlink() && inode.size < I_BLOCK_SIZE as u64)
+        {
+            let length = data.len().min(I_BLOCK_SIZE);
+            data[..length].copy_from_slice(&inode.block[..length]);
+            return Ok(data);
+        }
+
+        let block_count = inode.size.div_ceil(self.block_size);
+        let mut extents = vec![];
+        if inode.flags & INODE_FLAG_EXTENTS != 0 {
+            self.extents(&inode.block, EXTENT_MAX_DEPTH, &mut extents)?;
+        } else {
+            let mut blocks: Vec<u64> = (0..DIRECT_BLOCKS)
+                .map(|i| le_u32(&inode.block, i * 4) as u64)
+                .collect();
+            for (depth, i) in (DIRECT_BLOCKS..DIRECT_BLOCKS + 3).enumerate() {
+                if blocks.len() as u64 >= block_count {
+                    break;
+                }
+                let block = le_u32(&inode.block, i * 4) as u64;
+                self.indirect_blocks(block, depth as u32, block_count, &mut blocks)?;
+            }
+            extents.extend(
+                blocks
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, b)| (i as u64, (b != 0).then_some(b), 1)),
+            );
+        }
+
+        for (logical, physical, length) in extents {
+            let Some(physical) = physical else {
+                continue;
+            };
+            for i in 0..length {
+                let start = (logical + i) * self.block_size;
+                if start >= data.len() as u64 {
+                    break;
+                }
+                let start = start as usize;
+                let end = (start + self.block_size as usize).min(data.len());
+                let block = self.read_block(physical + i)?;
+                data[start..end].copy_from_slice(&block[..end - start]);
+            }
+        }
+        Ok(data)
+    }
+
+    pub fn read_dir(&self, inode: &Inode) -> Result<Vec<DirEntry>> {
+        if !inode.is_dir() {
+            bail!("Not a directory");
+        }
+        let data = self.read(inode)?;
+        let mut entries = vec![];
+        let mut offset = 0;
+        while offset + 8 <= data.len() {
+            let number = le_u32(&data, offset);
+            let rec_len = le_u16(&data, offset + 4) as usize;
+            let name_len = data[offset + 6] as usize;
+            if rec_len < 8 || offset + 8 + name_len > data.len() {
+                break;
+            }
+            if number != 0 {
+                let name = &data[offset + 8..offset + 8 + name_len];
+                entries.push(DirEntry {
+                    inode: number,
+                    name: String::from_utf8_lossy(name).into_owned(),
+                });
+            }
+            offset += rec_len;
+        }
+        Ok(entries)
+    }
+
+    /// Looks a path up from the root directory, following symbolic links
+    pub fn lookup(&self, path: &str) -> Result<Inode> {
+        let mut pending: Vec<String> = path.split('/').rev().map(String::from).collect();
+        let mut parents = vec![self.inode(ROOT_INODE)?];
+        let mut links = 0;
+        while let Some(name) = pending.pop() {
+            match name.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    if parents.len() > 1 {
+                        parents.pop();
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            let number = self
+                .read_dir(parents.last().unwrap())?
+                .into_iter()
+                .find(|e| e.name == name)
+                .ok_or_else(|| anyhow!("{path} not found"))?
+                .inode;
+            let inode = self.inode(number)?;
+            if !inode.is_symlink() {
+                parents.push(inode);
+                continue;
+            }
+            links += 1;
+            if links > MAX_SYMLINKS {
+                bail!("Too many levels of symbolic links in {path}");
+            }
+            let target = String::from_utf8_lossy(&self.read(&inode)?).into_owned();
+            if target.starts_with('/') {
+                parents.truncate(1);
+            }
+            pending.extend(target.split('/').rev().map(String::from));
+        }
+        Ok(parents.pop().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INODE_TABLE_BLOCK: usize = 5;
+    const DATA_BLOCK: usize = 10;
+    const FILE_INODE: u32 = 12;
+
+    /// A file system of 1 KiB blocks holding a single file, its inode
+    /// pointing to its data through an extent tree
+    fn image() -> Vec<u8> {
+        let mut disk = vec![0u8; 16 * 1024];
+        let sb = &mut disk[1024..2048];
+        sb[20..24].copy_from_slice(&1u32.to_le_bytes());
+        sb[40..44].copy_from_slice(&16u32.to_le_bytes());
+        sb[56..58].copy_from_slice(&EXT4_MAGIC.to_le_bytes());
+        sb[76..80].copy_from_slice(&1u32.to_le_bytes());
+        sb[88..90].copy_from_slice(&128u16.to_le_bytes());
+        disk[2048 + 8..2048 + 12].copy_from_slice(&(INODE_TABLE_BLOCK as u32).to_le_bytes());
+
+        let inode = INODE_TABLE_BLOCK * 1024 + (FILE_INODE as usize - 1) * 128;
+        let inode = &mut disk[inode..inode + 128];
+        inode[0..2].copy_from_slice(&0x81a4u16.to_le_bytes());
+        inode[4..8].copy_from_slice(&10u32.to_le_bytes());
+        inode[32..36].copy_from_slice(&INODE_FLAG_EXTENTS.to_le_bytes());
+        let header = [EXTENT_MAGIC, 1, 4, 0];
+        for (i, field) in header.iter().enumerate() {
+            inode[40 + i * 2..42 + i * 2].copy_from_slice(&field.to_le_bytes());
+        }
+        inode[56..58].copy_from_slice(&1u16.to_le_bytes());
+        inode[60..64].copy_from_slice(&(DATA_BLOCK as u32).to_le_bytes());
+
+        disk[DATA_BLOCK * 1024..DATA_BLOCK * 1024 + 10].copy_from_slice(b"hello ext4");
+        disk
+    }
+
+    #[test]
+    fn read_extent_file() {
+        let disk = image();
+        let fs = Ext4::open(&disk[..], 0).unwrap();
+        assert_eq!(
+            fs.read(&fs.inode(FILE_INODE).unwrap()).unwrap(),
+            b"hello ext4"
+        );
+        assert!(fs.inode(0).is_err());
+    }
+
+    #[test]
+    fn corrupted_fields() {
+        let mut disk = image();
+        disk[1024 + 40..1024 + 44].fill(0);
+        assert!(Ext4::open(&disk[..], 0).is_err());
+
+        let disk = image();
+        let fs = Ext4::open(&disk[..], 0).unwrap();
+        let mut inode = fs.inode(FILE_INODE).unwrap();
+        // More extent entries than i_block holds
+        inode.block[2..4].copy_from_slice(&5u16.to_le_bytes());
+        assert!(fs.read(&inode).is_err());
+        inode.size = u64::MAX;
+        assert!(fs.read(&inode).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! GUID Partition Table parsing.
+
+use super::BlockDevice;
+use crate::uefi::guid_from_le_bytes;
+use crate::{Part, tcg};
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::{Uuid, uuid};
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Sector sizes the primary GPT header is looked for with
+const SECTOR_SIZES: [u64; 2] = [512, 4096];
+/// Size of the header fields defined by the UEFI specification
+const GPT_HEADER_MIN_SIZE: usize = 92;
+
+pub const EFI_SYSTEM_PARTITION_GUID: Uuid = uuid!("c12a7328-f81f-11d2-ba4b-00a0c93ec93b");
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn le_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Partition {
+    /// Partition number, starting at 1
+    pub number: u32,
+    pub type_guid: String,
+    pub guid: String,
+    pub name: String,
+    pub first_lba: u64,
+    pub last_lba: u64,
+    pub attributes: u64,
+    /// Raw EFI_PARTITION_ENTRY
+    #[serde(skip)]
+    raw: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Gpt {
+    pub sector_size: u64,
+    pub disk_guid: String,
+    pub partitions: Vec<Partition>,
+    /// Raw EFI_PARTITION_TABLE_HEADER
+    #[serde(skip)]
+    header: Vec<u8>,
+}
+
+impl Partition {
+    pub fn type_guid(&self) -> Uuid {
+        Uuid::parse_str(&self.type_guid).unwrap()
+    }
+}
+
+impl Gpt {
+    /// Reads the primary GPT of a disk
+    pub(crate) fn read<D: BlockDevice + ?Sized>(disk: &D) -> Result<Gpt> {
+        for sector_size in SECTOR_SIZES {
+            let mut lba1 = vec![0u8; sector_size as usize];
+            if disk.read_at(sector_size, &mut lba1).is_err() || &lba1[..8] != GPT_SIGNATURE {
+                continue;
+            }
+            return Gpt::parse(disk, sector_size, &lba1);
+        }
+        bail!("No GUID partition table found")
+    }
+
+    fn parse<D: BlockDevice + ?Sized>(disk: &D, sector_size: u64, lba1: &[u8]) -> Result<Gpt> {
+        let header_size = le_u32(lba1, 12) as usize;
+        if !(GPT_HEADER_MIN_SIZE..=lba1.len()).contains(&header_size) {
+            bail!("Invalid GPT header size {header_size}");
+        }
+        let entries_lba = le_u64(lba1, 72);
+        let entry_count = le_u32(lba1, 80) as usize;
+        let entry_size = le_u32(lba1, 84) as usize;
+        if entry_size < 128 || entry_count * entry_size > 1024 * 1024 {
+            bail!("Invalid GPT partition entries");
+        }
+
+        let entries_offset = entries_lba
+            .checked_mul(sector_size)
+            .ok_or_else(|| anyhow!("Invalid GPT partition entries LBA {entries_lba}"))?;
+        let mut entries = vec![0u8; entry_count * entry_size];
+        disk.read_at(entries_offset, &mut entries)?;
+        let partitions = entries
+            .chunks_exact(entry_size)
+            .enumerate()
+            .filter(|(_, e)| e[..16].iter().any(|b| *b != 0))
+            .map(|(index, e)| {
+                let name: Vec<u16> = e[56..128]
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|c| *c != 0)
+                    .collect();
+                let (first_lba, last_lba) = (le_u64(e, 32), le_u64(e, 40));
+                let end = last_lba
+                    .checked_add(1)
+                    .and_then(|lba| lba.checked_mul(sector_size));
+                if first_lba > last_lba || end.is_none() {
+                    bail!("Invalid range of GPT partition {}", index + 1);
+                }
+                Ok(Partition {
+                    number: index as u32 + 1,
+                    type_guid: guid_from_le_bytes(e[0..16].try_into().unwrap()).to_string(),
+                    guid: guid_from_le_bytes(e[16..32].try_into().unwrap()).to_string(),
+                    name: String::from_utf16_lossy(&name),
+                    first_lba,
+                    last_lba,
+                    attributes: le_u64(e, 48),
+                    raw: e.to_vec(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Gpt {
+            sector_size,
+            disk_guid: guid_from_le_bytes(lba1[56..72].try_into().unwrap()).to_string(),
+            partitions,
+            header: lba1[..header_size].to_vec(),
+        })
+    }
+
+    /// Encodes the UEFI_GPT_DATA structure firmwares measure, holding the
+    /// header and the used partition entries
+    pub fn uefi_gpt_data(&self) -> Vec<u8> {
+        let mut data = self.header.clone();
+        data.extend((self.partitions.len() as u64).to_le_bytes());
+        for partition in &self.partitions {
+            data.extend(&partition.raw);
+        }
+        data
+    }
+
+    /// The EV_EFI_GPT_EVENT measured into PCR 5 when booting from the disk
+    pub fn event(&self) -> Part {
+        let data = self.uefi_gpt_data();
+        Part {
+            event_data: Some(hex::encode(&data)),
+            ..Part::new(tcg::EV_EFI_GPT_EVENT, &Sha256::digest(&data))
+        }
+    }
+
+    /// Returns the byte range of a partition on the disk, as its start and
+    /// length. Partition ranges are checked when the table is read.
+    pub fn partition_range(&self, partition: &Partition) -> (u64, u64) {
+        let start = partition.first_lba * self.sector_size;
+        let end = (partition.last_lba + 1) * self.sector_size;
+        (start, end - start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uefi::guid_to_le_bytes;
+
+    const LINUX_FILESYSTEM_GUID: Uuid = uuid!("0fc63daf-8483-4772-8e79-3d69d8477de4");
+
+    fn header() -> Vec<u8> {
+        let mut header = GPT_SIGNATURE.to_vec();
+        for field in [0x10000u32, 92, 0, 0] {
+            header.extend(field.to_le_bytes());
+        }
+        for lba in [1u64, 0x1fff, 34, 0x1fde] {
+            header.extend(lba.to_le_bytes());
+        }
+        header.extend(guid_to_le_bytes(&uuid!(
+            "5a1b6c2d-3e4f-4a5b-8c6d-7e8f90a1b2c3"
+        )));
+        header.extend(2u64.to_le_bytes());
+        for field in [4u32, 128, 0] {
+            header.extend(field.to_le_bytes());
+        }
+        header
+    }
+
+    fn entry(type_guid: &Uuid, guid: &Uuid, first_lba: u64, last_lba: u64, name: &str) -> Vec<u8> {
+        let mut entry = guid_to_le_bytes(type_guid);
+        entry.extend(guid_to_le_bytes(guid));
+        for field in [first_lba, last_lba, 0] {
+            entry.extend(field.to_le_bytes());
+        }
+        entry.extend(name.encode_utf16().flat_map(u16::to_le_bytes));
+        entry.resize(128, 0);
+        entry
+    }
+
+    fn disk(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut disk = vec![0u8; 512 * 34];
+        disk[512..512 + GPT_HEADER_MIN_SIZE].copy_from_slice(&header());
+        for (index, entry) in entries.iter().enumerate() {
+            let offset = 1024 + index * 128;
+            disk[offset..offset + 128].copy_from_slice(entry);
+        }
+        disk
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let esp = entry(
+            &EFI_SYSTEM_PARTITION_GUID,
+            &uuid!("11111111-2222-4333-8444-555555555555"),
+            2048,
+            4095,
+            "EFI-SYSTEM",
+        );
+        let root = entry(
+            &LINUX_FILESYSTEM_GUID,
+            &uuid!("66666666-7777-4888-9999-aaaaaaaaaaaa"),
+            4096,
+            8157,
+            "root",
+        );
+        let disk = disk(&[esp.clone(), vec![0; 128], root.clone()]);
+
+        let gpt = Gpt::read(&disk[..]).unwrap();
+        assert_eq!(gpt.sector_size, 512);
+        assert_eq!(gpt.disk_guid, "5a1b6c2d-3e4f-4a5b-8c6d-7e8f90a1b2c3");
+        let partitions: Vec<(u32, &str)> = gpt
+            .partitions
+            .iter()
+            .map(|p| (p.number, p.name.as_str()))
+            .collect();
+        assert_eq!(partitions, [(1, "EFI-SYSTEM"), (3, "root")]);
+        assert_eq!(gpt.partitions[0].type_guid(), EFI_SYSTEM_PARTITION_GUID);
+        assert_eq!(
+            gpt.partition_range(&gpt.partitions[0]),
+            (2048 * 512, 2048 * 512)
+        );
+
+        let mut data = header();
+        data.extend(2u64.to_le_bytes());
+        data.extend(esp);
+        data.extend(root);
+        assert_eq!(gpt.uefi_gpt_data(), data);
+        assert_eq!(
+            gpt.event().hash,
+            "9152b90488a5886b77c6653ce41654616cfc1e083178f8f99c7ad622c1adaaae"
+        );
+    }
+
+    #[test]
+    fn invalid_partition_range() {
+        let entry = entry(&LINUX_FILESYSTEM_GUID, &Uuid::nil(), 4096, 2048, "root");
+        assert!(Gpt::read(&disk(&[entry])[..]).is_err());
+    }
+}
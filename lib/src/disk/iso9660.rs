@@ -0,0 +1,353 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Minimal read only ISO 9660 support: path lookup with Rock Ridge names,
+//! file reading and El Torito EFI boot image lookup.
+
+use super::BlockDevice;
+use anyhow::{Result, anyhow, bail};
+
+const SECTOR_SIZE: u64 = 2048;
+const FIRST_VOLUME_DESCRIPTOR: u64 = 16;
+const STANDARD_IDENTIFIER: &[u8; 5] = b"CD001";
+
+const BOOT_RECORD: u8 = 0;
+const PRIMARY_VOLUME_DESCRIPTOR: u8 = 1;
+const TERMINATOR: u8 = 255;
+const EL_TORITO_IDENTIFIER: &[u8] = b"EL TORITO SPECIFICATION";
+
+const PLATFORM_EFI: u8 = 0xef;
+const BOOTABLE: u8 = 0x88;
+const SECTION_HEADER: u8 = 0x90;
+const FINAL_SECTION_HEADER: u8 = 0x91;
+const EXTENSION_ENTRY: u8 = 0x44;
+/// Unit of the El Torito sector counts
+const VIRTUAL_SECTOR_SIZE: u64 = 512;
+
+const FLAG_DIRECTORY: u8 = 0x02;
+/// Size of a directory record without its identifier
+const DIR_RECORD_MIN_SIZE: usize = 33;
+
+fn le_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    extent: u64,
+    size: u64,
+    flags: u8,
+}
+
+impl DirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.flags & FLAG_DIRECTORY != 0
+    }
+
+    fn parse(record: &[u8]) -> Result<DirEntry> {
+        if record.len() <= DIR_RECORD_MIN_SIZE {
+            bail!("Truncated ISO 9660 directory record");
+        }
+        let name_len = record[32] as usize;
+        let identifier = record
+            .get(DIR_RECORD_MIN_SIZE..DIR_RECORD_MIN_SIZE + name_len)
+            .ok_or_else(|| anyhow!("Invalid ISO 9660 identifier length {name_len}"))?;
+        // The system use area follows the padded identifier
+        let system_use = &record[(33 + name_len + (1 - name_len % 2)).min(record.len())..];
+        let name = rock_ridge_name(system_use).unwrap_or_else(|| match identifier {
+            [0] => ".".into(),
+            [1] => "..".into(),
+            _ => {
+                let name = String::from_utf8_lossy(identifier);
+                let name = name.split(';').next().unwrap_or_default();
+                name.strip_suffix('.').unwrap_or(name).into()
+            }
+        });
+        Ok(DirEntry {
+            name,
+            extent: le_u32(record, 2) as u64,
+            size: le_u32(record, 10) as u64,
+            flags: record[25],
+        })
+    }
+}
+
+/// Returns the Rock Ridge alternate name from a system use area
+fn rock_ridge_name(mut system_use: &[u8]) -> Option<String> {
+    let mut name: Option<Vec<u8>> = None;
+    while system_use.len() >= 4 {
+        let len = system_use[2] as usize;
+        if len < 4 || len > system_use.len() {
+            break;
+        }
+        if &system_use[..2] == b"NM" && len >= 5 {
+            name.get_or_insert_default()
+                .extend_from_slice(&system_use[5..len]);
+        }
+        system_use = &system_use[len..];
+    }
+    name.map(|n| String::from_utf8_lossy(&n).into_owned())
+}
+
+/// Returns whether a disk holds an ISO 9660 file system
+pub fn is_iso9660<D: BlockDevice + ?Sized>(disk: &D) -> bool {
+    let mut identifier = [0u8; 5];
+    disk.read_at(FIRST_VOLUME_DESCRIPTOR * SECTOR_SIZE + 1, &mut identifier)
+        .is_ok()
+        && &identifier == STANDARD_IDENTIFIER
+}
+
+pub struct Iso9660<'a, D: BlockDevice + ?Sized> {
+    disk: &'a D,
+    root: DirEntry,
+    boot_catalog: Option<u64>,
+}
+
+impl<'a, D: BlockDevice + ?Sized> Iso9660<'a, D> {
+    pub fn open(disk: &'a D) -> Result<Iso9660<'a, D>> {
+        let mut root = None;
+        let mut boot_catalog = None;
+        let mut descriptor = [0u8; SECTOR_SIZE as usize];
+        for sector in FIRST_VOLUME_DESCRIPTOR.. {
+            disk.read_at(sector * SECTOR_SIZE, &mut descriptor)?;
+            if &descriptor[1..6] != STANDARD_IDENTIFIER {
+                bail!("Not an ISO 9660 file system");
+            }
+            match descriptor[0] {
+                BOOT_RECORD if descriptor[7..].starts_with(EL_TORITO_IDENTIFIER) => {
+                    boot_catalog = Some(le_u32(&descriptor, 0x47) as u64);
+                }
+                PRIMARY_VOLUME_DESCRIPTOR => root = Some(DirEntry::parse(&descriptor[156..190])?),
+                TERMINATOR => break,
+                _ => {}
+            }
+        }
+        Ok(Iso9660 {
+            disk,
+            root: root.ok_or_else(|| anyhow!("No ISO 9660 primary volume descriptor"))?,
+            boot_catalog,
+        })
+    }
+
+    pub fn read(&self, entry: &DirEntry) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; entry.size as usize];
+        self.disk.read_at(entry.extent * SECTOR_SIZE, &mut data)?;
+        Ok(data)
+    }
+
+    pub fn read_dir(&self, dir: &DirEntry) -> Result<Vec<DirEntry>> {
+        if !dir.is_dir() {
+            bail!("{} is not a directory", dir.name);
+        }
+        let data = self.read(dir)?;
+        let mut entries = vec![];
+        // Records do not cross sector boundaries, the rest of a sector being
+        // zero padded
+        for sector in data.chunks(SECTOR_SIZE as usize) {
+            let mut offset = 0;
+            while offset < sector.len() {
+                let len = sector[offset] as usize;
+                if len < 34 || offset + len > sector.len() {
+                    break;
+                }
+                let entry = DirEntry::parse(&sector[offset..offset + len])?;
+                if entry.name != "." && entry.name != ".." {
+                    entries.push(entry);
+                }
+                offset += len;
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Looks a path up from the root directory. Names are compared case
+    /// insensitively, as plain ISO 9660 names are upper case.
+    pub fn lookup(&self, path: &str) -> Result<DirEntry> {
+        let mut entry = self.root.clone();
+        for name in path.split('/').filter(|c| !c.is_empty()) {
+            entry = self
+                .read_dir(&entry)?
+                .into_iter()
+                .find(|e| e.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| anyhow!("{path} not found"))?;
+        }
+        Ok(entry)
+    }
+
+    /// Returns the byte offset and sector count of the El Torito EFI boot
+    /// image
+    fn efi_boot_image(&self) -> Result<(u64, u64)> {
+        let catalog_sector = self
+            .boot_catalog
+            .ok_or_else(|| anyhow!("No El Torito boot record"))?;
+        let mut catalog = [0u8; SECTOR_SIZE as usize];
+        self.disk
+            .read_at(catalog_sector * SECTOR_SIZE, &mut catalog)?;
+        if catalog[0] != 1 || catalog[30..32] != [0x55, 0xaa] {
+            bail!("Invalid El Torito boot catalog");
+        }
+
+        // The default entry uses the platform of the validation entry, and
+        // section headers the platform of the entries following them
+        let image = |entry: &[u8]| {
+            (
+                le_u32(entry, 8) as u64 * SECTOR_SIZE,
+                le_u16(entry, 6) as u64,
+            )
+        };
+        if catalog[1] == PLATFORM_EFI && catalog[32] == BOOTABLE {
+            return Ok(image(&catalog[32..64]));
+        }
+        let mut offset = 64;
+        while offset + 32 <= catalog.len() {
+            let header = &catalog[offset..offset + 32];
+            if header[0] != SECTION_HEADER && header[0] != FINAL_SECTION_HEADER {
+                break;
+            }
+            let platform = header[1];
+            let mut count = le_u16(header, 2);
+            offset += 32;
+            while count > 0 && offset + 32 <= catalog.len() {
+                let entry = &catalog[offset..offset + 32];
+                offset += 32;
+                if entry[0] == EXTENSION_ENTRY {
+                    continue;
+                }
+                if platform == PLATFORM_EFI && entry[0] == BOOTABLE {
+                    return Ok(image(entry));
+                }
+                count -= 1;
+            }
+            if header[0] == FINAL_SECTION_HEADER {
+                break;
+            }
+        }
+        bail!("No El Torito EFI boot image")
+    }
+
+    /// Returns the byte range of the El Torito EFI boot image. Its sector
+    /// count is often too small to cover large images, so the size is read
+    /// from the FAT boot sector.
+    pub fn efi_boot_image_range(&self) -> Result<(u64, u64)> {
+        let (start, sectors) = self.efi_boot_image()?;
+        let mut boot_sector = [0u8; 512];
+        self.disk.read_at(start, &mut boot_sector)?;
+        let bytes_per_sector = le_u16(&boot_sector, 11) as u64;
+        let total_sectors = match le_u16(&boot_sector, 19) {
+            0 => le_u32(&boot_sector, 32) as u64,
+            n => n as u64,
+        };
+        let len = match bytes_per_sector * total_sectors {
+            0 => sectors * VIRTUAL_SECTOR_SIZE,
+            len => len,
+        };
+        Ok((start, len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT_SECTOR: usize = 20;
+    const CATALOG_SECTOR: usize = 19;
+    const BOOT_IMAGE_SECTOR: usize = 22;
+
+    fn record(extent: u32, size: u32, flags: u8, identifier: &[u8], system_use: &[u8]) -> Vec<u8> {
+        let mut record = vec![0u8; DIR_RECORD_MIN_SIZE];
+        record[2..6].copy_from_slice(&extent.to_le_bytes());
+        record[10..14].copy_from_slice(&size.to_le_bytes());
+        record[25] = flags;
+        record[32] = identifier.len() as u8;
+        record.extend(identifier);
+        if identifier.len() % 2 == 0 {
+            record.push(0);
+        }
+        record.extend(system_use);
+        record[0] = record.len() as u8;
+        record
+    }
+
+    fn descriptor(iso: &mut [u8], sector: usize, kind: u8) -> &mut [u8] {
+        let descriptor = &mut iso[sector * 2048..(sector + 1) * 2048];
+        descriptor[0] = kind;
+        descriptor[1..6].copy_from_slice(STANDARD_IDENTIFIER);
+        descriptor[6] = 1;
+        descriptor
+    }
+
+    /// An ISO whose EFI boot image, named with Rock Ridge, is referenced by
+    /// the section entries of its El Torito boot catalog
+    fn iso() -> Vec<u8> {
+        let mut iso = vec![0u8; (BOOT_IMAGE_SECTOR + 1) * 2048];
+        let root = record(ROOT_SECTOR as u32, 2048, FLAG_DIRECTORY, &[0], &[]);
+        descriptor(&mut iso, 16, PRIMARY_VOLUME_DESCRIPTOR)[156..190].copy_from_slice(&root);
+        let boot_record = descriptor(&mut iso, 17, BOOT_RECORD);
+        boot_record[7..7 + EL_TORITO_IDENTIFIER.len()].copy_from_slice(EL_TORITO_IDENTIFIER);
+        boot_record[0x47..0x4b].copy_from_slice(&(CATALOG_SECTOR as u32).to_le_bytes());
+        descriptor(&mut iso, 18, TERMINATOR);
+
+        // BIOS default entry, then a final section of EFI entries
+        let catalog = &mut iso[CATALOG_SECTOR * 2048..];
+        catalog[0] = 1;
+        catalog[30..32].copy_from_slice(&[0x55, 0xaa]);
+        catalog[32] = BOOTABLE;
+        catalog[64] = FINAL_SECTION_HEADER;
+        catalog[65] = PLATFORM_EFI;
+        catalog[66..68].copy_from_slice(&1u16.to_le_bytes());
+        catalog[96] = BOOTABLE;
+        catalog[102..104].copy_from_slice(&4u16.to_le_bytes());
+        catalog[104..108].copy_from_slice(&(BOOT_IMAGE_SECTOR as u32).to_le_bytes());
+
+        let mut name = b"NM".to_vec();
+        name.extend([16, 1, 0]);
+        name.extend(b"efiboot.img");
+        let mut entries = record(ROOT_SECTOR as u32, 2048, FLAG_DIRECTORY, &[0], &[]);
+        entries.extend(record(ROOT_SECTOR as u32, 2048, FLAG_DIRECTORY, &[1], &[]));
+        entries.extend(record(
+            BOOT_IMAGE_SECTOR as u32,
+            2048,
+            0,
+            b"EFI.IMG;1",
+            &name,
+        ));
+        iso[ROOT_SECTOR * 2048..ROOT_SECTOR * 2048 + entries.len()].copy_from_slice(&entries);
+
+        // FAT boot sector of a 1.44 MB floppy, larger than the 4 sectors of
+        // the catalog entry
+        let boot_sector = &mut iso[BOOT_IMAGE_SECTOR * 2048..];
+        boot_sector[11..13].copy_from_slice(&512u16.to_le_bytes());
+        boot_sector[19..21].copy_from_slice(&2880u16.to_le_bytes());
+        iso
+    }
+
+    #[test]
+    fn el_torito_rock_ridge() {
+        let iso = iso();
+        assert!(is_iso9660(&iso[..]));
+        let fs = Iso9660::open(&iso[..]).unwrap();
+        let entry = fs.lookup("/efiboot.img").unwrap();
+        assert!(!entry.is_dir());
+        assert_eq!(fs.read(&entry).unwrap()[11..13], 512u16.to_le_bytes());
+        assert_eq!(
+            fs.efi_boot_image_range().unwrap(),
+            (BOOT_IMAGE_SECTOR as u64 * 2048, 2880 * 512)
+        );
+    }
+
+    #[test]
+    fn identifier_past_record() {
+        let mut iso = iso();
+        // Identifier length of the ".." record, running past the record
+        iso[ROOT_SECTOR * 2048 + 34 + 32] = 200;
+        let fs = Iso9660::open(&iso[..]).unwrap();
+        assert!(fs.lookup("/efiboot.img").is_err());
+    }
+}
@@ -0,0 +1,244 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Read only access to qcow2 images, version 2 and 3. Compressed clusters
+//! (deflate and zstd) are supported. Backing files, encryption, external
+//! data files and extended L2 entries are not.
+
+use super::BlockDevice;
+use anyhow::{Result, bail};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::fs::FileExt;
+
+pub const QCOW2_MAGIC: [u8; 4] = *b"QFI\xfb";
+
+const INCOMPAT_CORRUPT: u64 = 1 << 1;
+const INCOMPAT_EXTERNAL_DATA: u64 = 1 << 2;
+const INCOMPAT_EXTENDED_L2: u64 = 1 << 4;
+
+const COMPRESSION_DEFLATE: u8 = 0;
+const COMPRESSION_ZSTD: u8 = 1;
+
+const L1_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const L2_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const L2_COMPRESSED: u64 = 1 << 62;
+const L2_ZERO: u64 = 1;
+/// Largest L1 table, in bytes, as limited by QEMU
+const MAX_L1_SIZE: usize = 32 * 1024 * 1024;
+
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn be_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+pub struct Qcow2Image {
+    file: File,
+    file_size: u64,
+    cluster_bits: u32,
+    size: u64,
+    compression_type: u8,
+    l1_table: Vec<u64>,
+    l2_tables: RefCell<HashMap<u64, Vec<u64>>>,
+    /// Last decompressed cluster, as files are usually read sequentially
+    cluster_cache: RefCell<Option<(u64, Vec<u8>)>>,
+}
+
+impl Qcow2Image {
+    pub fn open(file: File) -> Result<Qcow2Image> {
+        let mut header = [0u8; 105];
+        file.read_exact_at(&mut header[..72], 0)?;
+        if header[..4] != QCOW2_MAGIC {
+            bail!("Not a qcow2 image");
+        }
+        let version = be_u32(&header, 4);
+        if version != 2 && version != 3 {
+            bail!("Unsupported qcow2 version {version}");
+        }
+        if be_u64(&header, 8) != 0 {
+            bail!("qcow2 images with a backing file are not supported");
+        }
+        if be_u32(&header, 32) != 0 {
+            bail!("Encrypted qcow2 images are not supported");
+        }
+
+        let mut compression_type = COMPRESSION_DEFLATE;
+        if version == 3 {
+            file.read_exact_at(&mut header[72..104], 72)?;
+            let incompatible = be_u64(&header, 72);
+            if incompatible & (INCOMPAT_CORRUPT | INCOMPAT_EXTERNAL_DATA | INCOMPAT_EXTENDED_L2)
+                != 0
+            {
+                bail!("Unsupported qcow2 incompatible features {incompatible:#x}");
+            }
+            if be_u32(&header, 100) > 104 {
+                file.read_exact_at(&mut header[104..105], 104)?;
+                compression_type = header[104];
+            }
+        }
+
+        let cluster_bits = be_u32(&header, 20);
+        if !(9..=21).contains(&cluster_bits) {
+            bail!("Invalid qcow2 cluster size");
+        }
+        let l1_size = be_u32(&header, 36) as usize * 8;
+        if l1_size > MAX_L1_SIZE {
+            bail!("Invalid qcow2 L1 table size {l1_size}");
+        }
+        let mut l1 = vec![0u8; l1_size];
+        file.read_exact_at(&mut l1, be_u64(&header, 40))?;
+
+        Ok(Qcow2Image {
+            file_size: file.metadata()?.len(),
+            file,
+            cluster_bits,
+            size: be_u64(&header, 24),
+            compression_type,
+            l1_table: l1.chunks_exact(8).map(|e| be_u64(e, 0)).collect(),
+            l2_tables: RefCell::new(HashMap::new()),
+            cluster_cache: RefCell::new(None),
+        })
+    }
+
+    fn cluster_size(&self) -> u64 {
+        1 << self.cluster_bits
+    }
+
+    fn l2_entry(&self, l2_offset: u64, index: usize) -> Result<u64> {
+        let mut tables = self.l2_tables.borrow_mut();
+        if let Entry::Vacant(entry) = tables.entry(l2_offset) {
+            let mut table = vec![0u8; self.cluster_size() as usize];
+            self.file.read_exact_at(&mut table, l2_offset)?;
+            entry.insert(table.chunks_exact(8).map(|e| be_u64(e, 0)).collect());
+        }
+        Ok(tables[&l2_offset][index])
+    }
+
+    fn decompress(&self, entry: u64) -> Result<Vec<u8>> {
+        let offset_bits = 62 - (self.cluster_bits - 8);
+        let offset = entry & ((1 << offset_bits) - 1);
+        let sectors = (entry >> offset_bits) & ((1 << (self.cluster_bits - 8)) - 1);
+        let Some(file_left) = self.file_size.checked_sub(offset) else {
+            bail!("Invalid qcow2 compressed cluster offset {offset:#x}");
+        };
+        let length = ((sectors + 1) * 512 - (offset & 511)).min(file_left);
+        let mut compressed = vec![0u8; length as usize];
+        self.file.read_exact_at(&mut compressed, offset)?;
+
+        let mut cluster = vec![0u8; self.cluster_size() as usize];
+        match self.compression_type {
+            COMPRESSION_DEFLATE => {
+                flate2::read::DeflateDecoder::new(&compressed[..]).read_exact(&mut cluster)?
+            }
+            COMPRESSION_ZSTD => {
+                zstd::stream::read::Decoder::new(&compressed[..])?.read_exact(&mut cluster)?
+            }
+            other => bail!("Unsupported qcow2 compression type {other}"),
+        }
+        Ok(cluster)
+    }
+
+    /// Reads within a single guest cluster
+    fn read_in_cluster(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let cluster_size = self.cluster_size();
+        let l2_entries = cluster_size / 8;
+        let cluster = offset / cluster_size;
+        let in_cluster = offset % cluster_size;
+
+        let l1_entry = self
+            .l1_table
+            .get((cluster / l2_entries) as usize)
+            .copied()
+            .unwrap_or(0);
+        let l2_offset = l1_entry & L1_OFFSET_MASK;
+        if l2_offset == 0 {
+            buf.fill(0);
+            return Ok(());
+        }
+        let entry = self.l2_entry(l2_offset, (cluster % l2_entries) as usize)?;
+
+        if entry & L2_COMPRESSED != 0 {
+            let mut cache = self.cluster_cache.borrow_mut();
+            if cache.as_ref().is_none_or(|(c, _)| *c != cluster) {
+                *cache = Some((cluster, self.decompress(entry)?));
+            }
+            let data = &cache.as_ref().unwrap().1;
+            buf.copy_from_slice(&data[in_cluster as usize..in_cluster as usize + buf.len()]);
+            return Ok(());
+        }
+
+        let host_offset = entry & L2_OFFSET_MASK;
+        if host_offset == 0 || entry & L2_ZERO != 0 {
+            buf.fill(0);
+            return Ok(());
+        }
+        Ok(self.file.read_exact_at(buf, host_offset + in_cluster)?)
+    }
+}
+
+impl BlockDevice for Qcow2Image {
+    fn read_at(&self, mut offset: u64, mut buf: &mut [u8]) -> Result<()> {
+        if offset
+            .checked_add(buf.len() as u64)
+            .is_none_or(|end| end > self.size)
+        {
+            bail!("Read beyond the end of the qcow2 image");
+        }
+        while !buf.is_empty() {
+            let cluster_left = self.cluster_size() - offset % self.cluster_size();
+            let length = (cluster_left as usize).min(buf.len());
+            let (head, tail) = buf.split_at_mut(length);
+            self.read_in_cluster(offset, head)?;
+            offset += length as u64;
+            buf = tail;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const CLUSTER_BITS: u32 = 16;
+    const CLUSTER_SIZE: usize = 1 << CLUSTER_BITS;
+
+    /// A version 2 image whose first guest cluster is compressed at the
+    /// given host offset
+    fn image(l1_size: u32, compressed_offset: u64) -> File {
+        let mut data = vec![0u8; 3 * CLUSTER_SIZE];
+        data[..4].copy_from_slice(&QCOW2_MAGIC);
+        data[4..8].copy_from_slice(&2u32.to_be_bytes());
+        data[20..24].copy_from_slice(&CLUSTER_BITS.to_be_bytes());
+        data[24..32].copy_from_slice(&(1u64 << 20).to_be_bytes());
+        data[36..40].copy_from_slice(&l1_size.to_be_bytes());
+        data[40..48].copy_from_slice(&(CLUSTER_SIZE as u64).to_be_bytes());
+        data[CLUSTER_SIZE..CLUSTER_SIZE + 8]
+            .copy_from_slice(&(2 * CLUSTER_SIZE as u64).to_be_bytes());
+        data[2 * CLUSTER_SIZE..2 * CLUSTER_SIZE + 8]
+            .copy_from_slice(&(L2_COMPRESSED | compressed_offset).to_be_bytes());
+
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&data).unwrap();
+        file
+    }
+
+    #[test]
+    fn corrupted_tables() {
+        assert!(Qcow2Image::open(image(u32::MAX, 0)).is_err());
+
+        let qcow2 = Qcow2Image::open(image(1, 1 << 40)).unwrap();
+        let mut buf = [0u8; 512];
+        assert!(qcow2.read_at(0, &mut buf).is_err());
+        assert!(qcow2.read_at(u64::MAX, &mut buf).is_err());
+    }
+}
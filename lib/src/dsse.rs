@@ -0,0 +1,138 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Dead Simple Signing Envelope (DSSE).
+//!
+//! Signatures are computed over the DSSEv1 pre-authentication encoding of the
+//! payload type and payload. ECDSA, RSA (PKCS #1 v1.5) and Ed25519 keys are
+//! supported. ECDSA signatures are DER encoded.
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{HasPublic, Id, PKeyRef, Private};
+use openssl::sign::{Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Signature {
+    /// Hex encoded SHA-256 fingerprint of the DER encoded public key
+    pub keyid: String,
+    /// Base64 encoded signature
+    pub sig: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    /// Base64 encoded payload
+    pub payload: String,
+    pub payload_type: String,
+    pub signatures: Vec<Signature>,
+}
+
+/// Pre-authentication encoding of a payload
+fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut buf = format!(
+        "DSSEv1 {} {payload_type} {} ",
+        payload_type.len(),
+        payload.len()
+    )
+    .into_bytes();
+    buf.extend(payload);
+    buf
+}
+
+fn keyid<T: HasPublic>(key: &PKeyRef<T>) -> Result<String> {
+    Ok(hex::encode(Sha256::digest(key.public_key_to_der()?)))
+}
+
+/// Returns the digest signatures are computed with for a key, if any
+fn message_digest<T: HasPublic>(key: &PKeyRef<T>) -> Result<Option<MessageDigest>> {
+    Ok(match key.id() {
+        Id::EC => match key.ec_key()?.group().curve_name() {
+            Some(Nid::SECP384R1) => Some(MessageDigest::sha384()),
+            Some(Nid::SECP521R1) => Some(MessageDigest::sha512()),
+            _ => Some(MessageDigest::sha256()),
+        },
+        Id::RSA => Some(MessageDigest::sha256()),
+        Id::ED25519 => None,
+        _ => bail!("Unsupported signing key type"),
+    })
+}
+
+/// Signs a payload, returning the envelope holding it
+pub fn sign(payload_type: &str, payload: &[u8], key: &PKeyRef<Private>) -> Result<Envelope> {
+    let mut signer = match message_digest(key)? {
+        Some(md) => Signer::new(md, key)?,
+        None => Signer::new_without_digest(key)?,
+    };
+    let sig = signer.sign_oneshot_to_vec(&pae(payload_type, payload))?;
+    Ok(Envelope {
+        payload: BASE64.encode(payload),
+        payload_type: payload_type.into(),
+        signatures: vec![Signature {
+            keyid: keyid(key)?,
+            sig: BASE64.encode(sig),
+        }],
+    })
+}
+
+/// Checks that the envelope holds a payload of the expected type and a valid
+/// signature from the given key. Returns the decoded payload.
+pub fn verify<T: HasPublic>(
+    envelope: &Envelope,
+    payload_type: &str,
+    key: &PKeyRef<T>,
+) -> Result<Vec<u8>> {
+    if envelope.payload_type != payload_type {
+        bail!("Unexpected payload type {}", envelope.payload_type);
+    }
+    let payload = BASE64
+        .decode(&envelope.payload)
+        .context("Invalid payload encoding")?;
+    let message = pae(payload_type, &payload);
+
+    for signature in &envelope.signatures {
+        let Ok(sig) = BASE64.decode(&signature.sig) else {
+            continue;
+        };
+        let mut verifier = match message_digest(key)? {
+            Some(md) => Verifier::new(md, key)?,
+            None => Verifier::new_without_digest(key)?,
+        };
+        if verifier.verify_oneshot(&sig, &message)? {
+            return Ok(payload);
+        }
+    }
+    bail!("No valid signature found")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::pkey::PKey;
+
+    #[test]
+    fn pae_encoding() {
+        assert_eq!(
+            pae("http://example.com/HelloWorld", b"hello world"),
+            b"DSSEv1 29 http://example.com/HelloWorld 11 hello world".to_vec()
+        );
+    }
+
+    #[test]
+    fn ed25519_sign_and_verify() {
+        let key = PKey::generate_ed25519().unwrap();
+        let envelope = sign("text/plain", b"payload", &key).unwrap();
+        assert_eq!(verify(&envelope, "text/plain", &key).unwrap(), b"payload");
+
+        let other = PKey::generate_ed25519().unwrap();
+        assert!(verify(&envelope, "text/plain", &other).is_err());
+    }
+}
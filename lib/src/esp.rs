@@ -4,28 +4,24 @@
 // SPDX-License-Identifier: MIT
 
 use crate::pefile;
-use std::fs;
+use crate::vfs::{FileType, Filesystem};
 use std::io;
 use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
-pub struct Esp {
+pub struct Esp<'a> {
+    fs: &'a dyn Filesystem,
     shim: PathBuf,
     grub: PathBuf,
 }
 
 const ESP_VENDOR_NAMES: [&str; 2] = ["redhat", "fedora"];
 
-fn esp_vendor_path(esp_root_path: &Path) -> io::Result<PathBuf> {
+fn esp_vendor_path(fs: &dyn Filesystem, esp_root_path: &Path) -> io::Result<PathBuf> {
     for vendor in ESP_VENDOR_NAMES {
         let vendor_path = esp_root_path.join(format!("EFI/{vendor}"));
-        match fs::metadata(&vendor_path) {
-            Err(_) => {}
-            Ok(metadata) => {
-                if metadata.is_dir() {
-                    return Ok(vendor_path);
-                }
-            }
+        if fs.is_dir(&vendor_path) {
+            return Ok(vendor_path);
         }
     }
     Err(io::Error::new(
@@ -34,10 +30,13 @@ fn esp_vendor_path(esp_root_path: &Path) -> io::Result<PathBuf> {
     ))
 }
 
-fn bin_path_from_esp_vendor(esp_vendor_path: &Path, bin_name: &str) -> io::Result<PathBuf> {
+fn bin_path_from_esp_vendor(
+    fs: &dyn Filesystem,
+    esp_vendor_path: &Path,
+    bin_name: &str,
+) -> io::Result<PathBuf> {
     let bin_path = esp_vendor_path.join(bin_name);
-    let metadata = fs::metadata(&bin_path)?;
-    if metadata.is_file() {
+    if fs.file_type(&bin_path)? == FileType::File {
         return Ok(bin_path);
     }
     Err(io::Error::new(
@@ -46,30 +45,35 @@ fn bin_path_from_esp_vendor(esp_vendor_path: &Path, bin_name: &str) -> io::Resul
     ))
 }
 
-impl Esp {
-    pub fn new(path: &str) -> io::Result<Esp> {
-        let path_pb = PathBuf::from(path);
-        if !fs::metadata(path)?.is_dir() {
+impl Esp<'_> {
+    pub fn new<'a>(fs: &'a dyn Filesystem, path: &str) -> io::Result<Esp<'a>> {
+        if fs.file_type(Path::new(path))? != FileType::Dir {
             return Err(io::Error::new(io::ErrorKind::NotADirectory, path));
         }
 
-        let esp_vendor_path = esp_vendor_path(&path_pb)?;
+        let esp_vendor_path = esp_vendor_path(fs, Path::new(path))?;
 
         Ok(Esp {
-            grub: bin_path_from_esp_vendor(&esp_vendor_path, "grubx64.efi")?,
-            shim: bin_path_from_esp_vendor(&esp_vendor_path, "shimx64.efi")?,
+            fs,
+            grub: bin_path_from_esp_vendor(fs, &esp_vendor_path, "grubx64.efi")?,
+            shim: bin_path_from_esp_vendor(fs, &esp_vendor_path, "shimx64.efi")?,
         })
     }
 
     /// Tries loading the shim binary
     pub fn shim(&self) -> pefile::PeFile {
-        pefile::PeFile::load_from_file(&self.shim.to_string_lossy(), false)
+        pefile::PeFile::load(self.fs, &self.shim.to_string_lossy(), false)
             .expect("Can't open shim binary")
     }
 
+    /// Path of the GRUB configuration file next to the grub binary
+    pub fn grub_cfg(&self) -> PathBuf {
+        self.grub.with_file_name("grub.cfg")
+    }
+
     /// Tries loading the grub binary
     pub fn grub(&self) -> pefile::PeFile {
-        pefile::PeFile::load_from_file(&self.grub.to_string_lossy(), false)
+        pefile::PeFile::load(self.fs, &self.grub.to_string_lossy(), false)
             .expect("Can't open grub binary")
     }
 }
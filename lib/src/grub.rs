@@ -0,0 +1,117 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Minimal GRUB configuration parsing, to find the files the default menu
+//! entry loads.
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MenuEntry {
+    pub title: String,
+    /// Kernel path, relative to the GRUB root device
+    pub linux: String,
+    /// Initrd paths, in load order
+    pub initrds: Vec<String>,
+}
+
+/// Splits a line into words, removing quotes
+fn words(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, c) if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            _ => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Removes the `(device)` prefix of a path
+fn strip_device(path: &str) -> String {
+    match path.strip_prefix('(').and_then(|p| p.split_once(')')) {
+        Some((_, path)) => path.into(),
+        None => path.into(),
+    }
+}
+
+/// Returns the menu entry booted by default. Numeric `default` values are
+/// supported, GRUB falling back to the first entry when they are out of
+/// range.
+pub fn default_entry(config: &str) -> Option<MenuEntry> {
+    let mut default = 0;
+    let mut entries: Vec<MenuEntry> = vec![];
+    let mut current: Option<MenuEntry> = None;
+    for line in config.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let words = words(line);
+        let Some(command) = words.first() else {
+            continue;
+        };
+        match (command.as_str(), &mut current) {
+            ("set", None) => {
+                if let Some(value) = words.get(1).and_then(|w| w.strip_prefix("default=")) {
+                    default = value.trim_matches('"').parse().unwrap_or(0);
+                }
+            }
+            ("menuentry", None) => {
+                current = Some(MenuEntry {
+                    title: words.get(1).cloned().unwrap_or_default(),
+                    ..Default::default()
+                })
+            }
+            ("linux" | "linuxefi" | "linux16", Some(entry)) => {
+                entry.linux = words.get(1).map(|p| strip_device(p)).unwrap_or_default();
+            }
+            ("initrd" | "initrdefi" | "initrd16", Some(entry)) => {
+                entry.initrds = words[1..].iter().map(|p| strip_device(p)).collect();
+            }
+            ("}", Some(_)) => entries.extend(current.take()),
+            _ => {}
+        }
+    }
+    let index = if default < entries.len() { default } else { 0 };
+    entries.into_iter().nth(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn live_iso_entry() {
+        let config = r#"
+set default="0"
+search --label fedora-coreos-42 --set root --no-floppy
+menuentry 'Fedora CoreOS (Live)' --class fedora --class gnu-linux {
+	linux /images/pxeboot/vmlinuz mitigations=auto,nosmt coreos.liveiso=fedora-coreos-42
+	initrd /images/pxeboot/initrd.img ($root)/images/ignition.img
+}
+"#;
+        assert_eq!(
+            default_entry(config),
+            Some(MenuEntry {
+                title: "Fedora CoreOS (Live)".into(),
+                linux: "/images/pxeboot/vmlinuz".into(),
+                initrds: vec![
+                    "/images/pxeboot/initrd.img".into(),
+                    "/images/ignition.img".into()
+                ],
+            })
+        );
+    }
+}
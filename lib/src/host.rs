@@ -0,0 +1,193 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Live host mode: computes the PCRs of the running system from the firmware
+//! variables exposed in /sys/firmware/efi, the booted kernel and the mounted
+//! ESP, and compares them to the values of the TPM.
+
+use crate::rootfs::RootFSTree;
+use crate::uefi::GUID_GLOBAL_VARIABLE;
+use crate::uefi::efivars::SECURE_BOOT_ATTR_HEADER_LENGTH;
+use crate::vfs::{FileType, Filesystem, HostFs};
+use crate::{Pcr, compute_pcr4_in, compute_pcr7_in, compute_pcr14};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::io;
+use std::path::Path;
+use uuid::Uuid;
+
+pub const EFIVARS_PATH: &str = "/sys/firmware/efi/efivars";
+pub const MOK_VARIABLES_PATH: &str = "/sys/firmware/efi/mok-variables";
+const TPM_PCRS_PATH: &str = "/sys/class/tpm/tpm0/pcr-sha256";
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+const OSRELEASE_PATH: &str = "/proc/sys/kernel/osrelease";
+const KERNELS_PATH: &str = "/usr/lib/modules";
+/// Usual mount points of the ESP, in lookup order
+const ESP_MOUNT_POINTS: [&str; 3] = ["/boot/efi", "/efi", "/boot"];
+
+/// Host filesystem whose kernels directory only lists the running kernel
+struct BootedFs {
+    kernel_version: String,
+}
+
+impl Filesystem for BootedFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        HostFs::host().read(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        let mut names = HostFs::host().read_dir(path)?;
+        if path == Path::new(KERNELS_PATH) {
+            names.retain(|name| *name == self.kernel_version);
+        }
+        Ok(names)
+    }
+
+    fn file_type(&self, path: &Path) -> io::Result<FileType> {
+        HostFs::host().file_type(path)
+    }
+}
+
+/// Reads the data of an EFI variable, without its attributes
+fn efivar(name: &str, guid: &Uuid) -> Option<Vec<u8>> {
+    let data = std::fs::read(Path::new(EFIVARS_PATH).join(format!("{name}-{guid}"))).ok()?;
+    data.get(SECURE_BOOT_ATTR_HEADER_LENGTH..)
+        .map(<[u8]>::to_vec)
+}
+
+/// Returns whether Secure Boot is enforced, that is enabled and not in setup
+/// mode
+pub fn secure_boot_enabled() -> bool {
+    let is_set = |name| efivar(name, &GUID_GLOBAL_VARIABLE).is_some_and(|data| data == [1]);
+    is_set("SecureBoot") && !is_set("SetupMode")
+}
+
+/// Returns the mount points of the FAT filesystems of a mountinfo table
+fn fat_mount_points(mountinfo: &str) -> Vec<&str> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (mount, source) = line.split_once(" - ")?;
+            let mount_point = mount.split(' ').nth(4)?;
+            (source.split(' ').next()? == "vfat").then_some(mount_point)
+        })
+        .collect()
+}
+
+/// Returns the mount point of the ESP, the first usual FAT mount point
+/// holding an EFI directory
+fn mounted_esp() -> Option<String> {
+    let mountinfo = std::fs::read_to_string(MOUNTINFO_PATH).ok()?;
+    let fat_mounts = fat_mount_points(&mountinfo);
+    ESP_MOUNT_POINTS
+        .into_iter()
+        .find(|path| fat_mounts.contains(path) && Path::new(path).join("EFI").is_dir())
+        .map(String::from)
+}
+
+/// The running system
+pub struct Host {
+    rootfs: RootFSTree,
+    secureboot_enabled: bool,
+}
+
+impl Host {
+    /// Finds the running kernel and the mounted ESP, falling back to the
+    /// bootupd update payloads of the root filesystem when the ESP is not
+    /// mounted
+    pub fn open() -> Result<Host> {
+        if !Path::new(EFIVARS_PATH).is_dir() {
+            anyhow::bail!("{EFIVARS_PATH} not found, the host has not booted with UEFI");
+        }
+        let kernel_version = std::fs::read_to_string(OSRELEASE_PATH)
+            .with_context(|| format!("Failed to read {OSRELEASE_PATH}"))?
+            .trim()
+            .to_string();
+        let mut rootfs = RootFSTree::from_fs(Box::new(BootedFs { kernel_version }));
+        if let Some(esp) = mounted_esp() {
+            rootfs = rootfs.with_esp(&esp);
+        }
+        Ok(Host {
+            rootfs,
+            secureboot_enabled: secure_boot_enabled(),
+        })
+    }
+
+    pub fn rootfs(&self) -> &RootFSTree {
+        &self.rootfs
+    }
+
+    pub fn secureboot_enabled(&self) -> bool {
+        self.secureboot_enabled
+    }
+
+    /// Computes PCRs 4, 7 and 14. PCR 14 is only computed when shim exposes
+    /// the MOK variables.
+    pub fn compute_pcrs(&self) -> Result<Vec<Pcr>> {
+        let rfs = &self.rootfs;
+        let mut pcrs = vec![
+            compute_pcr4_in(
+                rfs.fs(),
+                rfs.vmlinuz(),
+                rfs.esp(),
+                false,
+                self.secureboot_enabled,
+            ),
+            compute_pcr7_in(
+                rfs.fs(),
+                HostFs::host(),
+                EFIVARS_PATH,
+                rfs.esp(),
+                self.secureboot_enabled,
+            ),
+        ];
+        if Path::new(MOK_VARIABLES_PATH).is_dir() {
+            pcrs.push(compute_pcr14(MOK_VARIABLES_PATH)?);
+        }
+        Ok(pcrs)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PcrCheck {
+    pub id: u64,
+    pub computed: String,
+    /// Value read from the TPM, if available
+    pub tpm: Option<String>,
+    pub matches: bool,
+}
+
+/// Compares computed PCRs to the SHA-256 bank of the TPM
+pub fn check(pcrs: &[Pcr]) -> Vec<PcrCheck> {
+    pcrs.iter()
+        .map(|pcr| {
+            let tpm = std::fs::read_to_string(Path::new(TPM_PCRS_PATH).join(pcr.id.to_string()))
+                .ok()
+                .map(|value| value.trim().to_lowercase());
+            PcrCheck {
+                id: pcr.id,
+                computed: pcr.value.clone(),
+                matches: tpm.as_deref() == Some(pcr.value.as_str()),
+                tpm,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mountinfo_fat_mount_points() {
+        let mountinfo = "\
+22 1 253:0 / / rw,relatime shared:1 - xfs /dev/vda4 rw
+35 22 253:3 / /boot rw,relatime shared:2 - ext4 /dev/vda3 rw
+36 35 253:2 / /boot/efi rw,relatime shared:3 - vfat /dev/vda2 rw,fmask=0077
+37 22 0:5 / /efi rw,relatime shared:4 - tmpfs tmpfs rw
+";
+        assert_eq!(fat_mount_points(mountinfo), ["/boot/efi"]);
+    }
+}
@@ -0,0 +1,87 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! in-toto attestations of computed reference values.
+//!
+//! The computed values are the predicate of an in-toto v1 Statement whose
+//! subject is either the container image the values were computed from, or
+//! the files they were read from. Statements are signed as DSSE envelopes.
+
+use crate::dsse;
+use anyhow::{Context, Result, anyhow};
+use openssl::pkey::{HasPublic, PKeyRef, Private};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use std::collections::BTreeMap;
+
+pub const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+pub const PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+pub const PREDICATE_TYPE: &str =
+    "https://github.com/confidential-clusters/compute-pcrs/reference-values/v1";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Subject {
+    pub name: String,
+    /// Hex encoded digests, indexed by algorithm name
+    pub digest: BTreeMap<String, String>,
+}
+
+impl Subject {
+    /// Subject of a container image given its reference by digest, such as
+    /// `quay.io/org/image@sha256:<digest>`
+    pub fn from_image(reference: &str) -> Result<Subject> {
+        let (name, digest) = reference
+            .rsplit_once('@')
+            .ok_or_else(|| anyhow!("Image reference {reference} has no digest"))?;
+        let (alg, value) = digest
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid image digest {digest}"))?;
+        Ok(Subject {
+            name: name.into(),
+            digest: BTreeMap::from([(alg.into(), value.to_lowercase())]),
+        })
+    }
+
+    /// Subject of a file, given its content
+    pub fn from_data(name: &str, data: &[u8]) -> Subject {
+        Subject {
+            name: name.into(),
+            digest: BTreeMap::from([("sha256".into(), hex::encode(Sha256::digest(data)))]),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Statement {
+    #[serde(rename = "_type")]
+    pub statement_type: String,
+    pub subject: Vec<Subject>,
+    #[serde(rename = "predicateType")]
+    pub predicate_type: String,
+    pub predicate: serde_json::Value,
+}
+
+impl Statement {
+    pub fn new(subject: Vec<Subject>, predicate: serde_json::Value) -> Statement {
+        Statement {
+            statement_type: STATEMENT_TYPE.into(),
+            subject,
+            predicate_type: PREDICATE_TYPE.into(),
+            predicate,
+        }
+    }
+}
+
+/// Signs a statement as a DSSE envelope
+pub fn sign(statement: &Statement, key: &PKeyRef<Private>) -> Result<dsse::Envelope> {
+    dsse::sign(PAYLOAD_TYPE, &serde_json::to_vec(statement)?, key)
+}
+
+/// Verifies the signature of a DSSE envelope and returns the statement it
+/// holds
+pub fn verify<T: HasPublic>(envelope: &dsse::Envelope, key: &PKeyRef<T>) -> Result<Statement> {
+    let payload = dsse::verify(envelope, PAYLOAD_TYPE, key)?;
+    serde_json::from_slice(&payload).context("Invalid in-toto statement")
+}
@@ -0,0 +1,189 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Keylime measured boot reference state.
+//!
+//! The reference state document is the one `create_mb_refstate` produces out
+//! of a live event log. Here it is derived from the image and the variables
+//! instead. The firmware measurements (S-CRTM and platform firmware blobs)
+//! can't be derived from the image and must be supplied, as found in the
+//! event log of a machine of the target platform.
+
+use crate::certs::get_db_signatures;
+use crate::rootfs::RootFSTree;
+use crate::uefi::secureboot::SecureBootVarLoader;
+use crate::{esp, linux, mok};
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use std::path::Path;
+
+const INITRD_FILE_NAME: &str = "initramfs.img";
+
+fn hex_0x(data: &[u8]) -> String {
+    format!("0x{}", hex::encode(data))
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Signature {
+    #[serde(rename = "SignatureOwner")]
+    pub owner: String,
+    #[serde(rename = "SignatureData")]
+    pub data: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Digest {
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScrtmAndBios {
+    pub scrtm: String,
+    pub platform_firmware: Vec<String>,
+}
+
+impl ScrtmAndBios {
+    /// Creates the firmware measurements out of the hex encoded SHA-256
+    /// digests of the EV_S_CRTM_VERSION and EV_EFI_PLATFORM_FIRMWARE_BLOB
+    /// events of PCR 0
+    pub fn new(scrtm: &str, platform_firmware: &[String]) -> Result<ScrtmAndBios> {
+        let digest = |value: &str| {
+            let value = value.trim_start_matches("0x");
+            if value.len() != 64 || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+                bail!("{value} is not a hex encoded SHA-256 digest");
+            }
+            Ok(format!("0x{}", value.to_lowercase()))
+        };
+        Ok(ScrtmAndBios {
+            scrtm: digest(scrtm)?,
+            platform_firmware: platform_firmware
+                .iter()
+                .map(|d| digest(d))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Kernel {
+    pub shim_authcode_sha256: String,
+    pub grub_authcode_sha256: String,
+    pub kernel_authcode_sha256: String,
+    pub vmlinuz_plain_sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initrd_plain_sha256: Option<String>,
+    /// Regular expression the kernel command line must match
+    pub kernel_cmdline: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RefState {
+    pub has_secureboot: bool,
+    pub scrtm_and_bios: Vec<ScrtmAndBios>,
+    pub pk: Vec<Signature>,
+    pub kek: Vec<Signature>,
+    pub db: Vec<Signature>,
+    pub dbx: Vec<Signature>,
+    pub mokdig: Vec<Digest>,
+    pub mokxdig: Vec<Digest>,
+    pub kernels: Vec<Kernel>,
+}
+
+fn signatures(data: &[u8]) -> Result<Vec<Signature>> {
+    Ok(get_db_signatures(data)
+        .map_err(|e| anyhow!("{e}"))?
+        .into_iter()
+        .map(|s| Signature {
+            owner: s.owner.to_string(),
+            data: hex_0x(&s.data),
+        })
+        .collect())
+}
+
+fn kernel(rootfs: &RootFSTree, kernel_cmdline: &str) -> Result<Kernel> {
+    let fs = rootfs.fs();
+    let esp = esp::Esp::new(fs, rootfs.esp())?;
+    let vmlinuz = linux::load_vmlinuz(fs, rootfs.vmlinuz()).map_err(|e| anyhow!("{e}"))?;
+    let vmlinuz_path = Path::new(vmlinuz.path());
+    let vmlinuz_data = fs
+        .read(vmlinuz_path)
+        .with_context(|| format!("Failed to read {}", vmlinuz_path.display()))?;
+    let initrd = vmlinuz_path.with_file_name(INITRD_FILE_NAME);
+
+    Ok(Kernel {
+        shim_authcode_sha256: hex_0x(&esp.shim().authenticode()),
+        grub_authcode_sha256: hex_0x(&esp.grub().authenticode()),
+        kernel_authcode_sha256: hex_0x(&vmlinuz.authenticode()),
+        vmlinuz_plain_sha256: hex_0x(&Sha256::digest(vmlinuz_data)),
+        initrd_plain_sha256: fs
+            .read(&initrd)
+            .ok()
+            .map(|data| hex_0x(&Sha256::digest(data))),
+        kernel_cmdline: kernel_cmdline.into(),
+    })
+}
+
+/// Builds the measured boot reference state of an image booted with the
+/// given firmware, Secure Boot and MOK variables
+pub fn mb_refstate<L: SecureBootVarLoader>(
+    rootfs: &RootFSTree,
+    scrtm_and_bios: ScrtmAndBios,
+    secureboot_vars: L,
+    mok_variables: &str,
+    secureboot_enabled: bool,
+    kernel_cmdline: &str,
+) -> Result<RefState> {
+    let mut refstate = RefState {
+        has_secureboot: secureboot_enabled,
+        scrtm_and_bios: vec![scrtm_and_bios],
+        pk: vec![],
+        kek: vec![],
+        db: vec![],
+        dbx: vec![],
+        mokdig: vec![],
+        mokxdig: vec![],
+        kernels: vec![kernel(rootfs, kernel_cmdline)?],
+    };
+
+    for var in secureboot_vars {
+        let entries = signatures(var.data())?;
+        match var.name().as_str() {
+            "PK" => refstate.pk = entries,
+            "KEK" => refstate.kek = entries,
+            "db" => refstate.db = entries,
+            "dbx" => refstate.dbx = entries,
+            _ => {}
+        }
+    }
+
+    for (hash, event) in mok::MokEventHashes::new(mok_variables).zip(mok::MOK_EVENTS_PCR14) {
+        let digest = Digest {
+            sha256: hex_0x(&hash?),
+        };
+        match event {
+            "MokList" => refstate.mokdig.push(digest),
+            "MokListX" => refstate.mokxdig.push(digest),
+            _ => {}
+        }
+    }
+
+    Ok(refstate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn firmware_digests() {
+        let scrtm = "AB".repeat(32);
+        let firmware = vec![format!("0x{}", "cd".repeat(32))];
+        let scrtm_and_bios = ScrtmAndBios::new(&scrtm, &firmware).unwrap();
+        assert_eq!(scrtm_and_bios.scrtm, format!("0x{}", "ab".repeat(32)));
+        assert_eq!(scrtm_and_bios.platform_firmware, firmware);
+        assert!(ScrtmAndBios::new("abcd", &[]).is_err());
+    }
+}
@@ -5,25 +5,144 @@
 // SPDX-License-Identifier: MIT
 
 use crate::uefi::efivars::{EFIVarsLoader, SECURE_BOOT_ATTR_HEADER_LENGTH};
-use crate::uefi::secureboot::{SecureBootdbLoader, collect_secure_boot_hashes};
+use crate::uefi::secureboot::{SecureBootVarLoader, collect_secure_boot_parts};
+use crate::vfs::{Filesystem, HostFs};
+use anyhow::Result;
 use lief::generic::Section;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::HashSet;
+use std::path::Path;
 
+pub mod cel;
 pub mod certs;
+pub mod clevis;
+pub mod containers_storage;
+pub mod corim;
+pub mod diff;
+pub mod disk;
+pub mod dsse;
 mod esp;
+pub mod grub;
+pub mod host;
+pub mod intoto;
+pub mod keylime;
 mod linux;
 mod mok;
+pub mod oci;
+pub mod pcrlock;
+pub mod pcrsig;
 pub mod pefile;
 pub mod rootfs;
+pub mod rvps;
 pub mod shim;
+pub mod tcg;
+pub mod tpm2;
+pub mod tpm2_tools;
 pub mod uefi;
+pub mod vfs;
+pub mod whatis;
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Variable {
+    pub guid: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Certificate {
+    pub subject: String,
+    pub serial: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Part {
     pub name: String,
     pub hash: String,
+    /// Numeric TCG event type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_type: Option<u32>,
+    /// Path of the measured file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// UEFI variable the event refers to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variable: Option<Variable>,
+    /// Certificate logged by an authority event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<Certificate>,
+    /// Name of the measured UKI section
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+    /// Hex encoded raw event data, when it can be predicted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_data: Option<String>,
+}
+
+impl Part {
+    /// Creates a part for an event of the given type, named after the type
+    pub fn new(event_type: u32, hash: &[u8]) -> Part {
+        Part {
+            name: tcg::event_type_name(event_type).unwrap_or_default().into(),
+            hash: hex::encode(hash),
+            event_type: Some(event_type),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a part for an event measuring a UEFI_VARIABLE_DATA structure
+    pub fn from_variable(event_type: u32, var: &uefi::UEFIVariableData) -> Part {
+        Part {
+            variable: Some(Variable {
+                guid: var.guid().to_string(),
+                name: var.name(),
+            }),
+            event_data: Some(hex::encode(var.encode())),
+            ..Part::new(event_type, &var.hash())
+        }
+    }
+
+    fn with_path(self, path: &str) -> Part {
+        Part {
+            path: Some(path.into()),
+            ..self
+        }
+    }
+
+    fn with_certificate(self, cert: &certs::X509Cert) -> Part {
+        Part {
+            certificate: Some(Certificate {
+                subject: cert.subject.clone(),
+                serial: cert.serial.clone(),
+            }),
+            ..self
+        }
+    }
+
+    /// UKI section parts are named after the section
+    fn with_section(self, section: &str) -> Part {
+        Part {
+            name: section.into(),
+            section: Some(section.into()),
+            ..self
+        }
+    }
+
+    fn with_event_data(self, data: &[u8]) -> Part {
+        Part {
+            event_data: Some(hex::encode(data)),
+            ..self
+        }
+    }
+
+    /// Returns the part with only the name and hash fields
+    pub fn without_metadata(&self) -> Part {
+        Part {
+            name: self.name.clone(),
+            hash: self.hash.clone(),
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -33,93 +152,157 @@ pub struct Pcr {
     pub parts: Vec<Part>,
 }
 
+impl Pcr {
+    /// Computes the value of a PCR extended with the given parts, starting
+    /// from 0
+    fn from_parts(id: u64, parts: Vec<Part>) -> Pcr {
+        let mut result =
+            hex::decode("0000000000000000000000000000000000000000000000000000000000000000")
+                .unwrap()
+                .to_vec();
+
+        for part in &parts {
+            let mut hasher = Sha256::new();
+            hasher.update(result);
+            hasher.update(hex::decode(&part.hash).unwrap());
+            result = hasher.finalize().to_vec();
+        }
+
+        Pcr {
+            id,
+            value: hex::encode(result),
+            parts,
+        }
+    }
+
+    /// Returns the PCR with the metadata stripped from its parts
+    pub fn without_metadata(&self) -> Pcr {
+        Pcr {
+            id: self.id,
+            value: self.value.clone(),
+            parts: self.parts.iter().map(Part::without_metadata).collect(),
+        }
+    }
+}
+
+/// Returns the path of a file relative to the given root directory
+fn relative_path(path: &str, root: &str) -> String {
+    Path::new(path)
+        .strip_prefix(root)
+        .unwrap_or(Path::new(path))
+        .to_string_lossy()
+        .into_owned()
+}
+
 pub fn compute_pcr4(kernels_dir: &str, esp_path: &str, uki: bool, secureboot: bool) -> Pcr {
-    let esp = esp::Esp::new(esp_path).unwrap();
+    compute_pcr4_in(HostFs::host(), kernels_dir, esp_path, uki, secureboot)
+}
+
+/// Computes PCR 4 from the ESP and kernels of a filesystem
+pub fn compute_pcr4_in(
+    fs: &dyn Filesystem,
+    kernels_dir: &str,
+    esp_path: &str,
+    uki: bool,
+    secureboot: bool,
+) -> Pcr {
+    let esp = esp::Esp::new(fs, esp_path).unwrap();
 
-    let ev_efi_action_hash: Vec<u8> =
-        Sha256::digest(b"Calling EFI Application from Boot Option").to_vec();
-    let ev_separator_hash: Vec<u8> = Sha256::digest(hex::decode("00000000").unwrap()).to_vec();
+    let ev_efi_action = b"Calling EFI Application from Boot Option";
+    let ev_separator = hex::decode("00000000").unwrap();
 
-    let mut hashes: Vec<(String, Vec<u8>)> = vec![];
-    hashes.push(("EV_EFI_ACTION".into(), ev_efi_action_hash));
-    hashes.push(("EV_SEPARATOR".into(), ev_separator_hash));
+    let mut parts: Vec<Part> = vec![
+        Part::new(tcg::EV_EFI_ACTION, &Sha256::digest(ev_efi_action))
+            .with_event_data(ev_efi_action),
+        Part::new(tcg::EV_SEPARATOR, &Sha256::digest(&ev_separator)).with_event_data(&ev_separator),
+    ];
 
-    let mut bins = vec![esp.shim(), esp.grub()];
+    let mut bins = vec![(esp.shim(), esp_path), (esp.grub(), esp_path)];
 
     if secureboot && !uki {
-        bins.push(linux::load_vmlinuz(kernels_dir).unwrap())
+        bins.push((linux::load_vmlinuz(fs, kernels_dir).unwrap(), kernels_dir))
     }
     // TODO: write condition for uki and implement logic
 
-    let mut bin_hashes: Vec<(String, Vec<u8>)> = bins
-        .iter()
-        .map(|b| ("EV_EFI_BOOT_SERVICES_APPLICATION".into(), b.authenticode()))
-        .collect();
-    hashes.append(&mut bin_hashes);
-
-    // Start with 0
-    let mut result =
-        hex::decode("0000000000000000000000000000000000000000000000000000000000000000")
-            .unwrap()
-            .to_vec();
-
-    for (_p, h) in &hashes {
-        let mut hasher = Sha256::new();
-        hasher.update(result);
-        hasher.update(h);
-        result = hasher.finalize().to_vec();
-    }
+    parts.extend(bins.iter().map(|(b, root)| {
+        Part::new(tcg::EV_EFI_BOOT_SERVICES_APPLICATION, &b.authenticode())
+            .with_path(&relative_path(b.path(), root))
+    }));
+
+    Pcr::from_parts(4, parts)
+}
 
-    Pcr {
-        id: 4,
-        value: hex::encode(result),
-        parts: hashes
-            .iter()
-            .map(|(p, h)| Part {
-                name: p.to_string(),
-                hash: hex::encode(h),
-            })
-            .collect(),
+/// PCR 9 contains the digests of the files GRUB reads, as EV_IPL events:
+///    - the grub.cfg file next to the grub binary in the ESP
+///    - the kernel of the default menu entry
+///    - the initrds of the default menu entry, in load order
+///
+/// Kernel and initrd paths are resolved from `boot_path`, the directory the
+/// GRUB root device is found at. Configuration files sourced by grub.cfg
+/// are not predicted, so the value only matches configurations booting a
+/// menu entry directly, like the ones of live ISOs.
+pub fn compute_pcr9(esp_path: &str, boot_path: &str) -> Pcr {
+    compute_pcr9_in(HostFs::host(), esp_path, boot_path)
+}
+
+/// Computes PCR 9 from the ESP and boot files of a filesystem
+pub fn compute_pcr9_in(fs: &dyn Filesystem, esp_path: &str, boot_path: &str) -> Pcr {
+    let esp = esp::Esp::new(fs, esp_path).unwrap();
+    let grub_cfg = esp.grub_cfg();
+    let config = fs.read(&grub_cfg).expect("Can't read grub.cfg");
+
+    let mut parts = vec![
+        Part::new(tcg::EV_IPL, &Sha256::digest(&config))
+            .with_path(&relative_path(&grub_cfg.to_string_lossy(), esp_path)),
+    ];
+    if let Some(entry) = grub::default_entry(&String::from_utf8_lossy(&config)) {
+        let files = std::iter::once(&entry.linux).chain(&entry.initrds);
+        parts.extend(files.filter(|f| !f.is_empty()).map(|file| {
+            let path = Path::new(boot_path).join(file.trim_start_matches('/'));
+            let data = fs
+                .read(&path)
+                .unwrap_or_else(|e| panic!("Can't read {}: {e}", path.display()));
+            Part::new(tcg::EV_IPL, &Sha256::digest(&data)).with_path(file)
+        }));
     }
+
+    Pcr::from_parts(9, parts)
 }
 
+/// UKI sections measured into PCR 11 by systemd-stub, in measurement order.
+/// Sections missing from the UKI are not measured.
+const UKI_SECTIONS: [&str; 10] = [
+    ".linux", ".osrel", ".cmdline", ".initrd", ".ucode", ".splash", ".dtb", ".uname", ".sbat",
+    ".pcrpkey",
+];
+
+/// Computes PCR 11 from the sections of a UKI, as systemd-stub measures them:
+/// the name then the content of every section of [`UKI_SECTIONS`] present in
+/// the UKI. Optional sections such as .ucode, .splash, .dtb and .pcrpkey are
+/// part of the measurements when present, and any missing section is skipped
+/// rather than treated as an error, as systemd-stub does.
 pub fn compute_pcr11(uki: &str) -> Pcr {
-    let sections: Vec<&str> = vec![".linux", ".osrel", ".cmdline", ".initrd", ".uname", ".sbat"];
-
-    let pe: lief::pe::Binary = lief::pe::Binary::parse(uki).unwrap();
-    let mut hashes: Vec<(String, Vec<u8>)> = vec![];
-    sections.iter().for_each(|s| {
-        let section = pe.section_by_name(s).unwrap();
-        hashes.push(((*s).into(), Sha256::digest(format!("{s}\0")).to_vec()));
-        hashes.push(((*s).into(), Sha256::digest(section.content()).to_vec()));
+    let pe = pefile::PeFile::load_from_file(uki, false).unwrap();
+    let mut parts: Vec<Part> = vec![];
+    UKI_SECTIONS.iter().for_each(|s| {
+        let Some(section) = pe.image().section_by_name(s) else {
+            return;
+        };
+        let name = format!("{s}\0");
+        parts.push(
+            Part::new(tcg::EV_IPL, &Sha256::digest(&name))
+                .with_section(s)
+                .with_path(uki)
+                .with_event_data(name.as_bytes()),
+        );
+        parts.push(
+            Part::new(tcg::EV_IPL, &Sha256::digest(section.content()))
+                .with_section(s)
+                .with_path(uki),
+        );
     });
 
-    // Start with 0
-    let mut result =
-        hex::decode("0000000000000000000000000000000000000000000000000000000000000000")
-            .unwrap()
-            .to_vec();
-
-    for (_s, h) in &hashes {
-        let mut hasher = Sha256::new();
-        hasher.update(result);
-        hasher.update(h);
-        result = hasher.finalize().to_vec();
-    }
-
-    // println!("{}", hex::encode(&result));
-
-    Pcr {
-        id: 11,
-        value: hex::encode(result),
-        parts: hashes
-            .iter()
-            .map(|(s, h)| Part {
-                name: s.into(),
-                hash: hex::encode(h),
-            })
-            .collect(),
-    }
+    Pcr::from_parts(11, parts)
 }
 
 /// PCR 7 contains the digests of the variables defining the Secure Boot
@@ -137,49 +320,90 @@ pub fn compute_pcr11(uki: &str) -> Pcr {
 /// EFI vars are needed to compute pcr7.
 /// EFI vars can be loaded from
 ///     - efivars
+///     - an OVMF_VARS.fd variable store, with [`compute_pcr7_with`]
 ///
 pub fn compute_pcr7(efivars_path: Option<&str>, esp_path: &str, secureboot_enabled: bool) -> Pcr {
-    let esp = esp::Esp::new(esp_path).unwrap();
-    let mut hashes: Vec<(String, Vec<u8>)> = vec![(
-        "EV_EFI_VARIABLE_DRIVER_CONFIG".into(),
-        uefi::get_secureboot_state_event(secureboot_enabled).hash(),
-    )];
-    let sb_var_loader = EFIVarsLoader::new(
+    compute_pcr7_in(
+        HostFs::host(),
+        HostFs::host(),
         efivars_path.expect("No efivars directory path provided"),
-        SECURE_BOOT_ATTR_HEADER_LENGTH,
-    );
+        esp_path,
+        secureboot_enabled,
+    )
+}
+
+/// Computes PCR 7 from the ESP of a filesystem. EFI variables are read from
+/// the `efivars_path` directory of `efivars_fs`, which is usually the host
+/// rather than the image filesystem.
+pub fn compute_pcr7_in(
+    fs: &dyn Filesystem,
+    efivars_fs: &dyn Filesystem,
+    efivars_path: &str,
+    esp_path: &str,
+    secureboot_enabled: bool,
+) -> Pcr {
+    let sb_var_loader =
+        EFIVarsLoader::from_fs(efivars_fs, efivars_path, SECURE_BOOT_ATTR_HEADER_LENGTH);
+    compute_pcr7_with(fs, sb_var_loader, esp_path, secureboot_enabled)
+}
+
+/// Computes PCR 7 from the ESP of a filesystem, with the Secure Boot
+/// variables of a loader
+pub fn compute_pcr7_with<L: SecureBootVarLoader + Clone>(
+    fs: &dyn Filesystem,
+    sb_var_loader: L,
+    esp_path: &str,
+    secureboot_enabled: bool,
+) -> Pcr {
+    let esp = esp::Esp::new(fs, esp_path).unwrap();
+    let mut parts: Vec<Part> = vec![Part::from_variable(
+        tcg::EV_EFI_VARIABLE_DRIVER_CONFIG,
+        &uefi::get_secureboot_state_event(secureboot_enabled),
+    )];
 
     // Extend PCR7 with events for PK, KEK, db and dbx
-    hashes.extend(collect_secure_boot_hashes(sb_var_loader.clone()));
+    parts.extend(collect_secure_boot_parts(sb_var_loader.clone()));
 
-    hashes.push((
-        "EV_SEPARATOR".into(),
-        Sha256::digest(hex::decode("00000000").unwrap()).to_vec(),
-    ));
+    let ev_separator = hex::decode("00000000").unwrap();
+    parts.push(
+        Part::new(tcg::EV_SEPARATOR, &Sha256::digest(&ev_separator)).with_event_data(&ev_separator),
+    );
 
     let shim_bin = esp.shim();
+    let shim_path = relative_path(shim_bin.path(), esp_path);
     let sb_db = sb_var_loader.secureboot_db();
     let sb_db_certs = crate::certs::get_db_certs(&sb_db).unwrap();
     if secureboot_enabled {
-        let shim_cert = shim_bin.find_cert_in_db(&sb_db_certs);
+        let shim_cert = shim_bin.find_x509_in_db(&sb_db_certs);
         match shim_cert {
-            Some(cert) => hashes.push((
-                "EV_EFI_VARIABLE_AUTHORITY".into(),
-                uefi::UEFIVariableData::new(uefi::GUID_SECURITY_DATABASE, "db", cert).hash(),
-            )),
+            Some(cert) => parts.push(
+                Part::from_variable(
+                    tcg::EV_EFI_VARIABLE_AUTHORITY,
+                    &uefi::UEFIVariableData::new(
+                        uefi::GUID_SECURITY_DATABASE,
+                        "db",
+                        cert.raw.clone(),
+                    ),
+                )
+                .with_path(&shim_path)
+                .with_certificate(cert),
+            ),
             None => panic!("Can't find shim signature certificate in secure boot db"),
         }
     }
 
     let sbatlevel_raw = shim_bin.section(shim::SHIM_SBATLEVEL_SECTION);
     if sbatlevel_raw.is_none() || !secureboot_enabled {
-        hashes.push((
-            "EV_EFI_VARIABLE_AUTHORITY".into(),
-            shim::get_sbat_var_original_uefivar().hash(),
+        parts.push(Part::from_variable(
+            tcg::EV_EFI_VARIABLE_AUTHORITY,
+            &shim::get_sbat_var_original_uefivar(),
         ));
     } else if let Some(data) = sbatlevel_raw {
-        let sbatlevel = shim::get_sbatlevel_uefivar(&data, &shim::SbatLevelPolicyType::PREVIOUS);
-        hashes.push(("EV_EFI_VARIABLE_AUTHORITY".into(), sbatlevel.hash()));
+        let sbatlevel = shim::get_sbatlevel_uefivar(&data, &shim::SbatLevelPolicyType::PREVIOUS)
+            .expect("Unknown .sbatlevel section format");
+        parts.push(
+            Part::from_variable(tcg::EV_EFI_VARIABLE_AUTHORITY, &sbatlevel).with_path(&shim_path),
+        );
     }
 
     if secureboot_enabled {
@@ -189,98 +413,74 @@ pub fn compute_pcr7(efivars_path: Option<&str>, esp_path: &str, secureboot_enabl
         // In the case of UKI, the UKI and UKI addons should be processed
         let binaries = vec![esp.grub()];
         for bin in binaries {
+            let bin_path = relative_path(bin.path(), esp_path);
+            let mut authorities = vec![];
+
             // look for cert in secureboot
-            if let Some(sb_cert) = bin.find_cert_in_db(&sb_db_certs) {
-                let hash =
-                    uefi::UEFIVariableData::new(uefi::GUID_SECURITY_DATABASE, "db", sb_cert).hash();
-                if !logged_cert_hashes.contains(&hash) {
-                    logged_cert_hashes.insert(hash.clone());
-                    hashes.push(("EV_EFI_VARIABLE_AUTHORITY".into(), hash));
-                }
+            if let Some(sb_cert) = bin.find_x509_in_db(&sb_db_certs) {
+                let var = uefi::UEFIVariableData::new(
+                    uefi::GUID_SECURITY_DATABASE,
+                    "db",
+                    sb_cert.raw.clone(),
+                );
+                authorities.push((var, sb_cert));
             }
 
             // look for cert in shim vendor db
-            if let Some(vendor_db) = bin.find_cert_in_db(&shim_vendor_db) {
-                let hash = uefi::UEFIVariableData::new(
+            if let Some(vendor_db) = bin.find_x509_in_db(&shim_vendor_db) {
+                let var = uefi::UEFIVariableData::new(
                     uefi::GUID_SECURITY_DATABASE,
                     "vendor_db",
-                    vendor_db,
-                )
-                .hash();
-                if !logged_cert_hashes.contains(&hash) {
-                    logged_cert_hashes.insert(hash.clone());
-                    hashes.push(("EV_EFI_VARIABLE_AUTHORITY".into(), hash));
-                }
+                    vendor_db.raw.clone(),
+                );
+                authorities.push((var, vendor_db));
             }
 
             // look for cert in shim vendor cert
-            if let Some(vendor_cert) = bin.find_cert_in_db(&shim_vendor_cert) {
+            if let Some(vendor_cert) = bin.find_x509_in_db(&shim_vendor_cert) {
                 let mut vendor_cert_data = uefi::guid_to_le_bytes(&uefi::GUID_SHIM_LOCK);
-                vendor_cert_data.extend(&vendor_cert);
-                let hash = uefi::UEFIVariableData::new(
+                vendor_cert_data.extend(&vendor_cert.raw);
+                let var = uefi::UEFIVariableData::new(
                     uefi::GUID_SHIM_LOCK,
                     "MokListRT",
                     vendor_cert_data,
-                )
-                .hash();
+                );
+                authorities.push((var, vendor_cert));
+            }
+
+            for (var, cert) in authorities {
+                let hash = var.hash();
                 if !logged_cert_hashes.contains(&hash) {
-                    logged_cert_hashes.insert(hash.clone());
-                    hashes.push(("EV_EFI_VARIABLE_AUTHORITY".into(), hash));
+                    logged_cert_hashes.insert(hash);
+                    parts.push(
+                        Part::from_variable(tcg::EV_EFI_VARIABLE_AUTHORITY, &var)
+                            .with_path(&bin_path)
+                            .with_certificate(cert),
+                    );
                 }
             }
         }
     }
 
-    let mut result =
-        hex::decode("0000000000000000000000000000000000000000000000000000000000000000")
-            .unwrap()
-            .to_vec();
-
-    for (_s, h) in &hashes {
-        let mut hasher = Sha256::new();
-        hasher.update(result);
-        hasher.update(h);
-        result = hasher.finalize().to_vec();
-    }
-
-    Pcr {
-        id: 7,
-        value: hex::encode(result),
-        parts: hashes
-            .iter()
-            .map(|(s, h)| Part {
-                name: s.into(),
-                hash: hex::encode(h),
-            })
-            .collect(),
-    }
+    Pcr::from_parts(7, parts)
 }
 
-pub fn compute_pcr14(mok_variables: &str) -> Pcr {
-    let mok_event_loader = mok::MokEventHashes::new(mok_variables);
+pub fn compute_pcr14(mok_variables: &str) -> Result<Pcr> {
+    compute_pcr14_in(HostFs::host(), mok_variables)
+}
 
-    let elems: Vec<(String, Vec<u8>)> = mok_event_loader.map(|h| ("EV_IPL".into(), h)).collect();
+/// Computes PCR 14 from the MokList*RT files of a directory of a filesystem
+pub fn compute_pcr14_in(fs: &dyn Filesystem, mok_variables: &str) -> Result<Pcr> {
+    let mok_event_loader = mok::MokEventHashes::from_fs(fs, mok_variables);
 
-    let mut result =
-        hex::decode("0000000000000000000000000000000000000000000000000000000000000000")
-            .unwrap()
-            .to_vec();
-    for (_, h) in &elems {
-        let mut hasher = Sha256::new();
-        hasher.update(result);
-        hasher.update(h);
-        result = hasher.finalize().to_vec();
-    }
+    let parts: Vec<Part> = mok_event_loader
+        .zip(mok::MOK_EVENTS_PCR14)
+        .map(|(h, event)| {
+            Ok(Part::new(tcg::EV_IPL, &h?)
+                .with_path(&mok::mok_event_to_file_name(event))
+                .with_event_data(format!("{event}\0").as_bytes()))
+        })
+        .collect::<Result<_>>()?;
 
-    Pcr {
-        id: 14,
-        value: hex::encode(result),
-        parts: elems
-            .iter()
-            .map(|(e, h)| Part {
-                name: e.clone(),
-                hash: hex::encode(h),
-            })
-            .collect(),
-    }
+    Ok(Pcr::from_parts(14, parts))
 }
@@ -3,42 +3,56 @@
 //
 // SPDX-License-Identifier: MIT
 
+use crate::vfs::{Filesystem, HostFs};
+use anyhow::{Context, Result};
 use sha2::{Digest, Sha256};
-use std::fs;
 use std::path::{Path, PathBuf};
 
-const MOK_EVENTS_PCR14: [&str; 3] = ["MokList", "MokListX", "MokListTrusted"];
+pub(crate) const MOK_EVENTS_PCR14: [&str; 3] = ["MokList", "MokListX", "MokListTrusted"];
 
-fn mok_event_to_file_name(event_name: &str) -> String {
+pub(crate) fn mok_event_to_file_name(event_name: &str) -> String {
     format!("{}RT", event_name)
 }
 
-fn mok_event_hash(events_dir_path: &Path, event_name: &str) -> Vec<u8> {
-    let data = fs::read(events_dir_path.join(mok_event_to_file_name(event_name))).unwrap();
-    Sha256::digest(data).to_vec()
+fn mok_event_hash(
+    fs: &dyn Filesystem,
+    events_dir_path: &Path,
+    event_name: &str,
+) -> Result<Vec<u8>> {
+    let name = mok_event_to_file_name(event_name);
+    let data = fs
+        .read(&events_dir_path.join(&name))
+        .with_context(|| format!("Failed to read the {name} MOK variable"))?;
+    Ok(Sha256::digest(data).to_vec())
 }
 
 #[derive(Debug, Clone)]
-pub struct MokEventHashes {
+pub struct MokEventHashes<'a> {
+    fs: &'a dyn Filesystem,
     /// Path to the directory containing MokList{}RT files
     path: PathBuf,
     index: usize,
 }
 
-impl MokEventHashes {
-    pub fn new(path: &str) -> MokEventHashes {
+impl MokEventHashes<'_> {
+    pub fn new(path: &str) -> MokEventHashes<'static> {
+        MokEventHashes::from_fs(HostFs::host(), path)
+    }
+
+    pub fn from_fs<'a>(fs: &'a dyn Filesystem, path: &str) -> MokEventHashes<'a> {
         MokEventHashes {
+            fs,
             path: path.into(),
             index: 0,
         }
     }
 }
 
-impl Iterator for MokEventHashes {
-    type Item = Vec<u8>;
+impl Iterator for MokEventHashes<'_> {
+    type Item = Result<Vec<u8>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let hash = mok_event_hash(&self.path, MOK_EVENTS_PCR14.get(self.index)?);
+        let hash = mok_event_hash(self.fs, &self.path, MOK_EVENTS_PCR14.get(self.index)?);
         self.index += 1;
         Some(hash)
     }
@@ -0,0 +1,629 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! OCI archive and OCI image layout input.
+//!
+//! The image layers are streamed and only the paths compute-pcrs reads are
+//! extracted, in memory. Layers are applied following the OCI whiteout
+//! rules. Layers of an archive are read in archive order rather than in
+//! manifest order, so entries of lower layers never overwrite the ones of
+//! upper layers. Layers where needed paths are hard links to other files,
+//! such as the ostree objects of encapsulated images, are read a second
+//! time to extract those files.
+
+use crate::vfs::{Filesystem, MemoryFs};
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+pub const OCI_ARCHIVE_PREFIX: &str = "oci-archive:";
+pub const OCI_LAYOUT_PREFIX: &str = "oci:";
+const OCI_ARCHIVE_EXTENSION: &str = "ociarchive";
+
+const MEDIA_TYPE_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+const MEDIA_TYPE_DOCKER_MANIFEST_LIST: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
+
+/// Initramfs of a kernel, next to it in its modules directory
+const INITRD_FILE_NAME: &str = "initramfs.img";
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Blobs smaller than this are kept in memory while looking for the image
+/// manifest in an archive
+const METADATA_BLOB_MAX_SIZE: u64 = 1024 * 1024;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Paths compute-pcrs reads from the root filesystem
+fn is_needed(path: &Path) -> bool {
+    let components = path.components().count();
+    path.starts_with("usr/lib/bootupd/updates")
+        || (path.starts_with("usr/lib/modules")
+            && components == 5
+            && path
+                .file_name()
+                .is_some_and(|n| n == "vmlinuz" || n == INITRD_FILE_NAME))
+        || path == Path::new("etc/os-release")
+        || path == Path::new("usr/lib/os-release")
+}
+
+/// Returns whether a directory may contain paths compute-pcrs reads
+pub(crate) fn may_contain_needed(dir: &Path) -> bool {
+    const NEEDED_DIRS: [&str; 3] = ["usr/lib/bootupd/updates", "usr/lib/modules", "etc"];
+    NEEDED_DIRS
+        .iter()
+        .any(|d| Path::new(d).starts_with(dir) || dir.starts_with(d))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Platform {
+    architecture: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    #[serde(default)]
+    media_type: String,
+    digest: String,
+    #[serde(default)]
+    platform: Option<Platform>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Index {
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    layers: Vec<Descriptor>,
+}
+
+/// Returns the path of a blob relative to the root of an image layout
+fn blob_path(digest: &str) -> Result<PathBuf> {
+    let (alg, hex) = digest
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Invalid digest {digest}"))?;
+    Ok(Path::new("blobs").join(alg).join(hex))
+}
+
+/// Finds the image manifest descriptor an index points to, following nested
+/// indexes. The first manifest for the amd64 architecture, or without
+/// platform, is picked.
+fn find_manifest<F: Fn(&str) -> Result<Vec<u8>>>(index: &[u8], read_blob: &F) -> Result<String> {
+    let index: Index = serde_json::from_slice(index).context("Invalid image index")?;
+    let descriptor = index
+        .manifests
+        .iter()
+        .find(|d| {
+            d.platform
+                .as_ref()
+                .is_none_or(|p| p.architecture == "amd64")
+        })
+        .ok_or_else(|| anyhow!("No image manifest found for amd64"))?;
+    if descriptor.media_type == MEDIA_TYPE_INDEX
+        || descriptor.media_type == MEDIA_TYPE_DOCKER_MANIFEST_LIST
+    {
+        return find_manifest(&read_blob(&descriptor.digest)?, read_blob);
+    }
+    Ok(descriptor.digest.clone())
+}
+
+/// Returns a reader of the uncompressed content of a layer
+fn layer_reader<'a, R: Read + 'a>(reader: R) -> Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(reader);
+    let magic = reader.fill_buf()?;
+    if magic.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(flate2::read::GzDecoder::new(reader)))
+    } else if magic.starts_with(&ZSTD_MAGIC) {
+        Ok(Box::new(zstd::Decoder::with_buffer(reader)?))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Normalizes the path of a layer entry, relative to the root filesystem
+pub(crate) fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => normalized.push(c),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
+
+/// A whiteout applied by a layer
+struct Whiteout {
+    path: PathBuf,
+    layer: usize,
+    /// Opaque whiteouts hide the content of a directory, but not the
+    /// directory itself
+    opaque: bool,
+}
+
+impl Whiteout {
+    fn hides(&self, path: &Path, layer: usize) -> bool {
+        layer < self.layer && path.starts_with(&self.path) && (!self.opaque || path != self.path)
+    }
+}
+
+/// Extracts the needed paths of layers, which may be given in any order, to
+/// an in memory filesystem. Layers are numbered from the bottom one.
+pub(crate) struct Extractor {
+    fs: MemoryFs,
+    /// Layer that wrote each extracted path
+    written: HashMap<PathBuf, usize>,
+    whiteouts: Vec<Whiteout>,
+    /// Needed hard links of each layer to files that have not been
+    /// extracted, indexed by the path of the file
+    hard_links: HashMap<usize, HashMap<PathBuf, Vec<PathBuf>>>,
+}
+
+impl Extractor {
+    pub(crate) fn new() -> Extractor {
+        Extractor {
+            fs: MemoryFs::new(),
+            written: HashMap::new(),
+            whiteouts: vec![],
+            hard_links: HashMap::new(),
+        }
+    }
+
+    fn hidden(&self, path: &Path, layer: usize) -> bool {
+        self.written.get(path).is_some_and(|l| *l > layer)
+            || self.whiteouts.iter().any(|w| w.hides(path, layer))
+    }
+
+    /// Applies the whiteout of `path` by a layer. Opaque whiteouts hide the
+    /// content of the `path` directory of lower layers.
+    pub(crate) fn whiteout(&mut self, path: &Path, layer: usize, opaque: bool) {
+        let whiteout = Whiteout {
+            path: path.into(),
+            layer,
+            opaque,
+        };
+        let removed: Vec<PathBuf> = self
+            .written
+            .iter()
+            .filter(|(path, layer)| whiteout.hides(path, **layer))
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in removed {
+            self.written.remove(&path);
+            self.fs.remove(&path);
+        }
+        self.whiteouts.push(whiteout);
+    }
+
+    /// Returns whether to extract `path` of a layer, that is whether it is
+    /// needed and not hidden by an upper layer, and records it as written by
+    /// the layer
+    pub(crate) fn claim(&mut self, path: &Path, layer: usize) -> bool {
+        if !is_needed(path) || self.hidden(path, layer) {
+            return false;
+        }
+        self.written.insert(path.into(), layer);
+        true
+    }
+
+    pub(crate) fn file(&mut self, path: &Path, data: Vec<u8>) {
+        self.fs.insert_file(path, data);
+    }
+
+    pub(crate) fn dir(&mut self, path: &Path) {
+        self.fs.insert_dir(path);
+    }
+
+    /// Creates a symbolic link at `path`, pointing to the path `link`
+    /// resolves to inside the root filesystem. Relative links are resolved
+    /// against the directory of the link. Links escaping the root filesystem
+    /// are rejected.
+    pub(crate) fn symlink(&mut self, path: &Path, link: &Path) -> Result<()> {
+        let mut resolved = match link.is_absolute() {
+            true => PathBuf::new(),
+            false => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        };
+        for component in link.components() {
+            match component {
+                Component::Normal(c) => resolved.push(c),
+                Component::CurDir | Component::RootDir => {}
+                Component::ParentDir if resolved.pop() => {}
+                Component::ParentDir | Component::Prefix(_) => bail!(
+                    "Symbolic link {} to {} escapes the root filesystem",
+                    path.display(),
+                    link.display()
+                ),
+            }
+        }
+        self.fs.insert_symlink(path, &Path::new("/").join(resolved));
+        Ok(())
+    }
+
+    fn extract<R: Read>(&mut self, layer: usize, reader: R) -> Result<()> {
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let Some(path) = normalize(&entry.path()?) else {
+                continue;
+            };
+            let Some(file_name) = path.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+                continue;
+            };
+
+            if file_name == OPAQUE_WHITEOUT {
+                self.whiteout(path.parent().unwrap_or(Path::new("")), layer, true);
+                continue;
+            }
+            if let Some(name) = file_name.strip_prefix(WHITEOUT_PREFIX) {
+                self.whiteout(&path.with_file_name(name), layer, false);
+                continue;
+            }
+
+            if !self.claim(&path, layer) {
+                continue;
+            }
+            let entry_type = entry.header().entry_type();
+            if entry_type.is_dir() {
+                self.dir(&path);
+            } else if entry_type.is_file() {
+                let mut data = vec![];
+                entry
+                    .read_to_end(&mut data)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                self.file(&path, data);
+            } else if entry_type.is_symlink() {
+                if let Some(link) = entry.link_name()? {
+                    self.symlink(&path, &link)?;
+                }
+            } else if entry_type.is_hard_link() {
+                let Some(target) = entry.link_name()?.as_deref().and_then(normalize) else {
+                    continue;
+                };
+                // Hard links of ostree encapsulated images point to
+                // objects of the ostree repository, which are not needed
+                // paths and are read in a second pass
+                if self.written.get(&target) == Some(&layer) {
+                    let data = self.fs.read(&target)?;
+                    self.file(&path, data);
+                } else {
+                    self.hard_links
+                        .entry(layer)
+                        .or_default()
+                        .entry(target)
+                        .or_default()
+                        .push(path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Layers holding needed hard links to files that have not been
+    /// extracted
+    fn pending_hard_links(&self) -> Vec<usize> {
+        self.hard_links.keys().copied().collect()
+    }
+
+    /// Extracts the needed hard links of a layer, from a second read of the
+    /// layer
+    fn extract_hard_links<R: Read>(&mut self, layer: usize, reader: R) -> Result<()> {
+        let Some(mut links) = self.hard_links.remove(&layer) else {
+            return Ok(());
+        };
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            if links.is_empty() {
+                break;
+            }
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let Some(paths) = normalize(&entry.path()?).and_then(|p| links.remove(&p)) else {
+                continue;
+            };
+            let mut data = vec![];
+            entry.read_to_end(&mut data)?;
+            // Skip the links hidden since by upper layers
+            for path in paths {
+                if self.written.get(&path) == Some(&layer) {
+                    self.file(&path, data.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The extracted root filesystem
+    pub(crate) fn into_fs(self) -> MemoryFs {
+        self.fs
+    }
+}
+
+/// The needed paths of an OCI image root filesystem, read in memory
+#[derive(Debug)]
+pub struct OciImage {
+    manifest_digest: String,
+    fs: MemoryFs,
+}
+
+impl OciImage {
+    /// Returns whether a root filesystem source refers to an OCI archive or
+    /// layout
+    pub fn is_oci(source: &str) -> bool {
+        let path = Path::new(source);
+        source.starts_with(OCI_ARCHIVE_PREFIX)
+            || source.starts_with(OCI_LAYOUT_PREFIX)
+            || path.extension().is_some_and(|e| e == OCI_ARCHIVE_EXTENSION)
+            || path.join("oci-layout").is_file()
+    }
+
+    /// Opens an OCI archive or an OCI image layout, with or without the
+    /// `oci-archive:` and `oci:` transport prefixes
+    pub fn open(source: &str) -> Result<OciImage> {
+        if let Some(path) = source.strip_prefix(OCI_ARCHIVE_PREFIX) {
+            return OciImage::from_archive(Path::new(path));
+        }
+        if let Some(path) = source.strip_prefix(OCI_LAYOUT_PREFIX) {
+            return OciImage::from_layout(Path::new(path));
+        }
+        let path = Path::new(source);
+        if path.is_dir() {
+            OciImage::from_layout(path)
+        } else {
+            OciImage::from_archive(path)
+        }
+    }
+
+    pub fn from_layout(path: &Path) -> Result<OciImage> {
+        let read_blob = |digest: &str| -> Result<Vec<u8>> {
+            let blob = path.join(blob_path(digest)?);
+            fs::read(&blob).with_context(|| format!("Failed to read {}", blob.display()))
+        };
+        let index = fs::read(path.join("index.json"))
+            .with_context(|| format!("{} is not an OCI image layout", path.display()))?;
+        let manifest_digest = find_manifest(&index, &read_blob)?;
+        let manifest: Manifest = serde_json::from_slice(&read_blob(&manifest_digest)?)
+            .context("Invalid image manifest")?;
+
+        let open_layer = |layer: usize| -> Result<File> {
+            Ok(File::open(
+                path.join(blob_path(&manifest.layers[layer].digest)?),
+            )?)
+        };
+        let mut extractor = Extractor::new();
+        for layer in 0..manifest.layers.len() {
+            extractor.extract(layer, layer_reader(open_layer(layer)?)?)?;
+        }
+        for layer in extractor.pending_hard_links() {
+            extractor.extract_hard_links(layer, layer_reader(open_layer(layer)?)?)?;
+        }
+        Ok(OciImage::new(&manifest_digest, extractor.into_fs()))
+    }
+
+    pub fn from_archive(path: &Path) -> Result<OciImage> {
+        let open = || -> Result<tar::Archive<File>> {
+            let file =
+                File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+            Ok(tar::Archive::new(file))
+        };
+
+        // First pass: keep the index and the small blobs, among which are
+        // the image manifests
+        let mut index = None;
+        let mut blobs: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+        for entry in open()?.entries()? {
+            let mut entry = entry?;
+            let Some(entry_path) = normalize(&entry.path()?) else {
+                continue;
+            };
+            if entry_path == Path::new("index.json") {
+                let mut data = vec![];
+                entry.read_to_end(&mut data)?;
+                index = Some(data);
+            } else if entry_path.starts_with("blobs") && entry.size() <= METADATA_BLOB_MAX_SIZE {
+                let mut data = vec![];
+                entry.read_to_end(&mut data)?;
+                blobs.insert(entry_path, data);
+            }
+        }
+        let index = index.ok_or_else(|| anyhow!("{} is not an OCI archive", path.display()))?;
+        let read_blob = |digest: &str| -> Result<Vec<u8>> {
+            blobs
+                .get(&blob_path(digest)?)
+                .cloned()
+                .ok_or_else(|| anyhow!("Blob {digest} not found"))
+        };
+        let manifest_digest = find_manifest(&index, &read_blob)?;
+        let manifest: Manifest = serde_json::from_slice(&read_blob(&manifest_digest)?)
+            .context("Invalid image manifest")?;
+        let layers: HashMap<PathBuf, usize> = manifest
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(layer, d)| Ok((blob_path(&d.digest)?, layer)))
+            .collect::<Result<_>>()?;
+
+        // Second pass: extract the layers as they come
+        let mut extractor = Extractor::new();
+        let mut extracted = 0;
+        for entry in open()?.entries()? {
+            let entry = entry?;
+            let Some(layer) = normalize(&entry.path()?).and_then(|p| layers.get(&p).copied())
+            else {
+                continue;
+            };
+            extractor.extract(layer, layer_reader(entry)?)?;
+            extracted += 1;
+        }
+        if extracted != layers.len() {
+            bail!("Missing layers in {}", path.display());
+        }
+
+        // Third pass, when needed paths are hard links to files that are
+        // not, as in ostree encapsulated images
+        let pending = extractor.pending_hard_links();
+        if !pending.is_empty() {
+            for entry in open()?.entries()? {
+                let entry = entry?;
+                let Some(layer) = normalize(&entry.path()?).and_then(|p| layers.get(&p).copied())
+                else {
+                    continue;
+                };
+                if pending.contains(&layer) {
+                    extractor.extract_hard_links(layer, layer_reader(entry)?)?;
+                }
+            }
+        }
+        Ok(OciImage::new(&manifest_digest, extractor.into_fs()))
+    }
+
+    /// Wraps a root filesystem read in memory
+    pub(crate) fn new(manifest_digest: &str, fs: MemoryFs) -> OciImage {
+        OciImage {
+            manifest_digest: manifest_digest.into(),
+            fs,
+        }
+    }
+
+    /// Digest of the image manifest, such as `sha256:<hex>`
+    pub fn manifest_digest(&self) -> &str {
+        &self.manifest_digest
+    }
+
+    /// The extracted root filesystem
+    pub fn into_fs(self) -> MemoryFs {
+        self.fs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(entries: &[(&str, Option<&[u8]>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            match data {
+                Some(data) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_size(data.len() as u64);
+                    builder.append_data(&mut header, path, *data).unwrap();
+                }
+                None => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_size(0);
+                    builder.append_data(&mut header, path, &[][..]).unwrap();
+                }
+            }
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn whiteouts_apply_in_any_layer_order() {
+        const SHIM: &str = "usr/lib/bootupd/updates/EFI/fedora/shimx64.efi";
+        const GRUB: &str = "usr/lib/bootupd/updates/EFI/fedora/grubx64.efi";
+        let lower = layer(&[
+            (SHIM, Some(b"old shim")),
+            (GRUB, Some(b"grub")),
+            ("usr/bin/bash", Some(b"bash")),
+        ]);
+        let upper = layer(&[
+            (SHIM, Some(b"new shim")),
+            (
+                "usr/lib/bootupd/updates/EFI/fedora/.wh.grubx64.efi",
+                Some(b""),
+            ),
+        ]);
+
+        for order in [[0, 1], [1, 0]] {
+            let mut extractor = Extractor::new();
+            for layer in order {
+                let data = if layer == 0 { &lower } else { &upper };
+                extractor.extract(layer, &data[..]).unwrap();
+            }
+            let fs = extractor.into_fs();
+            assert_eq!(fs.read(Path::new(SHIM)).unwrap(), b"new shim");
+            assert!(!fs.is_file(Path::new(GRUB)));
+            assert!(!fs.is_file(Path::new("usr/bin/bash")));
+        }
+    }
+
+    #[test]
+    fn hard_links_to_unneeded_files() {
+        const OBJECT: &str = "sysroot/ostree/repo/objects/ab/cdef.file";
+        const KERNEL: &str = "usr/lib/modules/6.15.4/vmlinuz";
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(6);
+        builder
+            .append_data(&mut header, OBJECT, &b"kernel"[..])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        builder.append_link(&mut header, KERNEL, OBJECT).unwrap();
+        let data = builder.into_inner().unwrap();
+
+        let mut extractor = Extractor::new();
+        extractor.extract(0, &data[..]).unwrap();
+        assert_eq!(extractor.pending_hard_links(), [0]);
+        extractor.extract_hard_links(0, &data[..]).unwrap();
+        let fs = extractor.into_fs();
+        assert_eq!(fs.read(Path::new(KERNEL)).unwrap(), b"kernel");
+        assert!(!fs.is_file(Path::new(OBJECT)));
+    }
+
+    #[test]
+    fn symlinks_stay_in_root() {
+        let mut extractor = Extractor::new();
+        let dir = Path::new("usr/lib/modules/6.15.4");
+        extractor.file(Path::new("boot/vmlinuz"), b"kernel".to_vec());
+
+        extractor
+            .symlink(&dir.join("vmlinuz"), Path::new("../../../../boot/vmlinuz"))
+            .unwrap();
+        extractor
+            .symlink(&dir.join("initramfs.img"), Path::new("/boot/vmlinuz"))
+            .unwrap();
+        assert!(
+            extractor
+                .symlink(&dir.join("escape"), Path::new("../../../../../etc/shadow"))
+                .is_err()
+        );
+        assert!(
+            extractor
+                .symlink(&dir.join("escape"), Path::new("/../etc/shadow"))
+                .is_err()
+        );
+        let fs = extractor.into_fs();
+        assert_eq!(fs.read(&dir.join("vmlinuz")).unwrap(), b"kernel");
+        assert_eq!(fs.read(&dir.join("initramfs.img")).unwrap(), b"kernel");
+    }
+
+    #[test]
+    fn kernel_and_initramfs_are_needed() {
+        assert!(is_needed(Path::new("usr/lib/modules/6.15.4/vmlinuz")));
+        assert!(is_needed(Path::new("usr/lib/modules/6.15.4/initramfs.img")));
+        assert!(!is_needed(Path::new("usr/lib/modules/6.15.4/modules.dep")));
+        assert!(!is_needed(Path::new(
+            "usr/lib/modules/6.15.4/kernel/vmlinuz"
+        )));
+    }
+}
@@ -0,0 +1,182 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! systemd-pcrlock `.pcrlock` files.
+//!
+//! A `.pcrlock` file describes the measurements of a single boot component
+//! as a list of CEL-JSON records without `recnum` and `content`. Components
+//! are named following the systemd-pcrlock ordering conventions, so that the
+//! generated files sort together with the ones shipped by systemd. The EFI
+//! action and separator events are not written, as systemd already ships
+//! them.
+
+use crate::cel::Digest;
+use crate::{Part, Pcr, tcg};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const SECUREBOOT_POLICY: &str = "240-secureboot-policy";
+const SHIM: &str = "610-shim";
+const SECUREBOOT_AUTHORITY: &str = "620-secureboot-authority";
+const SHIM_MOK: &str = "630-shim-mok";
+const GRUB: &str = "640-grub";
+const UKI: &str = "650-uki";
+const KERNEL: &str = "670-kernel";
+
+/// Boot applications measured into PCR 4, in the order compute_pcr4 logs
+/// them. Used to name the applications of parts without a path.
+const PCR4_APPLICATIONS: [&str; 3] = [SHIM, GRUB, KERNEL];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub pcr: u64,
+    pub digests: Vec<Digest>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PcrlockFile {
+    /// File name, without the `.pcrlock` extension
+    #[serde(skip)]
+    pub name: String,
+    pub records: Vec<Record>,
+}
+
+impl PcrlockFile {
+    pub fn file_name(&self) -> String {
+        format!("{}.pcrlock", self.name)
+    }
+}
+
+fn application_component(part: &Part, index: usize) -> Option<&'static str> {
+    let Some(path) = &part.path else {
+        return PCR4_APPLICATIONS.get(index).copied();
+    };
+    let file_name = Path::new(path)
+        .file_name()?
+        .to_string_lossy()
+        .to_lowercase();
+    if file_name.starts_with("shim") {
+        Some(SHIM)
+    } else if file_name.starts_with("grub") {
+        Some(GRUB)
+    } else if file_name.starts_with("vmlinuz") {
+        Some(KERNEL)
+    } else {
+        None
+    }
+}
+
+fn event_type(part: &Part) -> Option<u32> {
+    part.event_type
+        .or_else(|| tcg::event_type_from_name(&part.name))
+}
+
+/// Returns the component a part belongs to, if it is written to a
+/// `.pcrlock` file. `application_index` is the position of the part among
+/// the boot applications of its PCR.
+fn component(pcr: u64, part: &Part, application_index: usize) -> Option<&'static str> {
+    match (pcr, event_type(part)) {
+        (4, Some(tcg::EV_EFI_BOOT_SERVICES_APPLICATION)) => {
+            application_component(part, application_index)
+        }
+        (7, Some(tcg::EV_EFI_VARIABLE_DRIVER_CONFIG)) => Some(SECUREBOOT_POLICY),
+        (7, Some(tcg::EV_EFI_VARIABLE_AUTHORITY)) => Some(SECUREBOOT_AUTHORITY),
+        // UKI section parts are named after the section
+        (11, _) => Some(UKI),
+        (14, Some(tcg::EV_IPL)) => Some(SHIM_MOK),
+        _ => None,
+    }
+}
+
+/// Splits the events of the computed PCRs into one `.pcrlock` file per boot
+/// component, sorted by file name
+pub fn pcrlock_files(pcrs: &[Pcr]) -> Vec<PcrlockFile> {
+    let mut files: Vec<PcrlockFile> = vec![];
+    for pcr in pcrs {
+        let mut application_index = 0;
+        for part in &pcr.parts {
+            let name = component(pcr.id, part, application_index);
+            if event_type(part) == Some(tcg::EV_EFI_BOOT_SERVICES_APPLICATION) {
+                application_index += 1;
+            }
+            let Some(name) = name else {
+                continue;
+            };
+
+            let record = Record {
+                pcr: pcr.id,
+                digests: vec![Digest {
+                    hash_alg: tcg::HashAlgorithm::Sha256.to_string(),
+                    digest: part.hash.clone(),
+                }],
+            };
+            match files.iter_mut().find(|f| f.name == name) {
+                Some(file) => file.records.push(record),
+                None => files.push(PcrlockFile {
+                    name: name.into(),
+                    records: vec![record],
+                }),
+            }
+        }
+    }
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Output {
+        pcrs: Vec<Pcr>,
+    }
+
+    fn fixture_pcrs() -> Vec<Pcr> {
+        let output: Output = serde_json::from_str(include_str!(
+            "../../test-fixtures/qemu-ovmf/fedora-42/fedora-42.20250705.3.0/all-pcrs.json"
+        ))
+        .unwrap();
+        output.pcrs
+    }
+
+    #[test]
+    fn component_ordering() {
+        let files = pcrlock_files(&fixture_pcrs());
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                SECUREBOOT_POLICY,
+                SHIM,
+                SECUREBOOT_AUTHORITY,
+                SHIM_MOK,
+                GRUB,
+                KERNEL
+            ]
+        );
+        let records: Vec<usize> = files.iter().map(|f| f.records.len()).collect();
+        assert_eq!(records, [5, 1, 3, 3, 1, 1]);
+    }
+
+    #[test]
+    fn pcrlock_json() {
+        let files = pcrlock_files(&fixture_pcrs());
+        let shim = files.iter().find(|f| f.name == SHIM).unwrap();
+        assert_eq!(shim.file_name(), "610-shim.pcrlock");
+        assert_eq!(
+            serde_json::to_value(shim).unwrap(),
+            serde_json::json!({
+                "records": [{
+                    "pcr": 4,
+                    "digests": [{
+                        "hashAlg": "sha256",
+                        "digest": "94896c17d49fc8c8df0cc2836611586edab1615ce7cb58cf13fc5798de56b367"
+                    }]
+                }]
+            })
+        );
+    }
+}
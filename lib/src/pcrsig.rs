@@ -0,0 +1,161 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! systemd `.pcrsig` signed PCR 11 policies.
+//!
+//! The JSON document produced by `systemd-measure sign` holds, for each PCR
+//! bank, one signed TPM2 PolicyPCR digest per boot phase. Boot phases are
+//! measured into PCR 11 by systemd-pcrphase after the UKI sections, as a
+//! colon separated list of words.
+
+use crate::tcg::HashAlgorithm;
+use crate::tpm2::{PcrSelection, policy_pcr};
+use crate::{Part, Pcr, tcg};
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use openssl::pkey::{HasPublic, PKey, PKeyRef, Private};
+use openssl::sign::{Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const PCRSIG_SECTION: &str = ".pcrsig";
+pub const PCRPKEY_SECTION: &str = ".pcrpkey";
+
+/// Boot phases signed by default by `systemd-measure sign`
+pub const DEFAULT_PHASES: [&str; 4] = [
+    "enter-initrd",
+    "enter-initrd:leave-initrd",
+    "enter-initrd:leave-initrd:sysinit",
+    "enter-initrd:leave-initrd:sysinit:ready",
+];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PcrSignature {
+    pub pcrs: Vec<u64>,
+    /// Hex encoded SHA-256 fingerprint of the DER encoded public key
+    pub pkfp: String,
+    /// Hex encoded PolicyPCR digest
+    pub pol: String,
+    /// Base64 encoded signature of the policy digest
+    pub sig: String,
+}
+
+/// Signatures indexed by PCR bank name
+pub type PcrSig = BTreeMap<String, Vec<PcrSignature>>;
+
+/// Returns the PCR 11 value once the words of a boot phase have been
+/// measured on top of the UKI measurements
+pub fn phase_pcr(pcr11: &Pcr, phase: &str) -> Pcr {
+    let mut parts = pcr11.parts.clone();
+    parts.extend(phase.split(':').filter(|w| !w.is_empty()).map(|word| Part {
+        name: word.into(),
+        ..Part::new(tcg::EV_IPL, &HashAlgorithm::Sha256.digest(word.as_bytes()))
+    }));
+    Pcr::from_parts(pcr11.id, parts)
+}
+
+fn public_key_fingerprint<T: HasPublic>(key: &PKeyRef<T>) -> Result<String> {
+    Ok(hex::encode(
+        HashAlgorithm::Sha256.digest(&key.public_key_to_der()?),
+    ))
+}
+
+fn phase_policy(pcr11: &Pcr, phase: &str, bank: HashAlgorithm) -> Result<Vec<u8>> {
+    let selection = PcrSelection::new(bank, &[pcr11.id])?;
+    policy_pcr(&selection, &[phase_pcr(pcr11, phase)], bank, None)
+}
+
+/// Signs the PolicyPCR digest of PCR 11 for every bank and boot phase
+pub fn sign(
+    pcr11: &Pcr,
+    phases: &[&str],
+    banks: &[HashAlgorithm],
+    key: &PKey<Private>,
+) -> Result<PcrSig> {
+    let pkfp = public_key_fingerprint(key)?;
+    let mut pcrsig = PcrSig::new();
+    for bank in banks {
+        let signatures = phases
+            .iter()
+            .map(|phase| {
+                let pol = phase_policy(pcr11, phase, *bank)?;
+                let mut signer = Signer::new(bank.message_digest(), key)?;
+                signer.update(&pol)?;
+                Ok(PcrSignature {
+                    pcrs: vec![pcr11.id],
+                    pkfp: pkfp.clone(),
+                    pol: hex::encode(&pol),
+                    sig: BASE64.encode(signer.sign_to_vec()?),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        pcrsig.insert(bank.to_string(), signatures);
+    }
+    Ok(pcrsig)
+}
+
+/// Checks that the signatures of a `.pcrsig` document cover the PCR 11
+/// value of every boot phase and are valid signatures from the given key.
+/// Only the banks whose PCR values are computed are checked.
+pub fn verify<T: HasPublic>(
+    pcrsig: &PcrSig,
+    pcr11: &Pcr,
+    phases: &[&str],
+    key: &PKeyRef<T>,
+) -> Result<()> {
+    let pkfp = public_key_fingerprint(key)?;
+    let bank = HashAlgorithm::Sha256;
+    let signatures = pcrsig
+        .get(&bank.to_string())
+        .ok_or_else(|| anyhow!("No {bank} signature found"))?;
+    for phase in phases {
+        let pol = hex::encode(phase_policy(pcr11, phase, bank)?);
+        let signature = signatures
+            .iter()
+            .find(|s| s.pol == pol && s.pkfp == pkfp)
+            .ok_or_else(|| anyhow!("No {bank} signature for phase {phase}"))?;
+        let mut verifier = Verifier::new(bank.message_digest(), key)?;
+        verifier.update(&hex::decode(&signature.pol)?)?;
+        let sig = BASE64
+            .decode(&signature.sig)
+            .context("Invalid signature encoding")?;
+        if !verifier.verify(&sig)? {
+            bail!("Invalid {bank} signature for phase {phase}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+
+    fn pcr11() -> Pcr {
+        Pcr::from_parts(
+            11,
+            vec![Part::new(
+                tcg::EV_IPL,
+                &HashAlgorithm::Sha256.digest(b".linux\0"),
+            )],
+        )
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let phases = DEFAULT_PHASES.to_vec();
+
+        let pcrsig = sign(&pcr11(), &phases, &[HashAlgorithm::Sha256], &key).unwrap();
+        assert_eq!(pcrsig["sha256"].len(), phases.len());
+        verify(&pcrsig, &pcr11(), &phases, &key).unwrap();
+
+        let other = phase_pcr(&pcr11(), "enter-initrd");
+        assert!(verify(&pcrsig, &other, &phases, &key).is_err());
+    }
+}
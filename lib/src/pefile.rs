@@ -3,31 +3,47 @@
 //
 // SPDX-License-Identifier: MIT
 
+use crate::vfs::{Filesystem, HostFs};
 use lief::generic::Section;
-use std::fs::File;
-use std::os::unix::fs::FileExt;
+use std::io::Cursor;
+use std::path::Path;
 
 const SHIM_VENDOR_CERT_SECTION: &str = ".vendor_cert";
 
 pub struct PeFile {
     image: lief::pe::Binary,
     path: String,
+    /// Raw file content, for the parts lief does not expose
+    data: Vec<u8>,
     vmlinuz: bool,
 }
 
 impl PeFile {
-    pub fn load_from_file(path: &str, vmlinuz: bool) -> Option<PeFile> {
+    pub fn load(fs: &dyn Filesystem, path: &str, vmlinuz: bool) -> Option<PeFile> {
+        let data = fs.read(Path::new(path)).ok()?;
+        let lief::Binary::PE(image) = lief::Binary::from(&mut Cursor::new(&data))? else {
+            return None;
+        };
         Some(PeFile {
-            image: lief::pe::Binary::parse(path)?,
+            image,
             path: path.into(),
+            data,
             vmlinuz,
         })
     }
 
+    pub fn load_from_file(path: &str, vmlinuz: bool) -> Option<PeFile> {
+        PeFile::load(HostFs::host(), path, vmlinuz)
+    }
+
     pub fn image(&self) -> &lief::pe::Binary {
         &self.image
     }
 
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
     pub fn authenticode(&self) -> Vec<u8> {
         if self.vmlinuz {
             return self.authenticode_vmlinuz();
@@ -43,31 +59,29 @@ impl PeFile {
         vec![]
     }
 
-    fn long_section_name(&self, mut name: String) -> String {
-        if name.as_bytes()[0] as char != '/' {
+    fn long_section_name(&self, name: String) -> String {
+        let Some(Ok(index)) = name.strip_prefix('/').map(str::parse::<u64>) else {
             return name;
-        }
-        name.remove(0);
+        };
         // Symbols are 18 bytes long
-        let mut string_offset = self.image.header().pointerto_symbol_table()
-            + self.image.header().numberof_symbols() * 18
-            + name.parse::<u32>().unwrap();
-        let mut long_name = String::new();
-        let mut next_char: [u8; 1] = [0];
-        loop {
-            let image_file = File::open(&self.path).unwrap();
-            if image_file
-                .read_exact_at(&mut next_char, string_offset as u64)
-                .is_err()
-                || next_char[0] == 0
-            {
-                break;
-            }
+        let string_offset = self.image.header().pointerto_symbol_table() as u64
+            + self.image.header().numberof_symbols() as u64 * 18
+            + index;
+        let string = usize::try_from(string_offset)
+            .ok()
+            .and_then(|offset| self.data.get(offset..))
+            .unwrap_or_default();
+        let end = string.iter().position(|c| *c == 0).unwrap_or(string.len());
+        string[..end].iter().map(|c| *c as char).collect()
+    }
 
-            long_name.push(next_char[0] as char);
-            string_offset += 1;
-        }
-        long_name
+    /// Returns the name and content of every section, resolving long
+    /// section names from the string table
+    pub fn sections(&self) -> Vec<(String, Vec<u8>)> {
+        self.image
+            .sections()
+            .map(|s| (self.long_section_name(s.name()), s.content().to_vec()))
+            .collect()
     }
 
     pub fn section(&self, name: &str) -> Option<Vec<u8>> {
@@ -79,31 +93,30 @@ impl PeFile {
         None
     }
 
-    fn get_vendor_cert_auth(&self) -> Option<Vec<u8>> {
+    /// Returns the vendor certificate or db of the .vendor_cert section, or
+    /// None when the section is missing or malformed
+    pub fn vendor_cert_auth(&self) -> Option<Vec<u8>> {
         let vendor_cert_raw = self.section(SHIM_VENDOR_CERT_SECTION)?;
         // 4 u32 header consisting of:
         //  - auth_size
         //  - deauth_size
         //  - auth_offset
         //  - deauth_offset
-        let auth_size = u32::from_le_bytes(
-            vendor_cert_raw[0..4]
-                .try_into()
-                .expect("Badly hardcoded section size"),
-        ) as usize;
-        let auth_offset = u32::from_le_bytes(
-            vendor_cert_raw[8..12]
-                .try_into()
-                .expect("Badly hardcoded section size"),
-        ) as usize;
-        Some(vendor_cert_raw[auth_offset..auth_offset + auth_size].to_vec())
+        let field = |offset: usize| -> Option<usize> {
+            let bytes = vendor_cert_raw.get(offset..offset + 4)?;
+            Some(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        };
+        let (auth_size, auth_offset) = (field(0)?, field(8)?);
+        vendor_cert_raw
+            .get(auth_offset..auth_offset.checked_add(auth_size)?)
+            .map(<[u8]>::to_vec)
     }
 
     /// The pe file can carry a .vendor_cert section, in which it could store
     /// certificates in db format. Just as shim could do.
     /// This function parses the db and returns the certificates
     pub fn vendor_db(&self) -> Vec<crate::certs::X509Cert> {
-        match self.get_vendor_cert_auth() {
+        match self.vendor_cert_auth() {
             None => vec![],
             Some(certs) => crate::certs::get_db_certs(&certs).unwrap_or_default(),
         }
@@ -113,7 +126,7 @@ impl PeFile {
     /// certificate. Just as shim could do.
     /// This function parses the certificate and returns a vector that holds it
     pub fn vendor_cert(&self) -> Vec<crate::certs::X509Cert> {
-        if let Some(vendor_cert_auth) = self.get_vendor_cert_auth() {
+        if let Some(vendor_cert_auth) = self.vendor_cert_auth() {
             return match crate::certs::X509Cert::from_der(&vendor_cert_auth) {
                 Ok(cert) => vec![cert],
                 Err(_) => vec![],
@@ -126,14 +139,23 @@ impl PeFile {
         self.image.signatures()
     }
 
-    pub fn find_cert_in_db(&self, db: &Vec<crate::certs::X509Cert>) -> Option<Vec<u8>> {
+    pub fn find_cert_in_db(&self, db: &[crate::certs::X509Cert]) -> Option<Vec<u8>> {
+        self.find_x509_in_db(db).map(|cert| cert.raw.clone())
+    }
+
+    /// Returns the certificate of the db that signed the pe file, either
+    /// directly or as the issuer of one of its signing certificates
+    pub fn find_x509_in_db<'a>(
+        &self,
+        db: &'a [crate::certs::X509Cert],
+    ) -> Option<&'a crate::certs::X509Cert> {
         for signature in self.signatures() {
             for certificate in signature.certificates() {
                 let file_cert_subject = certificate.subject();
                 let file_cert_issuer = certificate.issuer();
                 for cert in db {
                     if cert.subject == file_cert_subject || cert.subject == file_cert_issuer {
-                        return Some(cert.raw.clone());
+                        return Some(cert);
                     }
                 }
             }
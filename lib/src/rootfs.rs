@@ -3,28 +3,123 @@
 //
 // SPDX-License-Identifier: MIT
 
+use crate::containers_storage;
+use crate::disk::gpt::Gpt;
+use crate::disk::{DiskImage, LiveIso};
+use crate::oci::OciImage;
+use crate::vfs::{Filesystem, HostFs, TarFs};
 use std::io;
-use std::path;
+use std::path::{self, Path};
 
 const RELATIVE_KERNELS_PATH: &str = "usr/lib/modules/";
 const RELATIVE_ESP_PATH: &str = "usr/lib/bootupd/updates/";
+const TARBALL_EXTENSION: &str = "tar";
 
 pub struct RootFSTree {
+    /// Filesystem the paths below are read from
+    fs: Box<dyn Filesystem>,
+    root_path: String,
     esp_path: String,
     kernels_path: String,
+    /// Digest of the manifest of the image the root filesystem comes from
+    manifest_digest: Option<String>,
+    /// Partition table of the disk image the boot files have been read from
+    gpt: Option<Gpt>,
+    /// Whether the boot files have been read from a live ISO
+    live_iso: bool,
 }
 
 impl RootFSTree {
+    /// Creates a root filesystem tree from a directory of the host
     pub fn new(rootfs_path: &str) -> io::Result<RootFSTree> {
         let rootfs_path = path::absolute(rootfs_path)?;
-        let kernels_path = rootfs_path.join(RELATIVE_KERNELS_PATH);
-        let esp_path = rootfs_path.join(RELATIVE_ESP_PATH);
+        Ok(RootFSTree::from_fs(Box::new(HostFs::new(&rootfs_path))))
+    }
+
+    /// Creates a root filesystem tree from the root of any filesystem
+    pub fn from_fs(fs: Box<dyn Filesystem>) -> RootFSTree {
+        let root = Path::new("/");
+        RootFSTree {
+            fs,
+            root_path: "/".into(),
+            esp_path: root.join(RELATIVE_ESP_PATH).to_string_lossy().into_owned(),
+            kernels_path: root
+                .join(RELATIVE_KERNELS_PATH)
+                .to_string_lossy()
+                .into_owned(),
+            manifest_digest: None,
+            gpt: None,
+            live_iso: false,
+        }
+    }
+
+    /// Reads the boot binaries from another ESP directory, such as a mounted
+    /// ESP
+    pub fn with_esp(self, esp_path: &str) -> RootFSTree {
+        RootFSTree {
+            esp_path: esp_path.into(),
+            ..self
+        }
+    }
+
+    fn is_tarball(source: &str) -> bool {
+        let path = Path::new(source);
+        path.extension().is_some_and(|e| e == TARBALL_EXTENSION) && path.is_file()
+    }
+
+    /// Returns whether a root filesystem source refers to an image rather
+    /// than a directory
+    pub fn is_image(source: &str) -> bool {
+        source.starts_with(containers_storage::CONTAINERS_STORAGE_PREFIX)
+            || OciImage::is_oci(source)
+            || RootFSTree::is_tarball(source)
+            || LiveIso::is_live_iso(source)
+            || DiskImage::is_disk_image(source)
+    }
+
+    /// Opens a root filesystem from a directory, an uncompressed tarball, an
+    /// OCI archive or image layout, a containers-storage image, a live ISO, or
+    /// a raw or qcow2 disk image
+    pub fn open(source: &str) -> anyhow::Result<RootFSTree> {
+        let image = if let Some(reference) =
+            source.strip_prefix(containers_storage::CONTAINERS_STORAGE_PREFIX)
+        {
+            containers_storage::open(reference)?
+        } else if OciImage::is_oci(source) {
+            OciImage::open(source)?
+        } else if RootFSTree::is_tarball(source) {
+            let fs = TarFs::open(Path::new(source))?;
+            return Ok(RootFSTree::from_fs(Box::new(fs)));
+        } else if LiveIso::is_live_iso(source) {
+            // Checked before disk images, as hybrid ISOs also have a GPT
+            let live_iso = LiveIso::open(source)?;
+            return Ok(RootFSTree {
+                live_iso: true,
+                ..RootFSTree::from_fs(Box::new(live_iso.into_fs()))
+            });
+        } else if DiskImage::is_disk_image(source) {
+            let disk = DiskImage::open(source)?;
+            return Ok(RootFSTree {
+                gpt: Some(disk.gpt().clone()),
+                ..RootFSTree::from_fs(Box::new(disk.into_fs()))
+            });
+        } else {
+            return Ok(RootFSTree::new(source)?);
+        };
         Ok(RootFSTree {
-            esp_path: esp_path.to_str().unwrap().into(),
-            kernels_path: kernels_path.to_str().unwrap().into(),
+            manifest_digest: Some(image.manifest_digest().into()),
+            ..RootFSTree::from_fs(Box::new(image.into_fs()))
         })
     }
 
+    pub fn fs(&self) -> &dyn Filesystem {
+        self.fs.as_ref()
+    }
+
+    pub fn root(&self) -> &str {
+        self.root_path.as_str()
+    }
+
     pub fn esp(&self) -> &str {
         self.esp_path.as_str()
     }
@@ -32,4 +127,20 @@ impl RootFSTree {
     pub fn vmlinuz(&self) -> &str {
         self.kernels_path.as_str()
     }
+
+    /// Digest of the manifest of the image the root filesystem comes from
+    pub fn manifest_digest(&self) -> Option<&str> {
+        self.manifest_digest.as_deref()
+    }
+
+    /// Partition table of the disk image the root filesystem comes from
+    pub fn gpt(&self) -> Option<&Gpt> {
+        self.gpt.as_ref()
+    }
+
+    /// Returns whether the root filesystem comes from a live ISO, whose GRUB
+    /// configuration boots the kernel directly
+    pub fn is_live_iso(&self) -> bool {
+        self.live_iso
+    }
 }
@@ -0,0 +1,185 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Trustee Reference Value Provider Service (RVPS) reference values.
+
+use crate::Pcr;
+use crate::tcg::HashAlgorithm;
+use anyhow::Result;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+const REFERENCE_VALUE_VERSION: &str = "0.1.0";
+const MESSAGE_VERSION: &str = "0.1.0";
+const MESSAGE_TYPE_SAMPLE: &str = "sample";
+
+/// Trustee verifiers, which name the PCR reference values differently
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verifier {
+    /// TPM verifier, e.g. `tpm_pcr4`
+    Tpm,
+    /// Azure vTPM verifiers (SNP and TDX), e.g. `tpm.pcr04`
+    AzTpm,
+}
+
+impl Verifier {
+    pub fn pcr_name(&self, id: u64) -> String {
+        match self {
+            Verifier::Tpm => format!("tpm_pcr{id}"),
+            Verifier::AzTpm => format!("tpm.pcr{id:02}"),
+        }
+    }
+}
+
+impl fmt::Display for Verifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verifier::Tpm => write!(f, "tpm"),
+            Verifier::AzTpm => write!(f, "aztpm"),
+        }
+    }
+}
+
+impl FromStr for Verifier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tpm" => Ok(Verifier::Tpm),
+            "aztpm" => Ok(Verifier::AzTpm),
+            _ => Err(format!("unknown verifier {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HashValue {
+    pub alg: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReferenceValue {
+    pub version: String,
+    pub name: String,
+    /// RFC 3339 expiration date
+    pub expiration: String,
+    #[serde(rename = "hash-value")]
+    pub hash_value: Vec<HashValue>,
+}
+
+/// RVPS registration message
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub version: String,
+    #[serde(rename = "type")]
+    pub message_type: String,
+    pub payload: String,
+}
+
+/// Builds one reference value per PCR out of one or more compute-pcrs
+/// outputs. Every distinct value a PCR takes in the outputs is an
+/// acceptable value of its reference value.
+pub fn reference_values(
+    outputs: &[Vec<Pcr>],
+    verifier: Verifier,
+    expiration: DateTime<Utc>,
+) -> Vec<ReferenceValue> {
+    let mut values: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+    for pcr in outputs.iter().flatten() {
+        let pcr_values = values.entry(pcr.id).or_default();
+        if !pcr_values.contains(&pcr.value) {
+            pcr_values.push(pcr.value.clone());
+        }
+    }
+
+    values
+        .into_iter()
+        .map(|(id, pcr_values)| ReferenceValue {
+            version: REFERENCE_VALUE_VERSION.into(),
+            name: verifier.pcr_name(id),
+            expiration: expiration.to_rfc3339_opts(SecondsFormat::Secs, true),
+            hash_value: pcr_values
+                .into_iter()
+                .map(|value| HashValue {
+                    alg: HashAlgorithm::Sha256.to_string(),
+                    value,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Wraps reference values in a `sample` message, which registers them in
+/// the RVPS through its sample extractor
+pub fn sample_message(reference_values: &[ReferenceValue]) -> Result<Message> {
+    let payload: BTreeMap<&str, Vec<&str>> = reference_values
+        .iter()
+        .map(|rv| {
+            (
+                rv.name.as_str(),
+                rv.hash_value.iter().map(|h| h.value.as_str()).collect(),
+            )
+        })
+        .collect();
+    Ok(Message {
+        version: MESSAGE_VERSION.into(),
+        message_type: MESSAGE_TYPE_SAMPLE.into(),
+        payload: BASE64.encode(serde_json::to_vec(&payload)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcr(id: u64, value: &str) -> Pcr {
+        Pcr {
+            id,
+            value: value.repeat(32),
+            parts: vec![],
+        }
+    }
+
+    #[test]
+    fn sample_reference_values() {
+        let outputs = vec![
+            vec![pcr(4, "aa"), pcr(7, "cc")],
+            vec![pcr(4, "bb"), pcr(7, "cc")],
+        ];
+        let expiration = "2027-01-01T00:00:00Z".parse().unwrap();
+        let values = reference_values(&outputs, Verifier::AzTpm, expiration);
+        assert_eq!(
+            serde_json::to_value(&values[0]).unwrap(),
+            serde_json::json!({
+                "version": "0.1.0",
+                "name": "tpm.pcr04",
+                "expiration": "2027-01-01T00:00:00Z",
+                "hash-value": [
+                    {"alg": "sha256", "value": "aa".repeat(32)},
+                    {"alg": "sha256", "value": "bb".repeat(32)},
+                ]
+            })
+        );
+
+        let values = reference_values(&outputs, Verifier::Tpm, expiration);
+        let message = serde_json::to_value(sample_message(&values).unwrap()).unwrap();
+        assert_eq!(message["version"], "0.1.0");
+        assert_eq!(message["type"], "sample");
+        let payload = BASE64.decode(message["payload"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&payload).unwrap(),
+            serde_json::json!({
+                "tpm_pcr4": ["aa".repeat(32), "bb".repeat(32)],
+                "tpm_pcr7": ["cc".repeat(32)],
+            })
+        );
+    }
+}
@@ -14,43 +14,26 @@ pub enum SbatLevelPolicyType {
 }
 
 // Given the raw .sbatlevel section data, it returns the .sbatlevel data of the
-// target sbatlevel policy, which can be previous or latest.
+// target sbatlevel policy, which can be previous or latest, or None when the
+// section is malformed.
 //
 // The .sbatlevel section contains a 3*u32 header struct consisting of:
 //  - version
 //  - previous .sbatlevel policy section offset
 //  - latest .sbatlevel policy section offset
-fn get_sbatlevel_section(sbatlevel_raw: &[u8], sbatlevel_policy: &SbatLevelPolicyType) -> Vec<u8> {
-    let raw_len = sbatlevel_raw.len();
-    assert!(raw_len > 12, "Unknown sbatlevel data format: too short");
-
-    let policy_offset: usize = match sbatlevel_policy {
-        SbatLevelPolicyType::PREVIOUS => u32::from_le_bytes(
-            sbatlevel_raw[4..8]
-                .try_into()
-                .expect("Badly hardcoded section size"),
-        ) as usize,
-        SbatLevelPolicyType::LATEST => u32::from_le_bytes(
-            sbatlevel_raw[8..12]
-                .try_into()
-                .expect("Badly hardcoded section size"),
-        ) as usize,
-    } + 4;
-    assert!(
-        raw_len > policy_offset + 1,
-        "Unknown sbatlevel data format: too short"
-    );
-
-    let mut policy_end: usize = policy_offset;
-    loop {
-        let next_char = sbatlevel_raw[policy_end];
-        if next_char == 0 {
-            break;
-        }
-        policy_end += 1;
-    }
-
-    sbatlevel_raw[policy_offset..policy_end].to_vec()
+fn get_sbatlevel_section(
+    sbatlevel_raw: &[u8],
+    sbatlevel_policy: &SbatLevelPolicyType,
+) -> Option<Vec<u8>> {
+    let offset_field = match sbatlevel_policy {
+        SbatLevelPolicyType::PREVIOUS => 4,
+        SbatLevelPolicyType::LATEST => 8,
+    };
+    let offset = sbatlevel_raw.get(offset_field..offset_field + 4)?;
+    let policy_offset = (u32::from_le_bytes(offset.try_into().unwrap()) as usize).checked_add(4)?;
+    let policy = sbatlevel_raw.get(policy_offset..)?;
+    let policy_end = policy.iter().position(|c| *c == 0)?;
+    Some(policy[..policy_end].to_vec())
 }
 
 pub fn get_sbat_var_original_uefivar() -> UEFIVariableData {
@@ -58,14 +41,41 @@ pub fn get_sbat_var_original_uefivar() -> UEFIVariableData {
 }
 
 /// Given the raw data of the .sbatlevel section, and the policy type, it
-/// process it and returns a UEFIVariableData structure.
+/// process it and returns a UEFIVariableData structure, or None when the
+/// section is malformed.
 pub fn get_sbatlevel_uefivar(
     sbatlevel_raw: &[u8],
     sbatlevel_policy: &SbatLevelPolicyType,
-) -> UEFIVariableData {
-    UEFIVariableData::new(
+) -> Option<UEFIVariableData> {
+    Some(UEFIVariableData::new(
         GUID_SHIM_LOCK,
         "SbatLevel",
-        get_sbatlevel_section(sbatlevel_raw, sbatlevel_policy),
-    )
+        get_sbatlevel_section(sbatlevel_raw, sbatlevel_policy)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sbatlevel_policies() {
+        let mut section = vec![0u8; 4];
+        section.extend(8u32.to_le_bytes());
+        section.extend(26u32.to_le_bytes());
+        section.extend(b"sbat,1,2022052400\0sbat,1,2024010900\0");
+        assert_eq!(
+            get_sbatlevel_section(&section, &SbatLevelPolicyType::PREVIOUS).unwrap(),
+            b"sbat,1,2022052400"
+        );
+        assert_eq!(
+            get_sbatlevel_section(&section, &SbatLevelPolicyType::LATEST).unwrap(),
+            b"sbat,1,2024010900"
+        );
+        assert!(get_sbatlevel_section(&section[..10], &SbatLevelPolicyType::LATEST).is_none());
+        section[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(get_sbatlevel_section(&section, &SbatLevelPolicyType::LATEST).is_none());
+        // Unterminated policy
+        assert!(get_sbatlevel_section(&section[..25], &SbatLevelPolicyType::PREVIOUS).is_none());
+    }
 }
@@ -0,0 +1,188 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Constants from the TCG PC Client Platform Firmware Profile and the TPM 2.0
+//! specifications.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+pub const EV_PREBOOT_CERT: u32 = 0x0000_0000;
+pub const EV_POST_CODE: u32 = 0x0000_0001;
+pub const EV_NO_ACTION: u32 = 0x0000_0003;
+pub const EV_SEPARATOR: u32 = 0x0000_0004;
+pub const EV_ACTION: u32 = 0x0000_0005;
+pub const EV_EVENT_TAG: u32 = 0x0000_0006;
+pub const EV_S_CRTM_CONTENTS: u32 = 0x0000_0007;
+pub const EV_S_CRTM_VERSION: u32 = 0x0000_0008;
+pub const EV_CPU_MICROCODE: u32 = 0x0000_0009;
+pub const EV_PLATFORM_CONFIG_FLAGS: u32 = 0x0000_000A;
+pub const EV_TABLE_OF_DEVICES: u32 = 0x0000_000B;
+pub const EV_COMPACT_HASH: u32 = 0x0000_000C;
+pub const EV_IPL: u32 = 0x0000_000D;
+pub const EV_IPL_PARTITION_DATA: u32 = 0x0000_000E;
+pub const EV_NONHOST_CODE: u32 = 0x0000_000F;
+pub const EV_NONHOST_CONFIG: u32 = 0x0000_0010;
+pub const EV_NONHOST_INFO: u32 = 0x0000_0011;
+pub const EV_OMIT_BOOT_DEVICE_EVENTS: u32 = 0x0000_0012;
+pub const EV_EFI_VARIABLE_DRIVER_CONFIG: u32 = 0x8000_0001;
+pub const EV_EFI_VARIABLE_BOOT: u32 = 0x8000_0002;
+pub const EV_EFI_BOOT_SERVICES_APPLICATION: u32 = 0x8000_0003;
+pub const EV_EFI_BOOT_SERVICES_DRIVER: u32 = 0x8000_0004;
+pub const EV_EFI_RUNTIME_SERVICES_DRIVER: u32 = 0x8000_0005;
+pub const EV_EFI_GPT_EVENT: u32 = 0x8000_0006;
+pub const EV_EFI_ACTION: u32 = 0x8000_0007;
+pub const EV_EFI_PLATFORM_FIRMWARE_BLOB: u32 = 0x8000_0008;
+pub const EV_EFI_HANDOFF_TABLES: u32 = 0x8000_0009;
+pub const EV_EFI_PLATFORM_FIRMWARE_BLOB2: u32 = 0x8000_000A;
+pub const EV_EFI_HANDOFF_TABLES2: u32 = 0x8000_000B;
+pub const EV_EFI_VARIABLE_BOOT2: u32 = 0x8000_000C;
+pub const EV_EFI_HCRTM_EVENT: u32 = 0x8000_0010;
+pub const EV_EFI_VARIABLE_AUTHORITY: u32 = 0x8000_00E0;
+pub const EV_EFI_SPDM_FIRMWARE_BLOB: u32 = 0x8000_00E1;
+pub const EV_EFI_SPDM_FIRMWARE_CONFIG: u32 = 0x8000_00E2;
+
+const EVENT_TYPES: [(u32, &str); 34] = [
+    (EV_PREBOOT_CERT, "EV_PREBOOT_CERT"),
+    (EV_POST_CODE, "EV_POST_CODE"),
+    (EV_NO_ACTION, "EV_NO_ACTION"),
+    (EV_SEPARATOR, "EV_SEPARATOR"),
+    (EV_ACTION, "EV_ACTION"),
+    (EV_EVENT_TAG, "EV_EVENT_TAG"),
+    (EV_S_CRTM_CONTENTS, "EV_S_CRTM_CONTENTS"),
+    (EV_S_CRTM_VERSION, "EV_S_CRTM_VERSION"),
+    (EV_CPU_MICROCODE, "EV_CPU_MICROCODE"),
+    (EV_PLATFORM_CONFIG_FLAGS, "EV_PLATFORM_CONFIG_FLAGS"),
+    (EV_TABLE_OF_DEVICES, "EV_TABLE_OF_DEVICES"),
+    (EV_COMPACT_HASH, "EV_COMPACT_HASH"),
+    (EV_IPL, "EV_IPL"),
+    (EV_IPL_PARTITION_DATA, "EV_IPL_PARTITION_DATA"),
+    (EV_NONHOST_CODE, "EV_NONHOST_CODE"),
+    (EV_NONHOST_CONFIG, "EV_NONHOST_CONFIG"),
+    (EV_NONHOST_INFO, "EV_NONHOST_INFO"),
+    (EV_OMIT_BOOT_DEVICE_EVENTS, "EV_OMIT_BOOT_DEVICE_EVENTS"),
+    (
+        EV_EFI_VARIABLE_DRIVER_CONFIG,
+        "EV_EFI_VARIABLE_DRIVER_CONFIG",
+    ),
+    (EV_EFI_VARIABLE_BOOT, "EV_EFI_VARIABLE_BOOT"),
+    (
+        EV_EFI_BOOT_SERVICES_APPLICATION,
+        "EV_EFI_BOOT_SERVICES_APPLICATION",
+    ),
+    (EV_EFI_BOOT_SERVICES_DRIVER, "EV_EFI_BOOT_SERVICES_DRIVER"),
+    (
+        EV_EFI_RUNTIME_SERVICES_DRIVER,
+        "EV_EFI_RUNTIME_SERVICES_DRIVER",
+    ),
+    (EV_EFI_GPT_EVENT, "EV_EFI_GPT_EVENT"),
+    (EV_EFI_ACTION, "EV_EFI_ACTION"),
+    (
+        EV_EFI_PLATFORM_FIRMWARE_BLOB,
+        "EV_EFI_PLATFORM_FIRMWARE_BLOB",
+    ),
+    (EV_EFI_HANDOFF_TABLES, "EV_EFI_HANDOFF_TABLES"),
+    (
+        EV_EFI_PLATFORM_FIRMWARE_BLOB2,
+        "EV_EFI_PLATFORM_FIRMWARE_BLOB2",
+    ),
+    (EV_EFI_HANDOFF_TABLES2, "EV_EFI_HANDOFF_TABLES2"),
+    (EV_EFI_VARIABLE_BOOT2, "EV_EFI_VARIABLE_BOOT2"),
+    (EV_EFI_HCRTM_EVENT, "EV_EFI_HCRTM_EVENT"),
+    (EV_EFI_VARIABLE_AUTHORITY, "EV_EFI_VARIABLE_AUTHORITY"),
+    (EV_EFI_SPDM_FIRMWARE_BLOB, "EV_EFI_SPDM_FIRMWARE_BLOB"),
+    (EV_EFI_SPDM_FIRMWARE_CONFIG, "EV_EFI_SPDM_FIRMWARE_CONFIG"),
+];
+
+pub const TPM_ALG_SHA1: u16 = 0x0004;
+pub const TPM_ALG_SHA256: u16 = 0x000B;
+pub const TPM_ALG_SHA384: u16 = 0x000C;
+pub const TPM_ALG_SHA512: u16 = 0x000D;
+
+pub const TPM_CC_POLICY_PCR: u32 = 0x0000_017F;
+
+/// Returns the numeric event type given its name (e.g. "EV_SEPARATOR")
+pub fn event_type_from_name(name: &str) -> Option<u32> {
+    EVENT_TYPES
+        .iter()
+        .find(|(_, n)| *n == name)
+        .map(|(t, _)| *t)
+}
+
+/// Returns the name of a numeric event type
+pub fn event_type_name(event_type: u32) -> Option<&'static str> {
+    EVENT_TYPES
+        .iter()
+        .find(|(t, _)| *t == event_type)
+        .map(|(_, n)| *n)
+}
+
+/// Hash algorithms of the TPM PCR banks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// TPM_ALG_ID of the algorithm
+    pub fn alg_id(&self) -> u16 {
+        match self {
+            HashAlgorithm::Sha1 => TPM_ALG_SHA1,
+            HashAlgorithm::Sha256 => TPM_ALG_SHA256,
+            HashAlgorithm::Sha384 => TPM_ALG_SHA384,
+            HashAlgorithm::Sha512 => TPM_ALG_SHA512,
+        }
+    }
+
+    pub fn digest_size(&self) -> usize {
+        self.message_digest().size()
+    }
+
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        openssl::hash::hash(self.message_digest(), data)
+            .expect("Failed to compute digest")
+            .to_vec()
+    }
+
+    pub fn message_digest(&self) -> openssl::hash::MessageDigest {
+        match self {
+            HashAlgorithm::Sha1 => openssl::hash::MessageDigest::sha1(),
+            HashAlgorithm::Sha256 => openssl::hash::MessageDigest::sha256(),
+            HashAlgorithm::Sha384 => openssl::hash::MessageDigest::sha384(),
+            HashAlgorithm::Sha512 => openssl::hash::MessageDigest::sha512(),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha384 => "sha384",
+            HashAlgorithm::Sha512 => "sha512",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "sha384" => Ok(HashAlgorithm::Sha384),
+            "sha512" => Ok(HashAlgorithm::Sha512),
+            _ => Err(format!("unknown hash algorithm {s}")),
+        }
+    }
+}
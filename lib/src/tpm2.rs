@@ -0,0 +1,177 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! TPM 2.0 structures and digests derived from computed PCR values.
+
+use crate::Pcr;
+use crate::tcg::{HashAlgorithm, TPM_CC_POLICY_PCR};
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+/// Number of PCRs of a PC Client TPM
+const PCR_COUNT: u64 = 24;
+/// Size of the pcrSelect bitmap covering all PCRs
+const PCR_SELECT_SIZE: u8 = 3;
+
+/// A selection of PCRs of a single bank
+#[derive(Clone, Debug, PartialEq)]
+pub struct PcrSelection {
+    pub bank: HashAlgorithm,
+    /// PCR indexes, in ascending order
+    pub pcrs: Vec<u64>,
+}
+
+impl PcrSelection {
+    pub fn new(bank: HashAlgorithm, pcrs: &[u64]) -> Result<PcrSelection> {
+        if let Some(pcr) = pcrs.iter().find(|p| **p >= PCR_COUNT) {
+            bail!("PCR {pcr} is out of range");
+        }
+        let mut pcrs = pcrs.to_vec();
+        pcrs.sort();
+        pcrs.dedup();
+        Ok(PcrSelection { bank, pcrs })
+    }
+
+    /// Marshals the selection as a TPML_PCR_SELECTION holding a single
+    /// TPMS_PCR_SELECTION
+    pub fn marshal(&self) -> Vec<u8> {
+        let mut select = [0u8; PCR_SELECT_SIZE as usize];
+        for pcr in &self.pcrs {
+            select[(pcr / 8) as usize] |= 1 << (pcr % 8);
+        }
+
+        let mut buf = vec![];
+        buf.extend(1u32.to_be_bytes());
+        buf.extend(self.bank.alg_id().to_be_bytes());
+        buf.push(PCR_SELECT_SIZE);
+        buf.extend(select);
+        buf
+    }
+
+    /// Returns the values of the selected PCRs, in selection order
+    pub fn values(&self, pcrs: &[Pcr]) -> Result<Vec<Vec<u8>>> {
+        if self.bank != HashAlgorithm::Sha256 {
+            bail!("PCR values are only computed for the sha256 bank");
+        }
+        self.pcrs
+            .iter()
+            .map(|id| {
+                let pcr = pcrs
+                    .iter()
+                    .find(|p| p.id == *id)
+                    .ok_or_else(|| anyhow!("PCR {id} has not been computed"))?;
+                Ok(hex::decode(&pcr.value)?)
+            })
+            .collect()
+    }
+}
+
+/// Computes the digest of the selected PCR values concatenated in selection
+/// order, using the given hash algorithm
+pub fn pcr_digest(selection: &PcrSelection, pcrs: &[Pcr], alg: HashAlgorithm) -> Result<Vec<u8>> {
+    Ok(alg.digest(&selection.values(pcrs)?.concat()))
+}
+
+/// Computes the policyDigest resulting of a TPM2_PolicyPCR command on the
+/// selected PCRs. The command is chained onto `previous` if given, otherwise
+/// onto the empty policy.
+pub fn policy_pcr(
+    selection: &PcrSelection,
+    pcrs: &[Pcr],
+    alg: HashAlgorithm,
+    previous: Option<&[u8]>,
+) -> Result<Vec<u8>> {
+    let policy = match previous {
+        Some(digest) if digest.len() != alg.digest_size() => {
+            bail!("previous policy digest is not a {alg} digest")
+        }
+        Some(digest) => digest.to_vec(),
+        None => vec![0; alg.digest_size()],
+    };
+
+    let mut data = policy;
+    data.extend(TPM_CC_POLICY_PCR.to_be_bytes());
+    data.extend(selection.marshal());
+    data.extend(pcr_digest(selection, pcrs, alg)?);
+    Ok(alg.digest(&data))
+}
+
+/// The PCR related fields of a TPMS_QUOTE_INFO structure, as found in the
+/// TPMS_ATTEST structure of a quote over the selected PCRs
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QuoteInfo {
+    /// Hex encoded marshalled TPML_PCR_SELECTION
+    pub pcr_select: String,
+    /// Hex encoded digest of the selected PCR values
+    pub pcr_digest: String,
+}
+
+impl QuoteInfo {
+    /// Marshals the TPMS_QUOTE_INFO structure
+    pub fn marshal(&self) -> Result<Vec<u8>> {
+        let digest = hex::decode(&self.pcr_digest)?;
+        let mut buf = hex::decode(&self.pcr_select)?;
+        buf.extend((digest.len() as u16).to_be_bytes());
+        buf.extend(digest);
+        Ok(buf)
+    }
+}
+
+/// Computes the expected TPMS_QUOTE_INFO of a quote over the selected PCRs.
+/// `alg` is the hash algorithm of the signing scheme of the quote, which is
+/// usually the same as the one of the selected bank.
+pub fn quote_info(selection: &PcrSelection, pcrs: &[Pcr], alg: HashAlgorithm) -> Result<QuoteInfo> {
+    Ok(QuoteInfo {
+        pcr_select: hex::encode(selection.marshal()),
+        pcr_digest: hex::encode(pcr_digest(selection, pcrs, alg)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    fn zero_pcrs() -> Vec<Pcr> {
+        [0, 7]
+            .iter()
+            .map(|id| Pcr {
+                id: *id,
+                value: hex::encode([0u8; 32]),
+                parts: vec![],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn marshal_selection() {
+        let selection = PcrSelection::new(HashAlgorithm::Sha256, &[7, 0, 23]).unwrap();
+        assert_eq!(selection.marshal(), hex!("00000001000b03810080").to_vec());
+    }
+
+    #[test]
+    fn policy_digest() {
+        let selection = PcrSelection::new(HashAlgorithm::Sha256, &[0]).unwrap();
+        assert_eq!(
+            policy_pcr(&selection, &zero_pcrs(), HashAlgorithm::Sha256, None).unwrap(),
+            hex!("093ceb41181d47808862d7946268ee6a17a10e3d1b79b32351bc56e4beaceff0").to_vec()
+        );
+    }
+
+    #[test]
+    fn marshal_quote_info() {
+        let selection = PcrSelection::new(HashAlgorithm::Sha256, &[0, 7]).unwrap();
+        let info = quote_info(&selection, &zero_pcrs(), HashAlgorithm::Sha256).unwrap();
+        assert_eq!(
+            info.marshal().unwrap(),
+            hex!(
+                "00000001000b03810000"
+                "0020"
+                "f5a5fd42d16a20302798ef6ed309979b43003d2320d9f0e8ea9831a92759fb4b"
+            )
+            .to_vec()
+        );
+    }
+}
@@ -0,0 +1,107 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! tpm2-tools files, to reproduce the computed PCR state on a fresh (software)
+//! TPM and to compare it with the one read back.
+
+use crate::Pcr;
+use crate::tcg::HashAlgorithm;
+use crate::tpm2::PcrSelection;
+use anyhow::{Result, bail};
+
+pub const SCRIPT_FILE_NAME: &str = "pcrextend.sh";
+pub const PCR_DAT_FILE_NAME: &str = "pcr.dat";
+
+/// Banks the computed parts hold digests for
+const BANKS: [HashAlgorithm; 1] = [HashAlgorithm::Sha256];
+
+fn pcr_list(pcrs: &[Pcr]) -> String {
+    let mut ids: Vec<u64> = pcrs.iter().map(|p| p.id).collect();
+    ids.sort();
+    ids.dedup();
+    ids.iter().map(u64::to_string).collect::<Vec<_>>().join(",")
+}
+
+/// Returns a digest checked to be a hex encoded SHA-256 digest, as it is
+/// written to the script unquoted
+fn sha256_hex(digest: &str) -> Result<&str> {
+    if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("{digest:?} is not a hex encoded SHA-256 digest");
+    }
+    Ok(digest)
+}
+
+/// Returns a shell script extending every event of the computed PCRs, in
+/// event order, for every bank. Part names are only written to comments,
+/// without control characters.
+pub fn pcrextend_script(pcrs: &[Pcr]) -> Result<String> {
+    let mut script = String::from("#!/bin/sh\nset -e\n");
+    for pcr in pcrs {
+        script.push_str(&format!(
+            "\n# PCR {}: {}\n",
+            pcr.id,
+            sha256_hex(&pcr.value)?
+        ));
+        for part in &pcr.parts {
+            let hash = sha256_hex(&part.hash)?;
+            let name: String = part.name.chars().filter(|c| !c.is_control()).collect();
+            for bank in BANKS {
+                script.push_str(&format!(
+                    "tpm2_pcrextend {}:{bank}={hash}  # {name}\n",
+                    pcr.id
+                ));
+            }
+        }
+    }
+    for bank in BANKS {
+        script.push_str(&format!(
+            "\n# Read back with: tpm2_pcrread {bank}:{} -o {PCR_DAT_FILE_NAME}\n",
+            pcr_list(pcrs)
+        ));
+    }
+    Ok(script)
+}
+
+/// Returns the PCR values as written by `tpm2_pcrread -o`: the raw values of
+/// the selected PCRs concatenated in ascending PCR order
+pub fn pcr_dat(pcrs: &[Pcr]) -> Result<Vec<u8>> {
+    let ids: Vec<u64> = pcrs.iter().map(|p| p.id).collect();
+    let mut data = vec![];
+    for bank in BANKS {
+        data.extend(PcrSelection::new(bank, &ids)?.values(pcrs)?.concat());
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Part;
+
+    fn pcr(name: &str, hash: &str) -> Pcr {
+        Pcr {
+            id: 4,
+            value: "00".repeat(32),
+            parts: vec![Part {
+                name: name.into(),
+                hash: hash.into(),
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn hostile_part_name() {
+        let hash = "ab".repeat(32);
+        let script = pcrextend_script(&[pcr("shim\nreboot\r\n# x", &hash)]).unwrap();
+        assert!(script.contains(&format!(
+            "tpm2_pcrextend 4:sha256={hash}  # shimreboot# x\n"
+        )));
+        assert!(!script.lines().any(|l| l.starts_with("reboot")));
+
+        assert!(pcrextend_script(&[pcr("shim", &format!("{}; reboot", "ab".repeat(29)))]).is_err());
+        assert!(pcrextend_script(&[pcr("shim", "abcd")]).is_err());
+    }
+}
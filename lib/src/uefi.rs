@@ -6,7 +6,11 @@
 use sha2::{Digest, Sha256};
 use uuid::{Uuid, uuid};
 
+pub mod auth;
+pub mod aws;
 pub mod efivars;
+pub mod json;
+pub mod ovmf;
 pub mod secureboot;
 
 pub const GUID_GLOBAL_VARIABLE: Uuid = uuid!("8be4df61-93ca-11d2-aa0d-00e098032b8c");
@@ -14,6 +18,7 @@ pub const GUID_SECURITY_DATABASE: Uuid = uuid!("d719b2cb-3d3a-4596-a3bc-dad00e67
 pub const GUID_SHIM_LOCK: Uuid = uuid!("605dab50-e046-4300-abb6-3dd810dd8b23");
 
 pub const EFI_CERT_TYPE_X509_GUID: Uuid = uuid!("a5c059a1-94e4-4aa7-87b5-ab155c2bf072");
+pub const EFI_CERT_SHA256_GUID: Uuid = uuid!("c1c41626-504c-4092-aca9-41f936934328");
 
 // Generates the little endian representation of a GUID variable
 // name.
@@ -27,13 +32,24 @@ pub fn guid_to_le_bytes(guid: &Uuid) -> Vec<u8> {
     guid_bytes_le
 }
 
+// Parses the little endian representation of a GUID, as found in UEFI
+// structures.
+pub fn guid_from_le_bytes(bytes: &[u8; 16]) -> Uuid {
+    let mut guid_bytes: [u8; 16] = *bytes;
+
+    guid_bytes[0..4].reverse();
+    guid_bytes[4..6].reverse();
+    guid_bytes[6..8].reverse();
+    Uuid::from_bytes(guid_bytes)
+}
+
 pub fn get_secureboot_state_event(enabled: bool) -> UEFIVariableData {
     UEFIVariableData::new(GUID_GLOBAL_VARIABLE, "SecureBoot", vec![enabled as u8])
 }
 
 // Struct representing UEFIVariable data and the events it could measure in
 // the TPM
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct UEFIVariableData {
     variable_name: Uuid,
     unicode_name_len: u64,
@@ -61,7 +77,7 @@ impl UEFIVariableData {
     // hashed.
     // This method returns the content that is hashed to obtain the event hash
     // that extends the TPM
-    fn encode(&self) -> Vec<u8> {
+    pub fn encode(&self) -> Vec<u8> {
         // Make a u8 buffer from the char16 representation of the unicode name
         let unicode_name_u8: Vec<u8> = self
             .unicode_name
@@ -88,6 +104,14 @@ impl UEFIVariableData {
     pub fn data(&self) -> &[u8] {
         &self.variable_data
     }
+
+    pub fn guid(&self) -> Uuid {
+        self.variable_name
+    }
+
+    pub fn name(&self) -> String {
+        String::from_utf16_lossy(&self.unicode_name)
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,217 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Signed Secure Boot variable updates (`.auth` files), applied to loaded
+//! variables to predict their content after the update. Signatures are not
+//! verified.
+
+use super::efivars::get_secure_boot_targets;
+use super::{UEFIVariableData, secureboot};
+use crate::certs;
+use anyhow::{Context, Result, anyhow, bail};
+use uuid::Uuid;
+
+/// Size of the EFI_TIME leading EFI_VARIABLE_AUTHENTICATION_2
+const EFI_TIME_SIZE: usize = 16;
+/// WIN_CERTIFICATE header and certificate type GUID of WIN_CERTIFICATE_UEFI_GUID
+const WIN_CERTIFICATE_UEFI_GUID_HEADER_SIZE: usize = 24;
+const SIGNATURE_LIST_HEADER_SIZE: usize = 28;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UpdateMode {
+    /// The variable is replaced, or deleted by an empty payload
+    Replace,
+    /// The payload signature lists are appended, with EFI_VARIABLE_APPEND_WRITE
+    Append,
+}
+
+/// Returns the signature lists of an EFI_VARIABLE_AUTHENTICATION_2 payload
+pub fn auth_payload(auth: &[u8]) -> Result<&[u8]> {
+    if auth.len() < EFI_TIME_SIZE + WIN_CERTIFICATE_UEFI_GUID_HEADER_SIZE {
+        bail!("Truncated EFI_VARIABLE_AUTHENTICATION_2 header");
+    }
+    let length = u32::from_le_bytes(auth[EFI_TIME_SIZE..EFI_TIME_SIZE + 4].try_into().unwrap());
+    let length = length as usize;
+    if length < WIN_CERTIFICATE_UEFI_GUID_HEADER_SIZE || EFI_TIME_SIZE + length > auth.len() {
+        bail!("Invalid WIN_CERTIFICATE length {length}");
+    }
+    Ok(&auth[EFI_TIME_SIZE + length..])
+}
+
+struct SignatureList<'a> {
+    /// EFI_SIGNATURE_LIST header, followed by the signature header
+    header: &'a [u8],
+    item_size: usize,
+    items: Vec<&'a [u8]>,
+}
+
+fn signature_lists(data: &[u8]) -> Vec<SignatureList<'_>> {
+    let mut lists = vec![];
+    let mut offset = 0;
+    while offset + SIGNATURE_LIST_HEADER_SIZE <= data.len() {
+        let field = |i: usize| {
+            u32::from_le_bytes(data[offset + i..offset + i + 4].try_into().unwrap()) as usize
+        };
+        let (list_size, head_size, item_size) = (field(16), field(20), field(24));
+        let items_start = offset + SIGNATURE_LIST_HEADER_SIZE + head_size;
+        if list_size < SIGNATURE_LIST_HEADER_SIZE + head_size
+            || offset + list_size > data.len()
+            || item_size == 0
+        {
+            break;
+        }
+        lists.push(SignatureList {
+            header: &data[offset..items_start],
+            item_size,
+            items: data[items_start..offset + list_size]
+                .chunks_exact(item_size)
+                .collect(),
+        });
+        offset += list_size;
+    }
+    lists
+}
+
+/// Appends signature lists to a variable the way firmware does: signatures
+/// already present with the same type are dropped, as are the lists they
+/// empty, and the remaining lists are appended unchanged.
+pub fn append_signature_lists(existing: &[u8], new: &[u8]) -> Vec<u8> {
+    let existing_lists = signature_lists(existing);
+    let is_known = |list_type: &[u8], item: &[u8]| {
+        existing_lists.iter().any(|list| {
+            list.header[..16] == list_type[..16]
+                && list.item_size == item.len()
+                && list.items.contains(&item)
+        })
+    };
+
+    let mut data = existing.to_vec();
+    for list in signature_lists(new) {
+        let items: Vec<&[u8]> = list
+            .items
+            .into_iter()
+            .filter(|item| !is_known(list.header, item))
+            .collect();
+        if items.is_empty() {
+            continue;
+        }
+        let list_size = list.header.len() + items.len() * list.item_size;
+        data.extend(&list.header[..16]);
+        data.extend((list_size as u32).to_le_bytes());
+        data.extend(&list.header[20..]);
+        data.extend(items.concat());
+    }
+    data
+}
+
+/// A signed update of a Secure Boot variable
+#[derive(Clone, Debug)]
+pub struct AuthUpdate {
+    name: String,
+    guid: Uuid,
+    mode: UpdateMode,
+    payload: Vec<u8>,
+}
+
+impl AuthUpdate {
+    /// Creates an update of PK, KEK, db or dbx from the content of a `.auth`
+    /// file
+    pub fn new(name: &str, mode: UpdateMode, auth: &[u8]) -> Result<AuthUpdate> {
+        let (name, guid) = get_secure_boot_targets()
+            .into_iter()
+            .find(|(var, _)| var == name)
+            .ok_or_else(|| anyhow!("{name} is not a Secure Boot variable"))?;
+        let payload = auth_payload(auth)?;
+        certs::get_db_signatures(payload)
+            .map_err(|e| anyhow!("{e}"))
+            .with_context(|| format!("Invalid {name} update payload"))?;
+        Ok(AuthUpdate {
+            name,
+            guid,
+            mode,
+            payload: payload.to_vec(),
+        })
+    }
+
+    /// Returns the data of the variable after the update
+    pub fn apply(&self, data: &[u8]) -> Vec<u8> {
+        match self.mode {
+            UpdateMode::Replace => self.payload.clone(),
+            UpdateMode::Append => append_signature_lists(data, &self.payload),
+        }
+    }
+}
+
+/// Loader of Secure Boot variables with updates applied, in order
+#[derive(Clone, Debug)]
+pub struct UpdatedVarsLoader<L> {
+    loader: L,
+    updates: Vec<AuthUpdate>,
+}
+
+impl<L: secureboot::SecureBootVarLoader> UpdatedVarsLoader<L> {
+    pub fn new(loader: L, updates: Vec<AuthUpdate>) -> UpdatedVarsLoader<L> {
+        UpdatedVarsLoader { loader, updates }
+    }
+
+    fn apply(&self, name: &str, guid: Uuid, data: Vec<u8>) -> Vec<u8> {
+        self.updates
+            .iter()
+            .filter(|update| update.name == name && update.guid == guid)
+            .fold(data, |data, update| update.apply(&data))
+    }
+}
+
+impl<L: secureboot::SecureBootVarLoader> Iterator for UpdatedVarsLoader<L> {
+    type Item = UEFIVariableData;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let var = self.loader.next()?;
+        let data = self.apply(&var.name(), var.guid(), var.data().to_vec());
+        Some(UEFIVariableData::new(var.guid(), &var.name(), data))
+    }
+}
+
+impl<L: secureboot::SecureBootVarLoader> secureboot::SecureBootdbLoader for UpdatedVarsLoader<L> {
+    fn secureboot_db(&self) -> Vec<u8> {
+        let db = self.loader.secureboot_db();
+        self.apply("db", super::GUID_SECURITY_DATABASE, db)
+    }
+}
+
+impl<L: secureboot::SecureBootVarLoader> secureboot::SecureBootVarLoader for UpdatedVarsLoader<L> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certs::{SignatureData, build_db, get_db_signatures};
+
+    #[test]
+    fn append_dbx_update() {
+        let owner = Uuid::nil();
+        let dbx = build_db(&[
+            SignatureData::sha256(owner, &[1; 32]),
+            SignatureData::sha256(owner, &[2; 32]),
+        ]);
+        let update = build_db(&[
+            SignatureData::sha256(owner, &[2; 32]),
+            SignatureData::sha256(owner, &[3; 32]),
+        ]);
+        let mut auth = vec![0u8; EFI_TIME_SIZE];
+        auth.extend(30u32.to_le_bytes());
+        auth.extend([0u8; 26]);
+        auth.extend(&update);
+
+        let update = AuthUpdate::new("dbx", UpdateMode::Append, &auth).unwrap();
+        let updated = update.apply(&dbx);
+        // The known hash is dropped, the new one appended in its own list
+        assert_eq!(&updated[..dbx.len()], dbx);
+        assert_eq!(
+            get_db_signatures(&updated[dbx.len()..]).unwrap(),
+            vec![SignatureData::sha256(owner, &[3; 32])]
+        );
+        assert!(AuthUpdate::new("MokList", UpdateMode::Append, &auth).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Loader for the AWS EC2 "uefi-data" variable store blobs supplied at AMI
+//! registration, in the AMZN format of python-uefivars.
+
+use super::secureboot::VarStoreLoader;
+use super::{UEFIVariableData, guid_from_le_bytes};
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use std::io::Read;
+
+const MAGIC: &[u8; 8] = b"AMZNUEFI";
+const VERSION: u32 = 0;
+/// Magic, CRC32 and version
+const HEADER_SIZE: usize = 16;
+const EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS: u32 = 0x20;
+/// EFI_TIME timestamp and SHA-256 certificate digest of time based
+/// authenticated variables
+const AUTHENTICATION_DATA_SIZE: usize = 16 + 32;
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            bail!("Truncated variable store");
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a 64 bits length prefixed byte string
+    fn data(&mut self) -> Result<&'a [u8]> {
+        let len = self.u64()?;
+        self.bytes(len.try_into()?)
+    }
+}
+
+/// Reads the variables of a file holding a base64 encoded AWS EC2 uefi-data
+/// blob
+pub fn load(path: &str) -> Result<VarStoreLoader> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {path}"))?;
+    from_base64(&data).with_context(|| format!("Failed to parse {path}"))
+}
+
+/// Decodes a base64 encoded blob, as passed to `aws ec2 register-image
+/// --uefi-data`
+pub fn from_base64(data: &[u8]) -> Result<VarStoreLoader> {
+    let data: Vec<u8> = data
+        .iter()
+        .filter(|c| !c.is_ascii_whitespace())
+        .copied()
+        .collect();
+    let blob = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .context("Invalid base64 encoding")?;
+    from_bytes(&blob)
+}
+
+pub fn from_bytes(blob: &[u8]) -> Result<VarStoreLoader> {
+    if blob.len() < HEADER_SIZE || &blob[..8] != MAGIC {
+        bail!("Not an AMZN UEFI variable store");
+    }
+    let version = u32::from_le_bytes(blob[12..16].try_into().unwrap());
+    if version != VERSION {
+        bail!("Unsupported AMZN UEFI variable store version {version}");
+    }
+    // The zlib stream checksum covers the variables, the header CRC32 is
+    // not checked
+    let mut data = vec![];
+    flate2::read::ZlibDecoder::new(&blob[HEADER_SIZE..])
+        .read_to_end(&mut data)
+        .context("Failed to decompress the variable store")?;
+
+    let mut reader = Reader { data: &data };
+    let count = reader.u64()?;
+    let mut variables = vec![];
+    for _ in 0..count {
+        let name = String::from_utf8_lossy(reader.data()?).into_owned();
+        let data = reader.data()?.to_vec();
+        let guid = guid_from_le_bytes(reader.bytes(16)?.try_into().unwrap());
+        let attributes = reader.u32()?;
+        if attributes & EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS != 0 {
+            reader.bytes(AUTHENTICATION_DATA_SIZE)?;
+        }
+        variables.push(UEFIVariableData::new(guid, &name, data));
+    }
+    Ok(VarStoreLoader::new(variables))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uefi::secureboot::SecureBootdbLoader;
+    use crate::uefi::{GUID_GLOBAL_VARIABLE, GUID_SECURITY_DATABASE, guid_to_le_bytes};
+    use std::io::Write;
+
+    fn entry(name: &str, guid: &uuid::Uuid, attributes: u32, data: &[u8]) -> Vec<u8> {
+        let mut entry = (name.len() as u64).to_le_bytes().to_vec();
+        entry.extend(name.as_bytes());
+        entry.extend((data.len() as u64).to_le_bytes());
+        entry.extend(data);
+        entry.extend(guid_to_le_bytes(guid));
+        entry.extend(attributes.to_le_bytes());
+        if attributes & EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS != 0 {
+            entry.extend([0u8; AUTHENTICATION_DATA_SIZE]);
+        }
+        entry
+    }
+
+    #[test]
+    fn uefi_data_blob() {
+        let mut store = 2u64.to_le_bytes().to_vec();
+        store.extend(entry("db", &GUID_SECURITY_DATABASE, 0x27, b"db"));
+        store.extend(entry("BootOrder", &GUID_GLOBAL_VARIABLE, 0x07, &[0, 0]));
+        let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&store).unwrap();
+        let mut blob = MAGIC.to_vec();
+        blob.extend(0u32.to_le_bytes());
+        blob.extend(VERSION.to_le_bytes());
+        blob.extend(encoder.finish().unwrap());
+        let encoded = base64::engine::general_purpose::STANDARD.encode(blob);
+
+        let loader = from_base64(encoded.as_bytes()).unwrap();
+        assert_eq!(loader.secureboot_db(), b"db");
+        assert!(loader.variable("BootOrder", GUID_GLOBAL_VARIABLE).is_some());
+        assert_eq!(
+            loader.map(|v| v.data().len()).collect::<Vec<_>>(),
+            [0, 0, 2, 0]
+        );
+    }
+}
@@ -5,7 +5,7 @@
 
 use super::{GUID_GLOBAL_VARIABLE, GUID_SECURITY_DATABASE, UEFIVariableData};
 use crate::uefi::secureboot;
-use std::fs;
+use crate::vfs::{FileType, Filesystem, HostFs};
 use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
@@ -18,17 +18,30 @@ const SECURE_BOOT_VARIABLES: [(&str, Uuid); 4] =
     [EFI_VAR_ID_PK, EFI_VAR_ID_KEK, EFI_VAR_ID_DB, EFI_VAR_ID_DBX];
 
 pub const SECURE_BOOT_ATTR_HEADER_LENGTH: usize = 4;
+/// EFI_VARIABLE_NON_VOLATILE | EFI_VARIABLE_BOOTSERVICE_ACCESS |
+/// EFI_VARIABLE_RUNTIME_ACCESS | EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS
+pub const SECURE_BOOT_VAR_ATTRIBUTES: u32 = 0x27;
 
 #[derive(Debug, Clone)]
-pub struct EFIVarsLoader {
+pub struct EFIVarsLoader<'a> {
+    fs: &'a dyn Filesystem,
     path: PathBuf,
     attribute_header: usize,
     index: usize,
 }
 
-impl EFIVarsLoader {
-    pub fn new(path: &str, attribute_header: usize) -> EFIVarsLoader {
+impl EFIVarsLoader<'_> {
+    pub fn new(path: &str, attribute_header: usize) -> EFIVarsLoader<'static> {
+        EFIVarsLoader::from_fs(HostFs::host(), path, attribute_header)
+    }
+
+    pub fn from_fs<'a>(
+        fs: &'a dyn Filesystem,
+        path: &str,
+        attribute_header: usize,
+    ) -> EFIVarsLoader<'a> {
         EFIVarsLoader {
+            fs,
             path: path.into(),
             attribute_header,
             index: 0,
@@ -36,12 +49,12 @@ impl EFIVarsLoader {
     }
 
     fn load_efivar(&self, guid: &uuid::Uuid, var: &str) -> UEFIVariableData {
-        let data = load_uefi_var_data(&self.path, var, guid, self.attribute_header);
+        let data = load_uefi_var_data(self.fs, &self.path, var, guid, self.attribute_header);
         UEFIVariableData::new(*guid, var, data)
     }
 }
 
-impl Iterator for EFIVarsLoader {
+impl Iterator for EFIVarsLoader<'_> {
     type Item = UEFIVariableData;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -51,14 +64,14 @@ impl Iterator for EFIVarsLoader {
     }
 }
 
-impl secureboot::SecureBootdbLoader for EFIVarsLoader {
+impl secureboot::SecureBootdbLoader for EFIVarsLoader<'_> {
     fn secureboot_db(&self) -> Vec<u8> {
         let (var, guid) = EFI_VAR_ID_DB;
-        load_uefi_var_data(&self.path, var, &guid, self.attribute_header)
+        load_uefi_var_data(self.fs, &self.path, var, &guid, self.attribute_header)
     }
 }
 
-impl secureboot::SecureBootVarLoader for EFIVarsLoader {}
+impl secureboot::SecureBootVarLoader for EFIVarsLoader<'_> {}
 
 pub fn get_secure_boot_targets() -> Vec<(String, Uuid)> {
     SECURE_BOOT_VARIABLES
@@ -66,17 +79,31 @@ pub fn get_secure_boot_targets() -> Vec<(String, Uuid)> {
         .to_vec()
 }
 
+/// Writes a variable to a directory in the `Name-GUID` layout of efivarfs,
+/// its data being prefixed with its attributes
+pub fn write_efivar(path: &Path, var: &UEFIVariableData, attributes: u32) -> std::io::Result<()> {
+    let mut data = attributes.to_le_bytes().to_vec();
+    data.extend(var.data());
+    std::fs::write(path.join(format!("{}-{}", var.name(), var.guid())), data)
+}
+
 /// Load data from a UEFI variable given:
-///     - path to the directory holding the file
+///     - filesystem and path to the directory holding the file
 ///     - var, UEFI variable name
 ///     - guid
 ///     - attribute header length
-fn load_uefi_var_data(path: &Path, var: &str, guid: &Uuid, attribute_header: usize) -> Vec<u8> {
-    let mut data = match fs::read(path.join(format!("{var}-{guid}"))) {
+fn load_uefi_var_data(
+    fs: &dyn Filesystem,
+    path: &Path,
+    var: &str,
+    guid: &Uuid,
+    attribute_header: usize,
+) -> Vec<u8> {
+    let mut data = match fs.read(&path.join(format!("{var}-{guid}"))) {
         Ok(res) => res,
         Err(err) => {
-            let path_md = fs::metadata(path).unwrap();
-            if err.kind() == std::io::ErrorKind::NotFound && path_md.is_dir() {
+            let path_type = fs.file_type(path).unwrap();
+            if err.kind() == std::io::ErrorKind::NotFound && path_type == FileType::Dir {
                 return vec![];
             }
             panic!("{err:?}");
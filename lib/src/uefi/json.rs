@@ -0,0 +1,113 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Loader for the JSON variable stores written by virt-firmware's
+//! `virt-fw-vars --output-json`.
+
+use super::UEFIVariableData;
+use super::secureboot::VarStoreLoader;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+struct JsonVarStore {
+    variables: Vec<JsonVariable>,
+}
+
+#[derive(Deserialize)]
+struct JsonVariable {
+    name: String,
+    guid: String,
+    /// Hex encoded data
+    data: String,
+}
+
+/// Reads the variables of a virt-firmware JSON variable store
+pub fn load(path: &str) -> Result<VarStoreLoader> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {path}"))?;
+    from_slice(&data).with_context(|| format!("Failed to parse {path}"))
+}
+
+pub fn from_slice(data: &[u8]) -> Result<VarStoreLoader> {
+    let store: JsonVarStore = serde_json::from_slice(data)?;
+    let variables = store
+        .variables
+        .into_iter()
+        .map(|var| {
+            let guid = Uuid::parse_str(&var.guid)
+                .with_context(|| format!("Invalid GUID of variable {}", var.name))?;
+            let data = hex::decode(&var.data)
+                .with_context(|| format!("Invalid data of variable {}", var.name))?;
+            Ok(UEFIVariableData::new(guid, &var.name, data))
+        })
+        .collect::<Result<_>>()?;
+    Ok(VarStoreLoader::new(variables))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::Filesystem;
+    use std::path::Path;
+
+    #[test]
+    fn virt_fw_vars_json() {
+        let json = r#"{
+    "version": 2,
+    "variables": [
+        {
+            "name": "db",
+            "guid": "d719b2cb-3d3a-4596-a3bc-dad00e67656f",
+            "attr": 39,
+            "data": "0102",
+            "time": "e907010f0c000000000000000000000000"
+        },
+        {
+            "name": "BootOrder",
+            "guid": "8be4df61-93ca-11d2-aa0d-00e098032b8c",
+            "attr": 7,
+            "data": "01000000"
+        },
+        {
+            "name": "Boot0000",
+            "guid": "8be4df61-93ca-11d2-aa0d-00e098032b8c",
+            "attr": 7,
+            "data": "00"
+        },
+        {
+            "name": "Boot0001",
+            "guid": "8be4df61-93ca-11d2-aa0d-00e098032b8c",
+            "attr": 7,
+            "data": "01"
+        },
+        {
+            "name": "MokListRT",
+            "guid": "605dab50-e046-4300-abb6-3dd810dd8b23",
+            "attr": 6,
+            "data": "ff"
+        }
+    ]
+}"#;
+        let loader = from_slice(json.as_bytes()).unwrap();
+        let vars: Vec<(String, Vec<u8>)> = loader
+            .clone()
+            .map(|v| (v.name(), v.data().to_vec()))
+            .collect();
+        assert_eq!(vars[2], ("db".into(), vec![1, 2]));
+        assert_eq!(vars[3], ("dbx".into(), vec![]));
+
+        let boot: Vec<String> = loader.boot_variables().iter().map(|v| v.name()).collect();
+        assert_eq!(boot, ["BootOrder", "Boot0001", "Boot0000"]);
+
+        let mok = loader.mok_variables();
+        assert_eq!(mok.read(Path::new("MokListRT")).unwrap(), vec![0xff]);
+        assert!(!mok.is_file(Path::new("MokListXRT")));
+        let Err(err) = crate::compute_pcr14_in(&mok, "/") else {
+            panic!("PCR 14 computed without MokListXRT");
+        };
+        assert!(err.to_string().contains("MokListXRT"));
+    }
+}
@@ -0,0 +1,175 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Loader for the Secure Boot variables of an EDK2 variable store firmware
+//! volume, such as the OVMF_VARS.fd templates shipped with OVMF.
+
+use super::secureboot::VarStoreLoader;
+use super::{UEFIVariableData, guid_from_le_bytes};
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+use uuid::{Uuid, uuid};
+
+const EFI_SYSTEM_NV_DATA_FV_GUID: Uuid = uuid!("fff12b8d-7696-4c8b-a985-2747075b4f50");
+const FV_SIGNATURE: &[u8; 4] = b"_FVH";
+
+const EFI_VARIABLE_GUID: Uuid = uuid!("ddcf3616-3275-4164-98b6-fe85707ffe7d");
+const EFI_AUTHENTICATED_VARIABLE_GUID: Uuid = uuid!("aaf32c78-947b-439a-a180-2e144ec37792");
+const VARIABLE_STORE_HEADER_SIZE: usize = 28;
+
+const VARIABLE_START_ID: u16 = 0x55aa;
+const VARIABLE_HEADER_SIZE: usize = 32;
+const AUTHENTICATED_VARIABLE_HEADER_SIZE: usize = 60;
+const VAR_ADDED: u8 = 0x3f;
+/// State of a variable being updated, valid until its new copy is added
+const VAR_ADDED_IN_DELETED_TRANSITION: u8 = 0x3e;
+
+fn le_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn guid(data: &[u8], offset: usize) -> Uuid {
+    guid_from_le_bytes(data[offset..offset + 16].try_into().unwrap())
+}
+
+/// Parses the variables of an EDK2 variable store firmware volume, indexed
+/// by name and vendor GUID
+fn parse_variables(fv: &[u8]) -> Result<HashMap<(String, Uuid), Vec<u8>>> {
+    if fv.len() < 56 || &fv[40..44] != FV_SIGNATURE {
+        bail!("Not a firmware volume");
+    }
+    if guid(fv, 16) != EFI_SYSTEM_NV_DATA_FV_GUID {
+        bail!("Not a variable store firmware volume");
+    }
+    let store = le_u16(fv, 48) as usize;
+    if fv.len() < store + VARIABLE_STORE_HEADER_SIZE {
+        bail!("Truncated variable store");
+    }
+    let header_size = match guid(fv, store) {
+        EFI_AUTHENTICATED_VARIABLE_GUID => AUTHENTICATED_VARIABLE_HEADER_SIZE,
+        EFI_VARIABLE_GUID => VARIABLE_HEADER_SIZE,
+        other => bail!("Unsupported variable store format {other}"),
+    };
+    let end = (store + le_u32(fv, store + 16) as usize).min(fv.len());
+
+    let mut added = HashMap::new();
+    let mut in_transition = HashMap::new();
+    let mut offset = store + VARIABLE_STORE_HEADER_SIZE;
+    while offset + header_size <= end && le_u16(fv, offset) == VARIABLE_START_ID {
+        let state = fv[offset + 2];
+        let header = &fv[offset..offset + header_size];
+        let (name_size, data_size) = (
+            le_u32(header, header_size - 24) as usize,
+            le_u32(header, header_size - 20) as usize,
+        );
+        let vendor = guid(header, header_size - 16);
+        let name_start = offset + header_size;
+        let data_start = name_start + name_size.next_multiple_of(4);
+        if data_start + data_size > end {
+            bail!("Truncated variable at offset {offset:#x}");
+        }
+        let name: Vec<u16> = fv[name_start..name_start + name_size]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|c| *c != 0)
+            .collect();
+        let key = (String::from_utf16_lossy(&name), vendor);
+        let data = fv[data_start..data_start + data_size].to_vec();
+        match state {
+            VAR_ADDED => {
+                added.insert(key, data);
+            }
+            VAR_ADDED_IN_DELETED_TRANSITION => {
+                in_transition.insert(key, data);
+            }
+            _ => {}
+        }
+        offset = (data_start + data_size).next_multiple_of(4);
+    }
+
+    for (key, data) in in_transition {
+        added.entry(key).or_insert(data);
+    }
+    Ok(added)
+}
+
+/// Reads the variables of an OVMF_VARS.fd variable store
+pub fn load(path: &str) -> Result<VarStoreLoader> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {path}"))?;
+    from_bytes(&data).with_context(|| format!("Failed to parse {path}"))
+}
+
+pub fn from_bytes(data: &[u8]) -> Result<VarStoreLoader> {
+    let variables = parse_variables(data)?
+        .into_iter()
+        .map(|((name, guid), data)| UEFIVariableData::new(guid, &name, data))
+        .collect();
+    Ok(VarStoreLoader::new(variables))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uefi::secureboot::SecureBootdbLoader;
+    use crate::uefi::{GUID_GLOBAL_VARIABLE, GUID_SECURITY_DATABASE, guid_to_le_bytes};
+
+    fn variable(state: u8, name: &str, guid: &Uuid, data: &[u8]) -> Vec<u8> {
+        let name: Vec<u8> = name
+            .encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let mut var = VARIABLE_START_ID.to_le_bytes().to_vec();
+        var.extend([state, 0]);
+        var.extend(0x27u32.to_le_bytes());
+        var.extend([0u8; 28]);
+        var.extend((name.len() as u32).to_le_bytes());
+        var.extend((data.len() as u32).to_le_bytes());
+        var.extend(guid_to_le_bytes(guid));
+        var.extend(&name);
+        var.resize(var.len().next_multiple_of(4), 0);
+        var.extend(data);
+        var.resize(var.len().next_multiple_of(4), 0);
+        var
+    }
+
+    #[test]
+    fn authenticated_variable_store() {
+        let mut fv = vec![0u8; 72];
+        fv[16..32].copy_from_slice(&guid_to_le_bytes(&EFI_SYSTEM_NV_DATA_FV_GUID));
+        fv[40..44].copy_from_slice(FV_SIGNATURE);
+        fv[48..50].copy_from_slice(&72u16.to_le_bytes());
+        let mut store = guid_to_le_bytes(&EFI_AUTHENTICATED_VARIABLE_GUID);
+        store.extend(0x1000u32.to_le_bytes());
+        store.extend([0x5a, 0xfe, 0, 0, 0, 0, 0, 0]);
+        store.extend(variable(0x3c, "db", &GUID_SECURITY_DATABASE, b"old"));
+        store.extend(variable(VAR_ADDED, "db", &GUID_SECURITY_DATABASE, b"new"));
+        store.extend(variable(
+            VAR_ADDED_IN_DELETED_TRANSITION,
+            "PK",
+            &GUID_GLOBAL_VARIABLE,
+            b"pk",
+        ));
+        fv.extend(store);
+        fv.extend([0xff; 64]);
+
+        let loader = from_bytes(&fv).unwrap();
+        assert_eq!(loader.secureboot_db(), b"new");
+        let vars: Vec<(String, Vec<u8>)> = loader.map(|v| (v.name(), v.data().to_vec())).collect();
+        assert_eq!(
+            vars,
+            vec![
+                ("PK".into(), b"pk".to_vec()),
+                ("KEK".into(), vec![]),
+                ("db".into(), b"new".to_vec()),
+                ("dbx".into(), vec![]),
+            ]
+        );
+    }
+}
@@ -3,7 +3,12 @@
 //
 // SPDX-License-Identifier: MIT
 
-use super::UEFIVariableData;
+use super::efivars::get_secure_boot_targets;
+use super::{GUID_GLOBAL_VARIABLE, GUID_SECURITY_DATABASE, GUID_SHIM_LOCK, UEFIVariableData};
+use crate::vfs::MemoryFs;
+use crate::{Part, mok, tcg};
+use std::path::Path;
+use uuid::Uuid;
 
 pub trait SecureBootdbLoader {
     /// Method that returns the raw data of the secure boot db
@@ -12,8 +17,90 @@ pub trait SecureBootdbLoader {
 
 pub trait SecureBootVarLoader: Iterator<Item = UEFIVariableData> + SecureBootdbLoader {}
 
-pub fn collect_secure_boot_hashes<L: SecureBootVarLoader>(loader: L) -> Vec<(String, Vec<u8>)> {
+pub fn collect_secure_boot_parts<L: SecureBootVarLoader>(loader: L) -> Vec<Part> {
     loader
-        .map(|var| ("EV_EFI_VARIABLE_DRIVER_CONFIG".into(), var.hash()))
+        .map(|var| Part::from_variable(tcg::EV_EFI_VARIABLE_DRIVER_CONFIG, &var))
         .collect()
 }
+
+/// Secure Boot variables of a variable store parsed in full, such as an
+/// OVMF_VARS.fd firmware volume, a virt-firmware JSON store or an AWS EC2
+/// uefi-data blob
+#[derive(Debug, Clone)]
+pub struct VarStoreLoader {
+    variables: Vec<UEFIVariableData>,
+    index: usize,
+}
+
+impl VarStoreLoader {
+    pub fn new(variables: Vec<UEFIVariableData>) -> VarStoreLoader {
+        VarStoreLoader {
+            variables,
+            index: 0,
+        }
+    }
+
+    /// Collects the Secure Boot variables of another loader, such as an
+    /// efivarfs directory or a loader with updates applied
+    pub fn from_loader<L: SecureBootVarLoader>(loader: L) -> VarStoreLoader {
+        VarStoreLoader::new(loader.collect())
+    }
+
+    pub fn variable(&self, name: &str, guid: Uuid) -> Option<&UEFIVariableData> {
+        self.variables
+            .iter()
+            .find(|var| var.guid() == guid && var.name() == name)
+    }
+
+    /// Data of a variable, empty when it is not set
+    fn data(&self, name: &str, guid: Uuid) -> Vec<u8> {
+        self.variable(name, guid)
+            .map(|var| var.data().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// The MokList*RT variables, as files at the root of a filesystem laid
+    /// out like /sys/firmware/efi/mok-variables
+    pub fn mok_variables(&self) -> MemoryFs {
+        let mut fs = MemoryFs::new();
+        for event in mok::MOK_EVENTS_PCR14 {
+            let name = mok::mok_event_to_file_name(event);
+            if let Some(var) = self.variable(&name, GUID_SHIM_LOCK) {
+                fs.insert_file(Path::new(&name), var.data().to_vec());
+            }
+        }
+        fs
+    }
+
+    /// BootOrder followed by the Boot#### variables it lists, in boot order,
+    /// as measured into PCR 1. Missing boot options are skipped.
+    pub fn boot_variables(&self) -> Vec<&UEFIVariableData> {
+        let Some(boot_order) = self.variable("BootOrder", GUID_GLOBAL_VARIABLE) else {
+            return vec![];
+        };
+        let options = boot_order.data().chunks_exact(2).filter_map(|n| {
+            let number = u16::from_le_bytes([n[0], n[1]]);
+            self.variable(&format!("Boot{number:04X}"), GUID_GLOBAL_VARIABLE)
+        });
+        std::iter::once(boot_order).chain(options).collect()
+    }
+}
+
+impl Iterator for VarStoreLoader {
+    type Item = UEFIVariableData;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (name, guid) = get_secure_boot_targets().into_iter().nth(self.index)?;
+        self.index += 1;
+        let data = self.data(&name, guid);
+        Some(UEFIVariableData::new(guid, &name, data))
+    }
+}
+
+impl SecureBootdbLoader for VarStoreLoader {
+    fn secureboot_db(&self) -> Vec<u8> {
+        self.data("db", GUID_SECURITY_DATABASE)
+    }
+}
+
+impl SecureBootVarLoader for VarStoreLoader {}
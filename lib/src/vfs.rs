@@ -0,0 +1,425 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Read only filesystem abstraction the loaders read their inputs through.
+//!
+//! Paths are resolved from the root of the filesystem, absolute and relative
+//! paths being equivalent except for [`HostFs::host`], which reads host
+//! paths as they are.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Component, Path, PathBuf};
+
+const MAX_SYMLINKS: usize = 40;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileType {
+    File,
+    Dir,
+}
+
+pub trait Filesystem {
+    /// Reads the content of a file
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Returns the names of the entries of a directory
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<String>>;
+
+    /// Returns the type of a file, following symbolic links
+    fn file_type(&self, path: &Path) -> io::Result<FileType>;
+
+    fn is_file(&self, path: &Path) -> bool {
+        matches!(self.file_type(path), Ok(FileType::File))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        matches!(self.file_type(path), Ok(FileType::Dir))
+    }
+
+    /// Returns the sorted paths matching a pattern, whose components may
+    /// hold `*`, `?` and `[...]` wildcards. `**` is not supported.
+    fn glob(&self, pattern: &str) -> Vec<PathBuf> {
+        let mut paths = vec![PathBuf::from(if pattern.starts_with('/') {
+            "/"
+        } else {
+            ""
+        })];
+        for component in pattern.split('/').filter(|c| !c.is_empty()) {
+            let Ok(matcher) = glob::Pattern::new(component) else {
+                return vec![];
+            };
+            let is_literal = glob::Pattern::escape(component) == component;
+            paths = paths
+                .iter()
+                .flat_map(|path| {
+                    if is_literal {
+                        return vec![path.join(component)];
+                    }
+                    let mut names = self.read_dir(path).unwrap_or_default();
+                    names.retain(|name| matcher.matches(name));
+                    names.into_iter().map(|name| path.join(name)).collect()
+                })
+                .filter(|path| self.file_type(path).is_ok())
+                .collect();
+        }
+        paths.sort();
+        paths
+    }
+}
+
+impl fmt::Debug for dyn Filesystem + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Filesystem")
+    }
+}
+
+/// Directory of the host. Symbolic links are resolved by the host, so they
+/// may point out of the root directory.
+#[derive(Clone, Debug, Default)]
+pub struct HostFs {
+    root: Option<PathBuf>,
+}
+
+static HOST_FS: HostFs = HostFs { root: None };
+
+impl HostFs {
+    pub fn new(root: &Path) -> HostFs {
+        HostFs {
+            root: Some(root.into()),
+        }
+    }
+
+    /// The host filesystem, reading paths as they are
+    pub fn host() -> &'static HostFs {
+        &HOST_FS
+    }
+
+    fn path(&self, path: &Path) -> PathBuf {
+        match &self.root {
+            Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+            None => path.into(),
+        }
+    }
+}
+
+impl Filesystem for HostFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(self.path(path))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        std::fs::read_dir(self.path(path))?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect()
+    }
+
+    fn file_type(&self, path: &Path) -> io::Result<FileType> {
+        match std::fs::metadata(self.path(path))?.is_dir() {
+            true => Ok(FileType::Dir),
+            false => Ok(FileType::File),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node<F> {
+    File(F),
+    Dir,
+    Symlink(PathBuf),
+}
+
+/// Tree of nodes indexed by their path relative to the root, which is the
+/// empty path
+#[derive(Clone, Debug)]
+struct Tree<F> {
+    nodes: BTreeMap<PathBuf, Node<F>>,
+}
+
+impl<F> Tree<F> {
+    fn new() -> Tree<F> {
+        Tree {
+            nodes: BTreeMap::from([(PathBuf::new(), Node::Dir)]),
+        }
+    }
+
+    /// Normalizes a path to a key, without following symbolic links
+    fn key(path: &Path) -> PathBuf {
+        let mut key = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => key.push(name),
+                Component::ParentDir => {
+                    key.pop();
+                }
+                _ => {}
+            }
+        }
+        key
+    }
+
+    /// Inserts a node, creating its missing parent directories
+    fn insert(&mut self, path: &Path, node: Node<F>) {
+        let key = Tree::<F>::key(path);
+        for parent in key.ancestors().skip(1) {
+            self.nodes.entry(parent.into()).or_insert(Node::Dir);
+        }
+        self.nodes.insert(key, node);
+    }
+
+    /// Resolves a path to the key of its node, following symbolic links
+    fn resolve(&self, path: &Path) -> io::Result<(PathBuf, &Node<F>)> {
+        let not_found = || io::Error::new(io::ErrorKind::NotFound, path.display().to_string());
+        let mut pending: Vec<Component> = path.components().rev().collect();
+        let mut current = PathBuf::new();
+        let mut links = 0;
+        while let Some(component) = pending.pop() {
+            let name = match component {
+                Component::Normal(name) => name,
+                Component::ParentDir => {
+                    current.pop();
+                    continue;
+                }
+                _ => continue,
+            };
+            let next = current.join(name);
+            match self.nodes.get(&next).ok_or_else(not_found)? {
+                Node::Symlink(target) => {
+                    links += 1;
+                    if links > MAX_SYMLINKS {
+                        return Err(io::Error::other(format!(
+                            "Too many levels of symbolic links in {}",
+                            path.display()
+                        )));
+                    }
+                    if target.is_absolute() {
+                        current = PathBuf::new();
+                    }
+                    pending.extend(target.components().rev());
+                }
+                _ => current = next,
+            }
+        }
+        let node = self.nodes.get(&current).ok_or_else(not_found)?;
+        Ok((current, node))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        let (dir, node) = self.resolve(path)?;
+        if !matches!(node, Node::Dir) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                path.display().to_string(),
+            ));
+        }
+        Ok(self
+            .nodes
+            .keys()
+            .filter(|key| key.parent() == Some(&dir))
+            .filter_map(|key| key.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect())
+    }
+
+    fn file_type(&self, path: &Path) -> io::Result<FileType> {
+        match self.resolve(path)?.1 {
+            Node::Dir => Ok(FileType::Dir),
+            _ => Ok(FileType::File),
+        }
+    }
+
+    fn file(&self, path: &Path) -> io::Result<&F> {
+        match self.resolve(path)?.1 {
+            Node::File(file) => Ok(file),
+            _ => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                path.display().to_string(),
+            )),
+        }
+    }
+}
+
+/// In memory tree of files
+#[derive(Clone, Debug)]
+pub struct MemoryFs {
+    tree: Tree<Vec<u8>>,
+}
+
+impl Default for MemoryFs {
+    fn default() -> MemoryFs {
+        MemoryFs::new()
+    }
+}
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs {
+        MemoryFs { tree: Tree::new() }
+    }
+
+    pub fn insert_file(&mut self, path: &Path, data: Vec<u8>) {
+        self.tree.insert(path, Node::File(data));
+    }
+
+    pub fn insert_dir(&mut self, path: &Path) {
+        self.tree.insert(path, Node::Dir);
+    }
+
+    pub fn insert_symlink(&mut self, path: &Path, target: &Path) {
+        self.tree.insert(path, Node::Symlink(target.into()));
+    }
+
+    /// Removes a node, without following symbolic links
+    pub fn remove(&mut self, path: &Path) {
+        self.tree.nodes.remove(&Tree::<Vec<u8>>::key(path));
+    }
+}
+
+impl Filesystem for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.tree.file(path).cloned()
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        self.tree.read_dir(path)
+    }
+
+    fn file_type(&self, path: &Path) -> io::Result<FileType> {
+        self.tree.file_type(path)
+    }
+}
+
+/// Uncompressed tarball, whose files are read in place
+#[derive(Debug)]
+pub struct TarFs {
+    file: File,
+    /// Offset and size of the content of the files in the tarball
+    tree: Tree<(u64, u64)>,
+}
+
+impl TarFs {
+    pub fn open(path: &Path) -> io::Result<TarFs> {
+        let file = File::open(path)?;
+        let mut tree = Tree::new();
+        let mut archive = tar::Archive::new(&file);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = entry.path()?.into_owned();
+            let node = match entry.header().entry_type() {
+                tar::EntryType::Regular | tar::EntryType::Continuous => {
+                    Node::File((entry.raw_file_position(), entry.size()))
+                }
+                tar::EntryType::Directory => Node::Dir,
+                tar::EntryType::Symlink => match entry.link_name()? {
+                    Some(target) => Node::Symlink(target.into_owned()),
+                    None => continue,
+                },
+                // Hard link targets are relative to the root of the tarball
+                tar::EntryType::Link => match entry.link_name()? {
+                    Some(target) => Node::Symlink(Path::new("/").join(target)),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            tree.insert(&path, node);
+        }
+        Ok(TarFs { file, tree })
+    }
+}
+
+impl Filesystem for TarFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let (offset, size) = *self.tree.file(path)?;
+        let mut data = vec![0u8; size as usize];
+        self.file.read_exact_at(&mut data, offset)?;
+        Ok(data)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        self.tree.read_dir(path)
+    }
+
+    fn file_type(&self, path: &Path) -> io::Result<FileType> {
+        self.tree.file_type(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_fs_symlinks_and_glob() {
+        let mut fs = MemoryFs::new();
+        fs.insert_file(
+            Path::new("/usr/lib/modules/6.14.0/vmlinuz"),
+            b"kernel".to_vec(),
+        );
+        fs.insert_symlink(
+            Path::new("/boot/vmlinuz"),
+            Path::new("../usr/lib/modules/6.14.0/vmlinuz"),
+        );
+        fs.insert_symlink(Path::new("/lib"), Path::new("/usr/lib"));
+
+        assert_eq!(fs.read(Path::new("/boot/vmlinuz")).unwrap(), b"kernel");
+        assert!(fs.is_dir(Path::new("lib/modules")));
+        assert_eq!(
+            fs.glob("/lib/modules/*/vmlinuz"),
+            vec![PathBuf::from("/lib/modules/6.14.0/vmlinuz")]
+        );
+        assert!(fs.read(Path::new("/usr/lib/modules")).is_err());
+    }
+
+    #[test]
+    fn tar_fs_links_and_duplicates() {
+        let mut builder = tar::Builder::new(vec![]);
+        let mut append = |path: &str, entry_type: tar::EntryType, data: &[u8], link: &str| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_size(data.len() as u64);
+            if entry_type == tar::EntryType::Regular {
+                builder.append_data(&mut header, path, data).unwrap();
+            } else {
+                builder.append_link(&mut header, path, link).unwrap();
+            }
+        };
+        const KERNEL: &str = "usr/lib/modules/6.15.4/vmlinuz";
+        append(KERNEL, tar::EntryType::Regular, b"old kernel", "");
+        append("objects/ab.file", tar::EntryType::Regular, b"initrd", "");
+        append(KERNEL, tar::EntryType::Regular, b"kernel", "");
+        append(
+            "boot/vmlinuz",
+            tar::EntryType::Symlink,
+            b"",
+            "/usr/lib/modules/6.15.4/vmlinuz",
+        );
+        append(
+            "usr/lib/modules/6.15.4/initramfs.img",
+            tar::EntryType::Link,
+            b"",
+            "objects/ab.file",
+        );
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("rootfs.tar");
+        std::fs::write(&path, builder.into_inner().unwrap()).unwrap();
+
+        let fs = TarFs::open(&path).unwrap();
+        assert_eq!(fs.read(Path::new("/boot/vmlinuz")).unwrap(), b"kernel");
+        assert_eq!(
+            fs.read(Path::new("usr/lib/modules/6.15.4/initramfs.img"))
+                .unwrap(),
+            b"initrd"
+        );
+        assert_eq!(
+            fs.glob("/usr/lib/modules/*/*"),
+            vec![
+                PathBuf::from("/usr/lib/modules/6.15.4/initramfs.img"),
+                PathBuf::from("/usr/lib/modules/6.15.4/vmlinuz"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,308 @@
+// SPDX-FileCopyrightText: Timothée Ravier <tim@siosm.fr>
+// SPDX-FileCopyrightText: Beñat Gartzia Arruabarrena <bgartzia@redhat.com>
+//
+// SPDX-License-Identifier: MIT
+
+//! Identification of event digests against the components of an image.
+//!
+//! Every digest that the components of an image could produce in an event
+//! log is collected as a [`Candidate`], so that unknown digests found in a
+//! real event log can be traced back to the file, section or certificate
+//! that produced them.
+
+use crate::pefile::PeFile;
+use crate::rootfs::RootFSTree;
+use crate::uefi::secureboot::{SecureBootdbLoader, VarStoreLoader};
+use crate::vfs::Filesystem;
+use crate::{certs, mok, shim, uefi};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Candidate {
+    /// Hex encoded SHA-256 digest that the component would be logged with
+    pub digest: String,
+    /// File or variable that produces the digest
+    pub component: String,
+    /// How the digest is derived from the component
+    pub description: String,
+}
+
+impl Candidate {
+    fn new(digest: &[u8], component: &str, description: &str) -> Candidate {
+        Candidate {
+            digest: hex::encode(digest),
+            component: component.into(),
+            description: description.into(),
+        }
+    }
+}
+
+fn authority_candidate(var: uefi::UEFIVariableData, component: &str, subject: &str) -> Candidate {
+    Candidate::new(
+        &var.hash(),
+        component,
+        &format!("EV_EFI_VARIABLE_AUTHORITY {} entry: {subject}", var.name()),
+    )
+}
+
+/// Digests derived from a PE binary: its authentihash, the hash of every
+/// section and the authority events of the certificates and hashes it
+/// embeds. Malformed shim sections are skipped.
+fn pe_candidates(pe: &PeFile, component: &str) -> Vec<Candidate> {
+    let mut candidates = vec![Candidate::new(
+        &pe.authenticode(),
+        component,
+        "EV_EFI_BOOT_SERVICES_APPLICATION authentihash",
+    )];
+
+    for (name, content) in pe.sections() {
+        candidates.push(Candidate::new(
+            &Sha256::digest(&content),
+            component,
+            &format!("{name} section content"),
+        ));
+        candidates.push(Candidate::new(
+            &Sha256::digest(format!("{name}\0")),
+            component,
+            &format!("{name} section name"),
+        ));
+    }
+
+    if let Some(vendor_db) = pe.vendor_cert_auth() {
+        candidates.extend(
+            signature_candidates(&vendor_db, "vendor_db", uefi::GUID_SECURITY_DATABASE)
+                .into_iter()
+                .map(|candidate| Candidate {
+                    component: component.into(),
+                    ..candidate
+                }),
+        );
+    }
+
+    for cert in pe.vendor_cert() {
+        let mut data = uefi::guid_to_le_bytes(&uefi::GUID_SHIM_LOCK);
+        data.extend(&cert.raw);
+        let var = uefi::UEFIVariableData::new(uefi::GUID_SHIM_LOCK, "MokListRT", data);
+        candidates.push(authority_candidate(var, component, &cert.subject));
+    }
+
+    if let Some(sbatlevel) = pe.section(shim::SHIM_SBATLEVEL_SECTION) {
+        for (policy, label) in [
+            (shim::SbatLevelPolicyType::PREVIOUS, "previous"),
+            (shim::SbatLevelPolicyType::LATEST, "latest"),
+        ] {
+            let Some(var) = shim::get_sbatlevel_uefivar(&sbatlevel, &policy) else {
+                continue;
+            };
+            candidates.push(Candidate::new(
+                &var.hash(),
+                component,
+                &format!("EV_EFI_VARIABLE_AUTHORITY SbatLevel {label} policy"),
+            ));
+        }
+    }
+
+    candidates
+}
+
+fn relative_name(path: &Path, root: &str) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Collects the files below a directory, recursively
+fn walk(fs: &dyn Filesystem, dir: &Path, files: &mut Vec<PathBuf>) {
+    for name in fs.read_dir(dir).unwrap_or_default() {
+        let path = dir.join(name);
+        if fs.is_dir(&path) {
+            walk(fs, &path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+fn esp_candidates(fs: &dyn Filesystem, esp_path: &str) -> Vec<Candidate> {
+    let mut files = vec![];
+    walk(fs, Path::new(esp_path), &mut files);
+    files.sort();
+    files
+        .iter()
+        .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("efi")))
+        .filter_map(|p| {
+            let pe = PeFile::load(fs, &p.to_string_lossy(), false)?;
+            Some(pe_candidates(&pe, &relative_name(p, esp_path)))
+        })
+        .flatten()
+        .collect()
+}
+
+fn kernel_candidates(fs: &dyn Filesystem, kernels_dir: &str) -> Vec<Candidate> {
+    let pattern = format!("{}/*/vmlinuz", kernels_dir.trim_end_matches('/'));
+    fs.glob(&pattern)
+        .iter()
+        .filter_map(|p| {
+            let pe = PeFile::load(fs, &p.to_string_lossy(), true)?;
+            Some(pe_candidates(&pe, &relative_name(p, kernels_dir)))
+        })
+        .flatten()
+        .collect()
+}
+
+/// Authority event candidates of every entry of a signature database,
+/// certificates and hashes alike
+fn signature_candidates(data: &[u8], var_name: &str, guid: uuid::Uuid) -> Vec<Candidate> {
+    certs::get_db_signatures(data)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|signature| {
+            let label = match signature.signature_type {
+                uefi::EFI_CERT_TYPE_X509_GUID => certs::X509Cert::from_der(&signature.data)
+                    .map(|cert| cert.subject)
+                    .unwrap_or_else(|_| "invalid certificate".into()),
+                uefi::EFI_CERT_SHA256_GUID => format!("SHA-256 {}", hex::encode(&signature.data)),
+                other => format!("signature of type {other}"),
+            };
+            let mut entry = uefi::guid_to_le_bytes(&signature.owner);
+            entry.extend(&signature.data);
+            let var = uefi::UEFIVariableData::new(guid, var_name, entry);
+            authority_candidate(var, var_name, &label)
+        })
+        .collect()
+}
+
+fn secureboot_candidates(loader: VarStoreLoader) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = loader
+        .clone()
+        .map(|var| {
+            Candidate::new(
+                &var.hash(),
+                &var.name(),
+                "EV_EFI_VARIABLE_DRIVER_CONFIG variable content",
+            )
+        })
+        .collect();
+    candidates.extend(signature_candidates(
+        &loader.secureboot_db(),
+        "db",
+        uefi::GUID_SECURITY_DATABASE,
+    ));
+    candidates
+}
+
+fn mok_candidates(fs: &dyn Filesystem, mok_variables: &str) -> Vec<Candidate> {
+    mok::MOK_EVENTS_PCR14
+        .iter()
+        .filter_map(|event| {
+            let name = mok::mok_event_to_file_name(event);
+            let data = fs.read(&Path::new(mok_variables).join(&name)).ok()?;
+            let mut candidates = vec![Candidate::new(
+                &Sha256::digest(&data),
+                &name,
+                "EV_IPL variable content",
+            )];
+            if name == "MokListRT" {
+                candidates.extend(signature_candidates(&data, &name, uefi::GUID_SHIM_LOCK));
+            }
+            Some(candidates)
+        })
+        .flatten()
+        .collect()
+}
+
+/// Digests that do not depend on the image
+fn fixed_candidates() -> Vec<Candidate> {
+    vec![
+        Candidate::new(
+            &Sha256::digest(b"Calling EFI Application from Boot Option"),
+            "firmware",
+            "EV_EFI_ACTION boot option call",
+        ),
+        Candidate::new(&Sha256::digest([0u8; 4]), "firmware", "EV_SEPARATOR"),
+        Candidate::new(
+            &uefi::get_secureboot_state_event(true).hash(),
+            "SecureBoot",
+            "EV_EFI_VARIABLE_DRIVER_CONFIG Secure Boot enabled",
+        ),
+        Candidate::new(
+            &uefi::get_secureboot_state_event(false).hash(),
+            "SecureBoot",
+            "EV_EFI_VARIABLE_DRIVER_CONFIG Secure Boot disabled",
+        ),
+        Candidate::new(
+            &shim::get_sbat_var_original_uefivar().hash(),
+            "shim",
+            "EV_EFI_VARIABLE_AUTHORITY SbatLevel original policy",
+        ),
+    ]
+}
+
+/// Collects every digest the given image and variables could be logged with.
+/// The MOK variables are read from a directory of a filesystem.
+pub fn collect_candidates(
+    rootfs: &RootFSTree,
+    secureboot_vars: Option<VarStoreLoader>,
+    mok_variables: Option<(&dyn Filesystem, &str)>,
+) -> Vec<Candidate> {
+    let mut candidates = fixed_candidates();
+    candidates.extend(esp_candidates(rootfs.fs(), rootfs.esp()));
+    candidates.extend(kernel_candidates(rootfs.fs(), rootfs.vmlinuz()));
+    if let Some(loader) = secureboot_vars {
+        candidates.extend(secureboot_candidates(loader));
+    }
+    if let Some((fs, path)) = mok_variables {
+        candidates.extend(mok_candidates(fs, path));
+    }
+    candidates
+}
+
+/// Returns the candidates matching a hex encoded digest
+pub fn identify<'a>(candidates: &'a [Candidate], digest: &str) -> Vec<&'a Candidate> {
+    let digest = digest.to_lowercase();
+    candidates.iter().filter(|c| c.digest == digest).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certs::{SignatureData, build_db};
+
+    #[test]
+    fn identify_signature_entries() {
+        let db = build_db(&[
+            SignatureData::sha256(uuid::Uuid::nil(), &[0xab; 32]),
+            SignatureData::sha256(uuid::Uuid::nil(), &[0xef; 32]),
+        ]);
+        let mok_list = build_db(&[SignatureData::sha256(uefi::GUID_SHIM_LOCK, &[0xcd; 32])]);
+        let mut candidates = signature_candidates(&db, "db", uefi::GUID_SECURITY_DATABASE);
+        candidates.extend(signature_candidates(
+            &mok_list,
+            "MokListRT",
+            uefi::GUID_SHIM_LOCK,
+        ));
+        assert_eq!(candidates.len(), 3);
+
+        let found = identify(
+            &candidates,
+            "D88153B4B90EB04FB3392B445707326265FCD8FD5B873E211202C2F7E8EA3504",
+        );
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].component, "db");
+        assert_eq!(
+            found[0].description,
+            format!(
+                "EV_EFI_VARIABLE_AUTHORITY db entry: SHA-256 {}",
+                "ab".repeat(32)
+            )
+        );
+        let found = identify(
+            &candidates,
+            "f29a8dc1e615bd2525c0e9cc83b1ffad146c26450a878f61df75dafdfe0ef9e3",
+        );
+        assert_eq!(found[0].component, "MokListRT");
+    }
+}